@@ -1,54 +1,96 @@
 use serde::{Serialize, Deserialize};
-use super::transaction::Transaction;
 use chrono::prelude::*;
+use crate::crypto::SignatureAlgorithm;
+use crate::network::message::TransactionData;
 use crate::utils::{hashing, encoding};
+use crate::wallet::{KeyPair, Wallet};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Block {
     pub blockHeader: BlockHeader,
-    pub transactions: Vec<Transaction>,
-    pub blockSize: Option<usize>, // Size of the block in bytes.
+    pub signature: Vec<u8>,
+    pub transactions: Vec<TransactionData>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct BlockHeader {
-    pub timestamp: i64,
+    pub height: u64,
     pub prevHash: String,
-    pub nonce: u64,
     pub merkleRoot: String,
+    pub timestamp: i64,
+    pub slot: u64,
+    // The beacon this block's proposer was elected from, and the
+    // proposer's VRF proof of eligibility, whose output feeds the next one.
+    pub seed: String,
+    pub vrfProof: Vec<u8>,
+    pub producer: String,
+    // Coins the block mints for its producer under the monetary policy.
+    pub reward: u64,
+    // The producer's key; the producer address must be derived from it.
+    pub algorithm: SignatureAlgorithm,
+    pub publicKey: Vec<u8>,
 }
 
 impl Block {
-    pub fn new(transactions: Vec<Transaction>, prevHash: String) -> Self {
+    pub fn new(height: u64, prevHash: String, producer: String, transactions: Vec<TransactionData>) -> Self {
         let blockHeader = BlockHeader {
-            timestamp: Utc::now().timestamp(),
+            height,
             prevHash,
-            nonce: 0,
-            merkleRoot: String::new(),
+            merkleRoot: Block::calculateMerkleRoot(&transactions),
+            timestamp: Utc::now().timestamp(),
+            slot: 0,
+            seed: String::new(),
+            vrfProof: Vec::new(),
+            producer,
+            reward: 0,
+            algorithm: SignatureAlgorithm::Ed25519,
+            publicKey: Vec::new(),
         };
 
-        let mut block = Block { 
-            blockHeader, 
+        Block {
+            blockHeader,
+            signature: Vec::new(),
             transactions,
-            blockSize: None,
-        };
-        block.blockHeader.merkleRoot = block.calculateMerkleRoot();
-        block.blockSize = Some(block.calculateBlockSize());
-        block
+        }
+    }
+
+    pub fn height(&self) -> u64 {
+        self.blockHeader.height
+    }
+
+    pub fn producer(&self) -> &str {
+        &self.blockHeader.producer
+    }
+
+    pub fn slot(&self) -> u64 {
+        self.blockHeader.slot
     }
 
+    // Records the producer's key in the header and signs the header hash.
+    pub fn sign(&mut self, keypair: &KeyPair) {
+        self.blockHeader.algorithm = keypair.algorithm;
+        self.blockHeader.publicKey = keypair.public_key.clone();
+        self.signature = keypair.sign_message(self.calculateHash().as_bytes());
+    }
+
+    pub fn hasValidSignature(&self) -> bool {
+        self.blockHeader.hasValidSignature(&self.signature)
+    }
+
+    // Only the header is hashed: the body is committed to by the merkle root
+    // and the signature is made over this hash.
     pub fn calculateHash(&self) -> String {
-        let headerBytes = encoding::serialize(&self.blockHeader)
-            .expect("Failed to serialize the block header");
-        hashing::sha256(&headerBytes)
+        self.blockHeader.calculateHash()
     }
 
-    fn calculateMerkleRoot(&self) -> String {
-        if self.transactions.is_empty() {
+    // An odd hash out at any level moves up unpaired. Pairing it with itself
+    // would give a block repeating its last transactions the same root.
+    pub fn calculateMerkleRoot(transactions: &[TransactionData]) -> String {
+        if transactions.is_empty() {
             return String::new();
         }
 
-        let mut hashes: Vec<String> = self.transactions
+        let mut hashes: Vec<String> = transactions
             .iter()
             .map(|tx| tx.calculateHash())
             .collect();
@@ -56,12 +98,12 @@ impl Block {
         while hashes.len() > 1 {
             let mut newHashes = Vec::new();
             for chunk in hashes.chunks(2) {
-                let concatenated = if chunk.len() == 2 {
-                    chunk[0].clone() + &chunk[1]
+                if chunk.len() == 2 {
+                    let concatenated = chunk[0].clone() + &chunk[1];
+                    newHashes.push(hashing::sha256(concatenated.as_bytes()));
                 } else {
-                    chunk[0].clone() + &chunk[0]
-                };
-                newHashes.push(hashing::sha256(concatenated.as_bytes()));
+                    newHashes.push(chunk[0].clone());
+                }
             }
             hashes = newHashes;
         }
@@ -69,16 +111,106 @@ impl Block {
         hashes[0].clone()
     }
 
-    pub fn mineBlock(&mut self, difficulty: usize) {
-        let targetPrefix = "0".repeat(difficulty);
-        while !self.calculateHash().starts_with(&targetPrefix) {
-            self.blockHeader.nonce += 1;
-        }
+    pub fn hasValidMerkleRoot(&self) -> bool {
+        self.blockHeader.merkleRoot == Block::calculateMerkleRoot(&self.transactions)
     }
 
-    fn calculateBlockSize(&self) -> usize {
+    pub fn calculateBlockSize(&self) -> usize {
         encoding::serialize(&self)
             .map(|bytes| bytes.len())
             .unwrap_or(0)
     }
-}
\ No newline at end of file
+}
+
+impl BlockHeader {
+    pub fn calculateHash(&self) -> String {
+        let headerBytes = encoding::serialize(self)
+            .expect("Failed to serialize the block header");
+        hashing::sha256(&headerBytes)
+    }
+
+    // Whether `signature` is the producer's, made with the key in the header.
+    pub fn hasValidSignature(&self, signature: &[u8]) -> bool {
+        !self.publicKey.is_empty()
+            && Wallet::generate_address(&self.publicKey) == self.producer
+            && KeyPair::verify(self.algorithm, &self.publicKey, self.calculateHash().as_bytes(), signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::message::{TransactionKind, DEFAULT_CHAIN_ID};
+
+    fn transfer(amount: u64) -> TransactionData {
+        TransactionData {
+            kind: TransactionKind::Transfer,
+            sender: "alice".to_string(),
+            receiver: "bob".to_string(),
+            amount,
+            fee: 0,
+            nonce: 0,
+            chainId: DEFAULT_CHAIN_ID,
+            algorithm: SignatureAlgorithm::Ed25519,
+            publicKey: Vec::new(),
+            signature: Vec::new(),
+            evidence: None,
+        }
+    }
+
+    #[test]
+    fn test_hash_covers_header_only() {
+        let mut block = Block::new(1, "parent".to_string(), "producer".to_string(), vec![transfer(10)]);
+        let hash = block.calculateHash();
+        assert_eq!(hash, block.blockHeader.calculateHash());
+
+        block.signature = vec![1, 2, 3];
+        assert_eq!(block.calculateHash(), hash);
+
+        block.blockHeader.producer = "someone_else".to_string();
+        assert_ne!(block.calculateHash(), hash);
+    }
+
+    #[test]
+    fn test_signature_binds_producer_and_header() {
+        let keypair = KeyPair::generate();
+        let producer = Wallet::generate_address(&keypair.public_key);
+        let mut block = Block::new(1, "parent".to_string(), producer, vec![transfer(10)]);
+        assert!(!block.hasValidSignature());
+
+        block.sign(&keypair);
+        assert!(block.hasValidSignature());
+
+        let mut moved = block.clone();
+        moved.blockHeader.slot += 1;
+        assert!(!moved.hasValidSignature());
+
+        let mut impostor = Block::new(1, "parent".to_string(), "someone_else".to_string(), Vec::new());
+        impostor.sign(&keypair);
+        assert!(!impostor.hasValidSignature());
+    }
+
+    #[test]
+    fn test_merkle_root_commits_to_transactions() {
+        let mut block = Block::new(0, String::new(), "producer".to_string(), vec![transfer(10), transfer(20)]);
+        assert!(block.hasValidMerkleRoot());
+        assert!(Block::new(0, String::new(), String::new(), Vec::new()).hasValidMerkleRoot());
+
+        block.transactions[1].amount = 30;
+        assert!(!block.hasValidMerkleRoot());
+    }
+
+    #[test]
+    fn test_repeated_transactions_change_merkle_root() {
+        let three = vec![transfer(10), transfer(20), transfer(30)];
+        let mut four = three.clone();
+        four.push(transfer(30));
+        assert_ne!(Block::calculateMerkleRoot(&three), Block::calculateMerkleRoot(&four));
+
+        let mut six = four.clone();
+        six.extend([transfer(10), transfer(20)]);
+        let mut eight = six.clone();
+        eight.extend([transfer(10), transfer(20)]);
+        assert_ne!(Block::calculateMerkleRoot(&six), Block::calculateMerkleRoot(&eight));
+    }
+}
@@ -1,5 +1,7 @@
 pub mod block;
 pub mod transaction;
+pub mod tree;
 
-pub use block::Block;
+pub use block::{Block, BlockHeader};
 pub use transaction::Transaction;
+pub use tree::BlockTree;
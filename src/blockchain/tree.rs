@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use crate::blockchain::Block;
+
+// A block with this many blocks built on top of it in the active chain is
+// final: the node will not reorganize it away.
+pub const FINALITY_DEPTH: u64 = 64;
+
+pub struct TreeNode {
+    pub block: Block,
+    // Sum of the fork-choice weights of this block and all its ancestors.
+    pub chainWeight: u64,
+}
+
+// Every block whose ancestry is known, across all branches, keyed by hash.
+#[derive(Default)]
+pub struct BlockTree {
+    nodes: HashMap<String, TreeNode>,
+    children: HashMap<String, Vec<String>>,
+}
+
+impl BlockTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.nodes.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&TreeNode> {
+        self.nodes.get(hash)
+    }
+
+    pub fn block(&self, hash: &str) -> Option<&Block> {
+        self.nodes.get(hash).map(|node| &node.block)
+    }
+
+    pub fn height(&self, hash: &str) -> Option<u64> {
+        self.nodes.get(hash).map(|node| node.block.height())
+    }
+
+    // Zero for the empty chain, written as an empty hash.
+    pub fn chainWeight(&self, hash: &str) -> u64 {
+        self.nodes.get(hash).map_or(0, |node| node.chainWeight)
+    }
+
+    // Adds `block` below its parent, which must already be in the tree
+    // unless `block` starts a chain. Returns the new chain weight.
+    pub fn insert(&mut self, hash: String, block: Block, weight: u64) -> u64 {
+        let parentHash = block.blockHeader.prevHash.clone();
+        let chainWeight = self.chainWeight(&parentHash).saturating_add(weight);
+        self.children.entry(parentHash).or_default().push(hash.clone());
+        self.nodes.insert(hash, TreeNode { block, chainWeight });
+        chainWeight
+    }
+
+    // Removes `hash` and every block built on it. Returns the removed blocks.
+    pub fn removeBranch(&mut self, hash: &str) -> Vec<Block> {
+        if let Some(node) = self.nodes.get(hash) {
+            if let Some(siblings) = self.children.get_mut(&node.block.blockHeader.prevHash) {
+                siblings.retain(|sibling| sibling != hash);
+            }
+        }
+        let mut removed = Vec::new();
+        let mut pending = vec![hash.to_string()];
+        while let Some(hash) = pending.pop() {
+            pending.extend(self.children.remove(&hash).unwrap_or_default());
+            if let Some(node) = self.nodes.remove(&hash) {
+                removed.push(node.block);
+            }
+        }
+        removed
+    }
+
+    // Walks back from both tips to their last common block. Returns the
+    // blocks to disconnect from `from`, newest first, and the blocks to
+    // connect to reach `to`, oldest first. An empty hash stands for the
+    // empty chain.
+    pub fn route(&self, from: &str, to: &str) -> (Vec<String>, Vec<String>) {
+        let position = |hash: &str| self.nodes.get(hash).map_or(-1, |node| node.block.height() as i64);
+        let parent = |hash: &str| self.nodes[hash].block.blockHeader.prevHash.clone();
+
+        let (mut from, mut to) = (from.to_string(), to.to_string());
+        let (mut disconnect, mut connect) = (Vec::new(), Vec::new());
+        while position(&from) > position(&to) {
+            disconnect.push(from.clone());
+            from = parent(&from);
+        }
+        while position(&to) > position(&from) {
+            connect.push(to.clone());
+            to = parent(&to);
+        }
+        while from != to {
+            disconnect.push(from.clone());
+            from = parent(&from);
+            connect.push(to.clone());
+            to = parent(&to);
+        }
+        connect.reverse();
+        (disconnect, connect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn child(parent: Option<&Block>, producer: &str) -> Block {
+        let (height, prevHash) = parent.map_or((0, String::new()), |parent| (parent.height() + 1, parent.calculateHash()));
+        Block::new(height, prevHash, producer.to_string(), Vec::new())
+    }
+
+    fn insert(tree: &mut BlockTree, block: &Block, weight: u64) -> u64 {
+        tree.insert(block.calculateHash(), block.clone(), weight)
+    }
+
+    #[test]
+    fn test_route_between_branches() {
+        let mut tree = BlockTree::new();
+        let genesis = child(None, "a");
+        let a1 = child(Some(&genesis), "a");
+        let a2 = child(Some(&a1), "a");
+        let b1 = child(Some(&genesis), "b");
+        let b2 = child(Some(&b1), "b");
+        let b3 = child(Some(&b2), "b");
+        for (block, weight) in [(&genesis, 1), (&a1, 5), (&a2, 5), (&b1, 1), (&b2, 1), (&b3, 1)] {
+            insert(&mut tree, block, weight);
+        }
+        let hash = |block: &Block| block.calculateHash();
+
+        assert_eq!(tree.chainWeight(&hash(&a2)), 11);
+        assert_eq!(tree.chainWeight(&hash(&b3)), 4);
+        assert_eq!(
+            tree.route(&hash(&a2), &hash(&b3)),
+            (vec![hash(&a2), hash(&a1)], vec![hash(&b1), hash(&b2), hash(&b3)])
+        );
+        assert_eq!(tree.route(&hash(&a1), &hash(&a2)), (vec![], vec![hash(&a2)]));
+        assert_eq!(tree.route("", &hash(&a1)), (vec![], vec![hash(&genesis), hash(&a1)]));
+    }
+
+    #[test]
+    fn test_remove_branch_drops_descendants() {
+        let mut tree = BlockTree::new();
+        let genesis = child(None, "a");
+        let a1 = child(Some(&genesis), "a");
+        let a2 = child(Some(&a1), "a");
+        let b1 = child(Some(&genesis), "b");
+        for block in [&genesis, &a1, &a2, &b1] {
+            insert(&mut tree, block, 1);
+        }
+
+        let removed = tree.removeBranch(&a1.calculateHash());
+        assert_eq!(removed.len(), 2);
+        assert_eq!(tree.len(), 2);
+        assert!(tree.contains(&b1.calculateHash()));
+        assert_eq!(tree.route(&b1.calculateHash(), &genesis.calculateHash()).0, vec![b1.calculateHash()]);
+    }
+}
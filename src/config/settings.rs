@@ -0,0 +1,155 @@
+use std::fs;
+use std::net::SocketAddr;
+use crate::network::node::DEFAULT_TARGET_OUTBOUND;
+use crate::ledger::MonetaryPolicy;
+use thiserror::Error;
+
+pub const DEFAULT_CONFIG_PATH: &str = "config/bitcoin.conf";
+pub const DEFAULT_BIND: &str = "127.0.0.1:8333";
+pub const DEFAULT_DATA_DIR: &str = "data";
+pub const DEFAULT_GENESIS_SUPPLY: u64 = 300;
+pub const DEFAULT_BLOCK_REWARD: u64 = 50;
+pub const DEFAULT_REWARD_INTERVAL: u64 = 210_000;
+pub const DEFAULT_REWARD_REDUCTION: u64 = 50;
+pub const DEFAULT_MAX_SUPPLY: u64 = 21_000_000;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ConfigError {
+    #[error("Failed to read config: {0}")]
+    Io(String),
+
+    #[error("Line {0} is not a key=value pair")]
+    InvalidLine(usize),
+
+    #[error("Invalid value for {key}: {value}")]
+    InvalidValue { key: String, value: String },
+
+    #[error("Unknown config key {0}")]
+    UnknownKey(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeConfig {
+    pub bind: SocketAddr,
+    pub seed_nodes: Vec<SocketAddr>,
+    pub target_outbound: usize,
+    pub data_dir: String,
+    pub monetary_policy: MonetaryPolicy,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            bind: DEFAULT_BIND.parse().unwrap(),
+            seed_nodes: Vec::new(),
+            target_outbound: DEFAULT_TARGET_OUTBOUND,
+            data_dir: DEFAULT_DATA_DIR.to_string(),
+            monetary_policy: MonetaryPolicy {
+                genesis_supply: DEFAULT_GENESIS_SUPPLY,
+                block_reward: DEFAULT_BLOCK_REWARD,
+                reduction_interval: DEFAULT_REWARD_INTERVAL,
+                reduction_percent: DEFAULT_REWARD_REDUCTION,
+                max_supply: DEFAULT_MAX_SUPPLY,
+            },
+        }
+    }
+}
+
+impl NodeConfig {
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|e| ConfigError::Io(e.to_string()))?;
+        NodeConfig::parse(&contents)
+    }
+
+    // bitcoin.conf style: one `key=value` per line, `#` starts a comment and
+    // `seednode` may be repeated.
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let mut config = NodeConfig::default();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or(ConfigError::InvalidLine(number + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+            let invalid = || ConfigError::InvalidValue {
+                key: key.to_string(),
+                value: value.to_string(),
+            };
+
+            match key {
+                "bind" => config.bind = value.parse().map_err(|_| invalid())?,
+                "seednode" => config.seed_nodes.push(value.parse().map_err(|_| invalid())?),
+                "targetoutbound" => config.target_outbound = value.parse().map_err(|_| invalid())?,
+                "datadir" => config.data_dir = value.to_string(),
+                "genesissupply" => config.monetary_policy.genesis_supply = value.parse().map_err(|_| invalid())?,
+                "blockreward" => config.monetary_policy.block_reward = value.parse().map_err(|_| invalid())?,
+                "rewardinterval" => config.monetary_policy.reduction_interval = value.parse().map_err(|_| invalid())?,
+                "rewardreduction" => {
+                    config.monetary_policy.reduction_percent = value
+                        .parse()
+                        .ok()
+                        .filter(|percent| *percent <= 100)
+                        .ok_or_else(invalid)?
+                }
+                "maxsupply" => config.monetary_policy.max_supply = value.parse().map_err(|_| invalid())?,
+                _ => return Err(ConfigError::UnknownKey(key.to_string())),
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = NodeConfig::parse(
+            "# local test network\n\
+             bind = 127.0.0.1:9000\n\
+             seednode=127.0.0.1:9001\n\
+             seednode=127.0.0.1:9002  # second seed\n\
+             \n\
+             targetoutbound=3\n\
+             datadir=data/node0\n\
+             blockreward=20\n\
+             rewardinterval=0\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.bind, "127.0.0.1:9000".parse().unwrap());
+        assert_eq!(
+            config.seed_nodes,
+            vec!["127.0.0.1:9001".parse().unwrap(), "127.0.0.1:9002".parse().unwrap()]
+        );
+        assert_eq!(config.target_outbound, 3);
+        assert_eq!(config.data_dir, "data/node0");
+        assert_eq!(config.monetary_policy.block_reward, 20);
+        assert_eq!(config.monetary_policy.scheduled_reward(u64::MAX), 20);
+        assert_eq!(config.monetary_policy.max_supply, DEFAULT_MAX_SUPPLY);
+        assert_eq!(NodeConfig::parse("").unwrap(), NodeConfig::default());
+    }
+
+    #[test]
+    fn test_invalid_config_rejected() {
+        assert_eq!(NodeConfig::parse("bind"), Err(ConfigError::InvalidLine(1)));
+        assert_eq!(
+            NodeConfig::parse("\nseednode=localhost"),
+            Err(ConfigError::InvalidValue {
+                key: "seednode".to_string(),
+                value: "localhost".to_string(),
+            })
+        );
+        assert_eq!(
+            NodeConfig::parse("rewardreduction=101"),
+            Err(ConfigError::InvalidValue {
+                key: "rewardreduction".to_string(),
+                value: "101".to_string(),
+            })
+        );
+        assert_eq!(NodeConfig::parse("rpcport=1"), Err(ConfigError::UnknownKey("rpcport".to_string())));
+        assert!(matches!(NodeConfig::load("does/not/exist.conf"), Err(ConfigError::Io(_))));
+    }
+}
@@ -0,0 +1,118 @@
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use crate::blockchain::{Block, BlockHeader};
+use crate::utils::hashing;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum EvidenceError {
+    #[error("Both headers are the same block")]
+    SameBlock,
+
+    #[error("Headers are for different slots")]
+    DifferentSlots,
+
+    #[error("Headers are from different producers")]
+    DifferentProducers,
+
+    #[error("Header is not signed by its producer")]
+    InvalidSignature,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SignedHeader {
+    pub header: BlockHeader,
+    pub signature: Vec<u8>,
+}
+
+impl SignedHeader {
+    pub fn of(block: &Block) -> Self {
+        Self {
+            header: block.blockHeader.clone(),
+            signature: block.signature.clone(),
+        }
+    }
+}
+
+// Two different headers a producer signed for the same slot. Only one
+// block per slot may be proposed, so this proves the producer equivocated.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DoubleSignEvidence {
+    pub first: SignedHeader,
+    pub second: SignedHeader,
+}
+
+impl DoubleSignEvidence {
+    // Orders the headers by hash so each pair has one encoding.
+    pub fn new(first: SignedHeader, second: SignedHeader) -> Self {
+        if first.header.calculateHash() <= second.header.calculateHash() {
+            Self { first, second }
+        } else {
+            Self { first: second, second: first }
+        }
+    }
+
+    pub fn offender(&self) -> &str {
+        &self.first.header.producer
+    }
+
+    pub fn slot(&self) -> u64 {
+        self.first.header.slot
+    }
+
+    pub fn id(&self) -> String {
+        let combined = format!("{}{}", self.first.header.calculateHash(), self.second.header.calculateHash());
+        hashing::sha256(combined.as_bytes())
+    }
+
+    pub fn verify(&self) -> Result<(), EvidenceError> {
+        let (first, second) = (&self.first.header, &self.second.header);
+        if first.calculateHash() == second.calculateHash() {
+            return Err(EvidenceError::SameBlock);
+        }
+        if first.slot != second.slot {
+            return Err(EvidenceError::DifferentSlots);
+        }
+        if first.producer != second.producer {
+            return Err(EvidenceError::DifferentProducers);
+        }
+        if !first.hasValidSignature(&self.first.signature) || !second.hasValidSignature(&self.second.signature) {
+            return Err(EvidenceError::InvalidSignature);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::{KeyPair, Wallet};
+
+    fn signedBlock(keypair: &KeyPair, slot: u64, prevHash: &str) -> Block {
+        let mut block = Block::new(1, prevHash.to_string(), Wallet::generate_address(&keypair.public_key), Vec::new());
+        block.blockHeader.slot = slot;
+        block.sign(keypair);
+        block
+    }
+
+    #[test]
+    fn test_double_sign_evidence() {
+        let keypair = KeyPair::generate();
+        let first = SignedHeader::of(&signedBlock(&keypair, 7, "a"));
+        let second = SignedHeader::of(&signedBlock(&keypair, 7, "b"));
+        let evidence = DoubleSignEvidence::new(first.clone(), second.clone());
+        assert_eq!(evidence.verify(), Ok(()));
+        assert_eq!(evidence, DoubleSignEvidence::new(second.clone(), first.clone()));
+        assert_eq!(evidence.offender(), Wallet::generate_address(&keypair.public_key));
+        assert_eq!(evidence.slot(), 7);
+
+        assert_eq!(DoubleSignEvidence::new(first.clone(), first.clone()).verify(), Err(EvidenceError::SameBlock));
+        let later = SignedHeader::of(&signedBlock(&keypair, 8, "b"));
+        assert_eq!(DoubleSignEvidence::new(first.clone(), later).verify(), Err(EvidenceError::DifferentSlots));
+        let other = SignedHeader::of(&signedBlock(&KeyPair::generate(), 7, "b"));
+        assert_eq!(DoubleSignEvidence::new(first.clone(), other).verify(), Err(EvidenceError::DifferentProducers));
+
+        let mut forged = second;
+        forged.header.prevHash = "c".to_string();
+        assert_eq!(DoubleSignEvidence::new(first, forged).verify(), Err(EvidenceError::InvalidSignature));
+    }
+}
@@ -1,3 +1,9 @@
+pub mod evidence;
 pub mod pos;
+pub mod signing;
+pub mod validators;
 
-pub use pos::ProofOfStake;
\ No newline at end of file
+pub use evidence::{DoubleSignEvidence, EvidenceError, SignedHeader};
+pub use pos::{ProofOfStake, ProposerError};
+pub use signing::SigningRecord;
+pub use validators::ValidatorSet;
@@ -1,63 +1,217 @@
-use std::collections::HashMap;
-use rand::Rng;
+use thiserror::Error;
+use crate::blockchain::BlockHeader;
+use crate::consensus::ValidatorSet;
+use crate::crypto::{vrf, SignatureAlgorithm};
 use crate::utils::hashing;
+use crate::wallet::{KeyPair, Wallet};
 
+// Time is divided into slots of this many seconds, counted from the Unix
+// epoch. Each slot has at most one block on a branch.
+pub const SLOT_SECS: i64 = 5;
+pub const SLOTS_PER_EPOCH: u64 = 32;
+// Chance, in percent, that the whole stake together wins a given slot.
+// Validators win in proportion to their stake, so some slots have no
+// proposer and some have several.
+pub const ACTIVE_SLOT_PERCENT: u64 = 50;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ProposerError {
+    #[error("Header is for slot {actual}, expected {expected}")]
+    WrongSlot { expected: u64, actual: u64 },
+
+    #[error("{0} is not a validator")]
+    NotValidator(String),
+
+    #[error("Header key does not belong to the producer")]
+    KeyMismatch,
+
+    #[error("Validators must use {} keys", SignatureAlgorithm::Ed25519)]
+    UnsupportedKey,
+
+    #[error("Invalid VRF proof")]
+    InvalidProof,
+
+    #[error("{producer} did not win slot {slot}")]
+    NotEligible { slot: u64, producer: String },
+}
+
+// Slot elections among the validators active in one epoch.
+#[derive(Default)]
 pub struct ProofOfStake {
-    stakes: HashMap<String, u64>,  
-    validatorHistory: Vec<String>, 
-    maxHistorySize: usize, 
+    validators: ValidatorSet,
 }
 
 impl ProofOfStake {
-    pub fn new(maxHistorySize: usize) -> Self {
-        ProofOfStake {
-            stakes: HashMap::new(),
-            validatorHistory: Vec::new(),
-            maxHistorySize,
-        }
+    pub fn new(validators: ValidatorSet) -> Self {
+        Self { validators }
+    }
+
+    pub fn validators(&self) -> &ValidatorSet {
+        &self.validators
     }
 
-    pub fn setStake(&mut self, validator: String, amount: u64) {
-        self.stakes.insert(validator, amount);
+    pub fn slotAt(timestamp: i64) -> u64 {
+        (timestamp.max(0) / SLOT_SECS) as u64
     }
 
-    pub fn selectValidator(&mut self, lastBlockHash: String) -> Option<String> {
-        let totalStake: u64 = self.stakes.values().sum();
-        if totalStake == 0 {
+    pub fn epochOf(slot: u64) -> u64 {
+        slot / SLOTS_PER_EPOCH
+    }
+
+    pub fn stakeOf(&self, validator: &str) -> u64 {
+        self.validators.stakeOf(validator)
+    }
+
+    pub fn totalStake(&self) -> u64 {
+        self.validators.totalStake()
+    }
+
+    // Evaluates our VRF on the slot's epoch seed and the slot. Returns the
+    // proof to put in the header if it shows we won the slot; nobody else
+    // can tell before we publish it.
+    pub fn proveEligibility(&self, keypair: &KeyPair, epochSeed: &str, slot: u64) -> Option<Vec<u8>> {
+        if keypair.algorithm != SignatureAlgorithm::Ed25519 {
             return None;
         }
+        let validator = Wallet::generate_address(&keypair.public_key);
+        let proof = vrf::prove(&keypair.private_key, ProofOfStake::vrfInput(epochSeed, slot).as_bytes()).ok()?;
+        let output = vrf::proof_to_hash(&proof)?;
+        self.isEligible(&validator, &output).then_some(proof)
+    }
 
-        let randomSeed = self.generateRandomSeed(&lastBlockHash);
-        let mut threshold = randomSeed % totalStake;
-        for (validator, stake) in &self.stakes {
-            if threshold < *stake {
-                if self.validatorHistory.contains(validator) {
-                    continue;
-                }
-                self.addToHistory(validator.clone());
-                return Some(validator.clone());
-            }
-            threshold -= stake;
+    // Checks that `header` fills `slot` and that its VRF proof shows the
+    // producer won it from the epoch seed.
+    pub fn verifyProposer(&self, epochSeed: &str, slot: u64, header: &BlockHeader) -> Result<(), ProposerError> {
+        if header.slot != slot {
+            return Err(ProposerError::WrongSlot { expected: slot, actual: header.slot });
+        }
+        if self.stakeOf(&header.producer) == 0 {
+            return Err(ProposerError::NotValidator(header.producer.clone()));
+        }
+        if Wallet::generate_address(&header.publicKey) != header.producer {
+            return Err(ProposerError::KeyMismatch);
+        }
+        if header.algorithm != SignatureAlgorithm::Ed25519 {
+            return Err(ProposerError::UnsupportedKey);
         }
-        None
+        let input = ProofOfStake::vrfInput(epochSeed, slot);
+        let output = vrf::verify(&header.publicKey, input.as_bytes(), &header.vrfProof)
+            .ok_or(ProposerError::InvalidProof)?;
+        if !self.isEligible(&header.producer, &output) {
+            return Err(ProposerError::NotEligible {
+                slot,
+                producer: header.producer.clone(),
+            });
+        }
+        Ok(())
     }
 
-    pub fn slash(&mut self, validator: &String, penalty: u64) {
-        if let Some(stake) = self.stakes.get_mut(validator) {
-            *stake = stake.saturating_sub(penalty);
-        }
+    // Beacon after `header`: its seed mixed with its proposer's VRF output,
+    // which the proposer cannot choose. The beacon as of an epoch's last
+    // block seeds the elections two epochs later, so by the time anyone
+    // can steer it, what it will elect is out of reach.
+    pub fn nextBeacon(header: &BlockHeader) -> String {
+        let output = vrf::proof_to_hash(&header.vrfProof).unwrap_or_default();
+        let combined = format!("{}{}", header.seed, hex::encode(output));
+        hashing::sha256(combined.as_bytes())
     }
 
-    fn addToHistory(&mut self, validator: String) {
-        self.validatorHistory.push(validator);
-        if self.validatorHistory.len() > self.maxHistorySize {
-            self.validatorHistory.remove(0); 
+    // A validator wins when its VRF output, read as a fraction of the
+    // output space, falls below its share of ACTIVE_SLOT_PERCENT.
+    fn isEligible(&self, validator: &str, output: &[u8]) -> bool {
+        let totalStake = self.totalStake();
+        if totalStake == 0 || output.len() < 8 {
+            return false;
         }
+        let mut value = [0u8; 8];
+        value.copy_from_slice(&output[..8]);
+        let value = u64::from_be_bytes(value) as u128;
+        // share / (100 * totalStake) scaled to 2^64, in two steps so any
+        // u64 stake fits in u128.
+        let share = self.stakeOf(validator) as u128 * ACTIVE_SLOT_PERCENT as u128;
+        let threshold = ((share << 57) / (100 * totalStake as u128)) << 7;
+        value < threshold
+    }
+
+    // First slot whose blocks no longer count towards the seed of `slot`'s
+    // epoch. The first two epochs have no earlier beacon and use the empty
+    // one.
+    pub fn seedCutoff(slot: u64) -> Option<u64> {
+        ProofOfStake::epochOf(slot).checked_sub(1).filter(|epoch| *epoch > 0).map(|epoch| epoch * SLOTS_PER_EPOCH)
+    }
+
+    fn vrfInput(epochSeed: &str, slot: u64) -> String {
+        format!("{}:{}", epochSeed, slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Block;
+
+    fn validator() -> (KeyPair, String) {
+        let keypair = KeyPair::generate();
+        let address = Wallet::generate_address(&keypair.public_key);
+        (keypair, address)
     }
 
-    fn generateRandomSeed(&self, lastBlockHash: &str) -> u64 {
-        let combined = format!("{}{}", lastBlockHash, rand::thread_rng().gen::<u64>());
-        let hash = hashing::sha256(combined.as_bytes());
-        u64::from_be_bytes(hash[0..8].try_into().unwrap())
+    #[test]
+    fn test_slots_won_in_proportion_to_stake() {
+        let (small, smallAddress) = validator();
+        let (large, largeAddress) = validator();
+        let consensus = ProofOfStake::new(ValidatorSet::genesis(&[(smallAddress, 10), (largeAddress, 30)]));
+
+        let wins = |keypair: &KeyPair| {
+            (0..400)
+                .filter(|slot| consensus.proveEligibility(keypair, "seed", *slot).is_some())
+                .count()
+        };
+        let (smallWins, largeWins) = (wins(&small), wins(&large));
+        assert!(smallWins > 0);
+        assert!(largeWins > 2 * smallWins);
+        assert!(largeWins < 300);
+        assert!(ProofOfStake::default().proveEligibility(&small, "seed", 0).is_none());
+    }
+
+    #[test]
+    fn test_verify_proposer() {
+        let (keypair, address) = validator();
+        let (other, otherAddress) = validator();
+        let consensus = ProofOfStake::new(ValidatorSet::genesis(&[(address.clone(), 10), (otherAddress.clone(), 10)]));
+        let (slot, proof) = (0..)
+            .find_map(|slot| consensus.proveEligibility(&keypair, "seed", slot).map(|proof| (slot, proof)))
+            .unwrap();
+
+        let mut header = Block::new(0, String::new(), address.clone(), Vec::new()).blockHeader;
+        header.slot = slot;
+        header.seed = "seed".to_string();
+        header.algorithm = keypair.algorithm;
+        header.publicKey = keypair.public_key.clone();
+        header.vrfProof = proof;
+        assert_eq!(consensus.verifyProposer("seed", slot, &header), Ok(()));
+        assert!(matches!(consensus.verifyProposer("seed", slot + 1, &header), Err(ProposerError::WrongSlot { .. })));
+        assert_eq!(consensus.verifyProposer("other seed", slot, &header), Err(ProposerError::InvalidProof));
+
+        let mut borrowed = header.clone();
+        borrowed.producer = otherAddress;
+        borrowed.publicKey = other.public_key.clone();
+        assert_eq!(consensus.verifyProposer("seed", slot, &borrowed), Err(ProposerError::InvalidProof));
+
+        let lost = (0..).find(|slot| consensus.proveEligibility(&keypair, "seed", *slot).is_none()).unwrap();
+        let mut unlucky = header.clone();
+        unlucky.slot = lost;
+        unlucky.vrfProof = vrf::prove(&keypair.private_key, ProofOfStake::vrfInput("seed", lost).as_bytes()).unwrap();
+        assert!(matches!(consensus.verifyProposer("seed", lost, &unlucky), Err(ProposerError::NotEligible { .. })));
+
+        assert_ne!(ProofOfStake::nextBeacon(&header), ProofOfStake::nextBeacon(&unlucky));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_seed_fixed_two_epochs_ahead() {
+        assert_eq!(ProofOfStake::seedCutoff(0), None);
+        assert_eq!(ProofOfStake::seedCutoff(2 * SLOTS_PER_EPOCH - 1), None);
+        assert_eq!(ProofOfStake::seedCutoff(2 * SLOTS_PER_EPOCH), Some(SLOTS_PER_EPOCH));
+        assert_eq!(ProofOfStake::seedCutoff(3 * SLOTS_PER_EPOCH - 1), Some(SLOTS_PER_EPOCH));
+    }
+}
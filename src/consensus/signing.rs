@@ -0,0 +1,79 @@
+use crate::storage::{Database, StorageError};
+
+pub const SIGNED_SLOT_KEY: &str = "last_signed_slot";
+
+// The highest slot our validator key signed a block for. Nothing at or
+// below it is signed again, so a reorg onto a branch with an older tip
+// can't get us to sign a second block for a slot and be slashed for it.
+// When opened on a database, each slot is saved and flushed before the
+// block is signed.
+#[derive(Default)]
+pub struct SigningRecord {
+    lastSigned: Option<u64>,
+    db: Option<Database>,
+}
+
+impl SigningRecord {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open(db: Database) -> Result<Self, StorageError> {
+        Ok(Self {
+            lastSigned: db.get(&SIGNED_SLOT_KEY)?,
+            db: Some(db),
+        })
+    }
+
+    pub fn lastSigned(&self) -> Option<u64> {
+        self.lastSigned
+    }
+
+    pub fn maySign(&self, slot: u64) -> bool {
+        self.lastSigned.is_none_or(|last| slot > last)
+    }
+
+    // Fails if the slot could not be saved, in which case the block must
+    // not be signed.
+    pub fn record(&mut self, slot: u64) -> Result<(), StorageError> {
+        let slot = self.lastSigned.map_or(slot, |last| last.max(slot));
+        self.lastSigned = Some(slot);
+        if let Some(db) = &self.db {
+            db.save(&SIGNED_SLOT_KEY, &slot)?;
+            db.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_slots_never_signed_again() {
+        let mut record = SigningRecord::new();
+        assert!(record.maySign(0));
+
+        record.record(5).unwrap();
+        assert!(!record.maySign(3));
+        assert!(!record.maySign(5));
+        assert!(record.maySign(6));
+
+        record.record(2).unwrap();
+        assert_eq!(record.lastSigned(), Some(5));
+    }
+
+    #[test]
+    fn test_signed_slot_persists_through_database() {
+        let db = Database::new("test_signing_db").unwrap();
+        db.delete(&SIGNED_SLOT_KEY).unwrap();
+
+        let mut record = SigningRecord::open(db.clone()).unwrap();
+        record.record(7).unwrap();
+        drop(record);
+
+        let reopened = SigningRecord::open(db).unwrap();
+        assert!(!reopened.maySign(7));
+    }
+}
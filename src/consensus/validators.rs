@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, VecDeque};
+
+// Bonded stake a validator needs to join or stay in the active set.
+pub const MIN_VALIDATOR_STAKE: u64 = 10;
+// Validators that can join, and separately leave, the active set at each
+// epoch boundary. The rest wait their turn in the queues.
+pub const CHURN_LIMIT: usize = 4;
+
+// The validators elected from during one epoch, snapshotted at its start,
+// and the validators waiting to join or leave at the next boundaries.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidatorSet {
+    epoch: u64,
+    active: BTreeMap<String, u64>,
+    activationQueue: VecDeque<String>,
+    exitQueue: VecDeque<String>,
+}
+
+impl ValidatorSet {
+    // Genesis validators are active from the first epoch without queueing.
+    pub fn genesis(bonds: &[(String, u64)]) -> Self {
+        Self {
+            active: bonds
+                .iter()
+                .filter(|(_, stake)| *stake >= MIN_VALIDATOR_STAKE)
+                .cloned()
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn active(&self) -> &BTreeMap<String, u64> {
+        &self.active
+    }
+
+    pub fn activationQueue(&self) -> &VecDeque<String> {
+        &self.activationQueue
+    }
+
+    pub fn exitQueue(&self) -> &VecDeque<String> {
+        &self.exitQueue
+    }
+
+    pub fn stakeOf(&self, validator: &str) -> u64 {
+        self.active.get(validator).copied().unwrap_or(0)
+    }
+
+    pub fn totalStake(&self) -> u64 {
+        self.active.values().fold(0, |total, stake| total.saturating_add(*stake))
+    }
+
+    // Drops `validator` from the set and the queues straight away.
+    pub fn remove(&mut self, validator: &str) {
+        self.active.remove(validator);
+        self.activationQueue.retain(|address| address != validator);
+        self.exitQueue.retain(|address| address != validator);
+    }
+
+    // Snapshot for `epoch` from the stake bonded at the end of the previous
+    // one. Active validators keep their place with their new stake; those
+    // that reached or dropped below MIN_VALIDATOR_STAKE queue to join or
+    // leave, and each epoch boundary since the last snapshot moves up to
+    // CHURN_LIMIT of each queue.
+    pub fn rotate(&self, epoch: u64, bonded: &[(String, u64)]) -> ValidatorSet {
+        let eligible: BTreeMap<&str, u64> = bonded
+            .iter()
+            .filter(|(_, stake)| *stake >= MIN_VALIDATOR_STAKE)
+            .map(|(address, stake)| (address.as_str(), *stake))
+            .collect();
+
+        let mut next = self.clone();
+        next.epoch = epoch;
+        next.activationQueue.retain(|address| eligible.contains_key(address.as_str()));
+        next.exitQueue.retain(|address| !eligible.contains_key(address.as_str()));
+        for address in eligible.keys() {
+            if !next.active.contains_key(*address) && !next.activationQueue.iter().any(|queued| queued == address) {
+                next.activationQueue.push_back(address.to_string());
+            }
+        }
+        for address in self.active.keys() {
+            if !eligible.contains_key(address.as_str()) && !next.exitQueue.contains(address) {
+                next.exitQueue.push_back(address.clone());
+            }
+        }
+        // Leaving validators stay active with the stake they were last
+        // elected with until their turn comes.
+        for (address, stake) in next.active.iter_mut() {
+            if let Some(bondedStake) = eligible.get(address.as_str()) {
+                *stake = *bondedStake;
+            }
+        }
+
+        let mut boundaries = epoch.saturating_sub(self.epoch);
+        while boundaries > 0 && !(next.activationQueue.is_empty() && next.exitQueue.is_empty()) {
+            for _ in 0..CHURN_LIMIT {
+                if let Some(address) = next.exitQueue.pop_front() {
+                    next.active.remove(&address);
+                }
+                if let Some(address) = next.activationQueue.pop_front() {
+                    let stake = eligible[address.as_str()];
+                    next.active.insert(address, stake);
+                }
+            }
+            boundaries -= 1;
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bonds(validators: &[(&str, u64)]) -> Vec<(String, u64)> {
+        validators.iter().map(|(address, stake)| (address.to_string(), *stake)).collect()
+    }
+
+    #[test]
+    fn test_rotation_respects_minimum_stake() {
+        let genesis = ValidatorSet::genesis(&bonds(&[("a", 20), ("b", MIN_VALIDATOR_STAKE - 1)]));
+        assert_eq!(genesis.totalStake(), 20);
+        assert_eq!(genesis.stakeOf("b"), 0);
+
+        let next = genesis.rotate(1, &bonds(&[("a", 30), ("b", MIN_VALIDATOR_STAKE)]));
+        assert_eq!(next.epoch(), 1);
+        assert_eq!(next.stakeOf("a"), 30);
+        assert_eq!(next.stakeOf("b"), MIN_VALIDATOR_STAKE);
+
+        let dropped = next.rotate(2, &bonds(&[("a", 30), ("b", 5)]));
+        assert_eq!(dropped.active().keys().collect::<Vec<_>>(), vec!["a"]);
+        assert_eq!(dropped.rotate(2, &bonds(&[("a", 30)])), dropped);
+    }
+
+    #[test]
+    fn test_churn_limit_queues_changes() {
+        let joining: Vec<(String, u64)> = (0..CHURN_LIMIT + 2).map(|i| (format!("v{}", i), 10)).collect();
+        let next = ValidatorSet::default().rotate(1, &joining);
+        assert_eq!(next.active().len(), CHURN_LIMIT);
+        assert_eq!(next.activationQueue().len(), 2);
+        assert_eq!(next.rotate(2, &joining).active().len(), CHURN_LIMIT + 2);
+        assert_eq!(ValidatorSet::default().rotate(5, &joining).active().len(), CHURN_LIMIT + 2);
+
+        let full = ValidatorSet::genesis(&joining);
+        let leaving = full.rotate(1, &joining[..1]);
+        assert_eq!(leaving.active().len(), joining.len() - CHURN_LIMIT);
+        assert_eq!(leaving.exitQueue().len(), joining.len() - 1 - CHURN_LIMIT);
+        assert_eq!(leaving.stakeOf("v5"), 10);
+
+        let returning = leaving.rotate(2, &joining);
+        assert!(returning.exitQueue().is_empty());
+        assert_eq!(returning.stakeOf("v5"), 10);
+    }
+}
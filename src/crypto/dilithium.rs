@@ -0,0 +1,720 @@
+// ML-DSA-44 (CRYSTALS-Dilithium as standardised in FIPS 204).
+
+use rand::Rng;
+use sha3::digest::{ExtendableOutput, Update, XofReader};
+use sha3::{Shake128, Shake256};
+use super::poly::{centered, reduce, Polynomial, N, Q};
+
+const D: u32 = 13;
+const TAU: usize = 39;
+const GAMMA1: i64 = 1 << 17;
+const GAMMA2: i64 = (Q - 1) / 88;
+const K: usize = 4;
+const L: usize = 4;
+const ETA: i64 = 2;
+const BETA: i64 = TAU as i64 * ETA;
+const OMEGA: usize = 80;
+
+const SEED_BYTES: usize = 32;
+const TR_BYTES: usize = 64;
+const CHALLENGE_BYTES: usize = 32;
+const T1_BITS: usize = 10;
+const ETA_BITS: usize = 3;
+const T0_BITS: usize = 13;
+const Z_BITS: usize = 18;
+const W1_BITS: usize = 6;
+
+pub const PUBLIC_KEY_BYTES: usize = SEED_BYTES + K * N * T1_BITS / 8;
+pub const SECRET_KEY_BYTES: usize =
+    2 * SEED_BYTES + TR_BYTES + (K + L) * N * ETA_BITS / 8 + K * N * T0_BITS / 8;
+pub const SIGNATURE_BYTES: usize = CHALLENGE_BYTES + L * N * Z_BITS / 8 + OMEGA + K;
+
+type PolyVec = Vec<Polynomial>;
+type Matrix = Vec<PolyVec>;
+
+pub fn generate_keypair() -> (Vec<u8>, Vec<u8>) {
+    let mut seed = [0u8; SEED_BYTES];
+    rand::thread_rng().fill(&mut seed);
+    keypair_from_seed(&seed)
+}
+
+pub fn keypair_from_seed(seed: &[u8; SEED_BYTES]) -> (Vec<u8>, Vec<u8>) {
+    let expanded = shake256(&[seed, &[K as u8, L as u8]], 128);
+    let (rho, rest) = expanded.split_at(SEED_BYTES);
+    let (rho_prime, key) = rest.split_at(64);
+
+    let (s1, s2) = expand_s(rho_prime);
+    let (t1, t0) = compute_t(rho, &s1, &s2);
+
+    let public_key = encode_public_key(rho, &t1);
+    let tr = shake256(&[&public_key], TR_BYTES);
+    let secret_key = encode_secret_key(rho, key, &tr, &s1, &s2, &t0);
+    (public_key, secret_key)
+}
+
+pub fn public_key_from_secret(secret_key: &[u8]) -> Result<Vec<u8>, String> {
+    let (rho, _, tr, s1, s2, _) = decode_secret_key(secret_key)?;
+    let (t1, _) = compute_t(rho, &s1, &s2);
+    let public_key = encode_public_key(rho, &t1);
+    if shake256(&[&public_key], TR_BYTES) != tr {
+        return Err("Secret key does not match its embedded public key hash".to_string());
+    }
+    Ok(public_key)
+}
+
+pub fn validate_public_key(public_key: &[u8]) -> Result<(), String> {
+    if public_key.len() != PUBLIC_KEY_BYTES {
+        return Err(format!(
+            "Invalid public key length: expected {}, got {}",
+            PUBLIC_KEY_BYTES,
+            public_key.len()
+        ));
+    }
+    Ok(())
+}
+
+pub fn sign(secret_key: &[u8], message: &[u8]) -> Result<Vec<u8>, String> {
+    let mut rnd = [0u8; SEED_BYTES];
+    rand::thread_rng().fill(&mut rnd);
+    sign_internal(secret_key, &format_message(message), &rnd)
+}
+
+pub fn sign_deterministic(secret_key: &[u8], message: &[u8]) -> Result<Vec<u8>, String> {
+    sign_internal(secret_key, &format_message(message), &[0u8; SEED_BYTES])
+}
+
+pub fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    verify_internal(public_key, &format_message(message), signature)
+}
+
+// Pure ML-DSA with an empty context string.
+fn format_message(message: &[u8]) -> Vec<u8> {
+    let mut formatted = vec![0u8, 0u8];
+    formatted.extend_from_slice(message);
+    formatted
+}
+
+fn sign_internal(secret_key: &[u8], message: &[u8], rnd: &[u8]) -> Result<Vec<u8>, String> {
+    let (rho, key, tr, s1, s2, t0) = decode_secret_key(secret_key)?;
+    let s1_hat = ntt_vec(&s1);
+    let s2_hat = ntt_vec(&s2);
+    let t0_hat = ntt_vec(&t0);
+    let matrix = expand_a(rho);
+
+    let mu = shake256(&[tr, message], 64);
+    let rho_second = shake256(&[key, rnd, &mu], 64);
+
+    let mut kappa: u16 = 0;
+    loop {
+        let y = expand_mask(&rho_second, kappa);
+        kappa = kappa.wrapping_add(L as u16);
+
+        let w: PolyVec = multiply_matrix(&matrix, &ntt_vec(&y))
+            .iter()
+            .map(Polynomial::inverse_ntt)
+            .collect();
+        let w1: PolyVec = w.iter().map(|p| map_poly(p, high_bits)).collect();
+
+        let challenge = shake256(&[&mu, &encode_w1(&w1)], CHALLENGE_BYTES);
+        let c_hat = sample_in_ball(&challenge).ntt();
+
+        let cs1: PolyVec = s1_hat.iter().map(|s| c_hat.pointwise(s).inverse_ntt()).collect();
+        let cs2: PolyVec = s2_hat.iter().map(|s| c_hat.pointwise(s).inverse_ntt()).collect();
+
+        let z: PolyVec = y.iter().zip(&cs1).map(|(yi, ci)| yi.add(ci)).collect();
+        if vec_norm(&z) >= GAMMA1 - BETA {
+            continue;
+        }
+
+        let w_minus_cs2: PolyVec = w.iter().zip(&cs2).map(|(wi, ci)| wi.sub(ci)).collect();
+        let low_norm = w_minus_cs2
+            .iter()
+            .flat_map(|p| p.coefficients.iter())
+            .map(|&c| low_bits(c).abs())
+            .max()
+            .unwrap_or(0);
+        if low_norm >= GAMMA2 - BETA {
+            continue;
+        }
+
+        let ct0: PolyVec = t0_hat.iter().map(|t| c_hat.pointwise(t).inverse_ntt()).collect();
+        if vec_norm(&ct0) >= GAMMA2 {
+            continue;
+        }
+
+        let hints: Vec<[bool; N]> = ct0
+            .iter()
+            .zip(&w_minus_cs2)
+            .map(|(ct0i, wi)| {
+                let minus_ct0 = ct0i.negate();
+                let r = wi.add(ct0i);
+                let mut hint = [false; N];
+                for (j, set) in hint.iter_mut().enumerate() {
+                    *set = make_hint(minus_ct0.coefficients[j], r.coefficients[j]);
+                }
+                hint
+            })
+            .collect();
+        let ones: usize = hints.iter().map(|h| h.iter().filter(|&&b| b).count()).sum();
+        if ones > OMEGA {
+            continue;
+        }
+
+        return Ok(encode_signature(&challenge, &z, &hints));
+    }
+}
+
+fn verify_internal(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let (rho, t1) = match decode_public_key(public_key) {
+        Some(decoded) => decoded,
+        None => return false,
+    };
+    let (challenge, z, hints) = match decode_signature(signature) {
+        Some(decoded) => decoded,
+        None => return false,
+    };
+    if vec_norm(&z) >= GAMMA1 - BETA {
+        return false;
+    }
+
+    let matrix = expand_a(rho);
+    let tr = shake256(&[public_key], TR_BYTES);
+    let mu = shake256(&[&tr, message], 64);
+    let c_hat = sample_in_ball(challenge).ntt();
+
+    let az = multiply_matrix(&matrix, &ntt_vec(&z));
+    let w1: PolyVec = az
+        .iter()
+        .zip(&t1)
+        .zip(&hints)
+        .map(|((azi, t1i), hint)| {
+            let ct1 = c_hat.pointwise(&t1i.shift_left(D).ntt());
+            let w_approx = azi.sub(&ct1).inverse_ntt();
+            let mut w1 = Polynomial::zero();
+            for (j, &set) in hint.iter().enumerate() {
+                w1.coefficients[j] = use_hint(set, w_approx.coefficients[j]);
+            }
+            w1
+        })
+        .collect();
+
+    let expected = shake256(&[&mu, &encode_w1(&w1)], CHALLENGE_BYTES);
+    expected == challenge
+}
+
+// t = A*s1 + s2, split into high (t1) and low (t0) bits.
+fn compute_t(rho: &[u8], s1: &[Polynomial], s2: &[Polynomial]) -> (PolyVec, PolyVec) {
+    let matrix = expand_a(rho);
+    multiply_matrix(&matrix, &ntt_vec(s1))
+        .iter()
+        .zip(s2)
+        .map(|(as1, s2i)| power2round_poly(&as1.inverse_ntt().add(s2i)))
+        .unzip()
+}
+
+fn shake256(inputs: &[&[u8]], length: usize) -> Vec<u8> {
+    let mut hasher = Shake256::default();
+    for input in inputs {
+        hasher.update(input);
+    }
+    let mut output = vec![0u8; length];
+    hasher.finalize_xof().read(&mut output);
+    output
+}
+
+fn expand_a(rho: &[u8]) -> Matrix {
+    (0..K)
+        .map(|r| (0..L).map(|s| rej_ntt_poly(rho, s as u8, r as u8)).collect())
+        .collect()
+}
+
+fn rej_ntt_poly(rho: &[u8], column: u8, row: u8) -> Polynomial {
+    let mut hasher = Shake128::default();
+    hasher.update(rho);
+    hasher.update(&[column, row]);
+    let mut reader = hasher.finalize_xof();
+
+    let mut poly = Polynomial::zero();
+    let mut j = 0;
+    let mut bytes = [0u8; 3];
+    while j < N {
+        reader.read(&mut bytes);
+        let candidate = bytes[0] as i64 | (bytes[1] as i64) << 8 | ((bytes[2] & 0x7F) as i64) << 16;
+        if candidate < Q {
+            poly.coefficients[j] = candidate;
+            j += 1;
+        }
+    }
+    poly
+}
+
+fn expand_s(rho_prime: &[u8]) -> (PolyVec, PolyVec) {
+    let s1 = (0..L).map(|r| rej_bounded_poly(rho_prime, r as u16)).collect();
+    let s2 = (0..K).map(|r| rej_bounded_poly(rho_prime, (r + L) as u16)).collect();
+    (s1, s2)
+}
+
+fn rej_bounded_poly(rho_prime: &[u8], nonce: u16) -> Polynomial {
+    let mut hasher = Shake256::default();
+    hasher.update(rho_prime);
+    hasher.update(&nonce.to_le_bytes());
+    let mut reader = hasher.finalize_xof();
+
+    let mut poly = Polynomial::zero();
+    let mut j = 0;
+    let mut byte = [0u8; 1];
+    while j < N {
+        reader.read(&mut byte);
+        for half in [byte[0] & 0x0F, byte[0] >> 4] {
+            if half < 15 && j < N {
+                poly.coefficients[j] = reduce(ETA - (half % 5) as i64);
+                j += 1;
+            }
+        }
+    }
+    poly
+}
+
+fn expand_mask(rho: &[u8], kappa: u16) -> PolyVec {
+    (0..L)
+        .map(|r| {
+            let nonce = kappa.wrapping_add(r as u16).to_le_bytes();
+            let bytes = shake256(&[rho, &nonce], N * Z_BITS / 8);
+            bit_unpack(&bytes, Z_BITS, GAMMA1)
+        })
+        .collect()
+}
+
+fn sample_in_ball(seed: &[u8]) -> Polynomial {
+    let mut hasher = Shake256::default();
+    hasher.update(seed);
+    let mut reader = hasher.finalize_xof();
+
+    let mut sign_bytes = [0u8; 8];
+    reader.read(&mut sign_bytes);
+    let mut signs = u64::from_le_bytes(sign_bytes);
+
+    let mut c = Polynomial::zero();
+    let mut byte = [0u8; 1];
+    for i in N - TAU..N {
+        let j = loop {
+            reader.read(&mut byte);
+            if byte[0] as usize <= i {
+                break byte[0] as usize;
+            }
+        };
+        c.coefficients[i] = c.coefficients[j];
+        c.coefficients[j] = if signs & 1 == 1 { Q - 1 } else { 1 };
+        signs >>= 1;
+    }
+    c
+}
+
+fn ntt_vec(v: &[Polynomial]) -> PolyVec {
+    v.iter().map(Polynomial::ntt).collect()
+}
+
+fn multiply_matrix(matrix: &Matrix, v_hat: &[Polynomial]) -> PolyVec {
+    matrix
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(v_hat)
+                .fold(Polynomial::zero(), |acc, (a, v)| acc.add(&a.pointwise(v)))
+        })
+        .collect()
+}
+
+fn vec_norm(v: &[Polynomial]) -> i64 {
+    v.iter().map(Polynomial::infinity_norm).max().unwrap_or(0)
+}
+
+fn map_poly(poly: &Polynomial, f: fn(i64) -> i64) -> Polynomial {
+    Polynomial {
+        coefficients: poly.coefficients.map(f),
+    }
+}
+
+fn power2round_poly(poly: &Polynomial) -> (Polynomial, Polynomial) {
+    let mut t1 = Polynomial::zero();
+    let mut t0 = Polynomial::zero();
+    for (i, &r) in poly.coefficients.iter().enumerate() {
+        let r0 = centered(r, 1 << D);
+        t1.coefficients[i] = (r - r0) >> D;
+        t0.coefficients[i] = reduce(r0);
+    }
+    (t1, t0)
+}
+
+fn decompose(r: i64) -> (i64, i64) {
+    let r = reduce(r);
+    let r0 = centered(r, 2 * GAMMA2);
+    if r - r0 == Q - 1 {
+        (0, r0 - 1)
+    } else {
+        ((r - r0) / (2 * GAMMA2), r0)
+    }
+}
+
+fn high_bits(r: i64) -> i64 {
+    decompose(r).0
+}
+
+fn low_bits(r: i64) -> i64 {
+    decompose(r).1
+}
+
+fn make_hint(z: i64, r: i64) -> bool {
+    high_bits(r) != high_bits(r + z)
+}
+
+fn use_hint(hint: bool, r: i64) -> i64 {
+    let m = (Q - 1) / (2 * GAMMA2);
+    let (r1, r0) = decompose(r);
+    match (hint, r0 > 0) {
+        (true, true) => (r1 + 1).rem_euclid(m),
+        (true, false) => (r1 - 1).rem_euclid(m),
+        _ => r1,
+    }
+}
+
+fn pack_bits(values: impl Iterator<Item = u32>, bits: usize, output: &mut Vec<u8>) {
+    let mut buffer: u64 = 0;
+    let mut filled = 0;
+    for value in values {
+        buffer |= (value as u64) << filled;
+        filled += bits;
+        while filled >= 8 {
+            output.push(buffer as u8);
+            buffer >>= 8;
+            filled -= 8;
+        }
+    }
+}
+
+fn unpack_bits(bytes: &[u8], bits: usize) -> [u32; N] {
+    let mut values = [0u32; N];
+    let mut buffer: u64 = 0;
+    let mut filled = 0;
+    let mut bytes = bytes.iter();
+    for value in values.iter_mut() {
+        while filled < bits {
+            buffer |= (*bytes.next().expect("Packed polynomial too short") as u64) << filled;
+            filled += 8;
+        }
+        *value = (buffer & ((1 << bits) - 1)) as u32;
+        buffer >>= bits;
+        filled -= bits;
+    }
+    values
+}
+
+fn simple_bit_pack(poly: &Polynomial, bits: usize, output: &mut Vec<u8>) {
+    pack_bits(poly.coefficients.iter().map(|&c| c as u32), bits, output);
+}
+
+fn simple_bit_unpack(bytes: &[u8], bits: usize) -> Polynomial {
+    Polynomial {
+        coefficients: unpack_bits(bytes, bits).map(|v| v as i64),
+    }
+}
+
+// Coefficients in [bound - 2^bits + 1, bound] are stored as `bound - coefficient`.
+fn bit_pack(poly: &Polynomial, bits: usize, bound: i64, output: &mut Vec<u8>) {
+    pack_bits(
+        poly.coefficients.iter().map(|&c| (bound - centered(c, Q)) as u32),
+        bits,
+        output,
+    );
+}
+
+fn bit_unpack(bytes: &[u8], bits: usize, bound: i64) -> Polynomial {
+    Polynomial {
+        coefficients: unpack_bits(bytes, bits).map(|v| reduce(bound - v as i64)),
+    }
+}
+
+fn encode_public_key(rho: &[u8], t1: &[Polynomial]) -> Vec<u8> {
+    let mut output = rho.to_vec();
+    for poly in t1 {
+        simple_bit_pack(poly, T1_BITS, &mut output);
+    }
+    output
+}
+
+fn decode_public_key(public_key: &[u8]) -> Option<(&[u8], PolyVec)> {
+    if public_key.len() != PUBLIC_KEY_BYTES {
+        return None;
+    }
+    let (rho, packed) = public_key.split_at(SEED_BYTES);
+    let t1 = packed
+        .chunks(N * T1_BITS / 8)
+        .map(|chunk| simple_bit_unpack(chunk, T1_BITS))
+        .collect();
+    Some((rho, t1))
+}
+
+fn encode_secret_key(
+    rho: &[u8],
+    key: &[u8],
+    tr: &[u8],
+    s1: &[Polynomial],
+    s2: &[Polynomial],
+    t0: &[Polynomial],
+) -> Vec<u8> {
+    let mut output = Vec::with_capacity(SECRET_KEY_BYTES);
+    output.extend_from_slice(rho);
+    output.extend_from_slice(key);
+    output.extend_from_slice(tr);
+    for poly in s1.iter().chain(s2) {
+        bit_pack(poly, ETA_BITS, ETA, &mut output);
+    }
+    for poly in t0 {
+        bit_pack(poly, T0_BITS, 1 << (D - 1), &mut output);
+    }
+    output
+}
+
+#[allow(clippy::type_complexity)]
+fn decode_secret_key(
+    secret_key: &[u8],
+) -> Result<(&[u8], &[u8], &[u8], PolyVec, PolyVec, PolyVec), String> {
+    if secret_key.len() != SECRET_KEY_BYTES {
+        return Err(format!(
+            "Invalid secret key length: expected {}, got {}",
+            SECRET_KEY_BYTES,
+            secret_key.len()
+        ));
+    }
+    let (rho, rest) = secret_key.split_at(SEED_BYTES);
+    let (key, rest) = rest.split_at(SEED_BYTES);
+    let (tr, rest) = rest.split_at(TR_BYTES);
+    let (packed_s, packed_t0) = rest.split_at((K + L) * N * ETA_BITS / 8);
+
+    let mut s: PolyVec = packed_s
+        .chunks(N * ETA_BITS / 8)
+        .map(|chunk| bit_unpack(chunk, ETA_BITS, ETA))
+        .collect();
+    let s2 = s.split_off(L);
+    let t0 = packed_t0
+        .chunks(N * T0_BITS / 8)
+        .map(|chunk| bit_unpack(chunk, T0_BITS, 1 << (D - 1)))
+        .collect();
+    Ok((rho, key, tr, s, s2, t0))
+}
+
+fn encode_w1(w1: &[Polynomial]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(K * N * W1_BITS / 8);
+    for poly in w1 {
+        simple_bit_pack(poly, W1_BITS, &mut output);
+    }
+    output
+}
+
+fn encode_signature(challenge: &[u8], z: &[Polynomial], hints: &[[bool; N]]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(SIGNATURE_BYTES);
+    output.extend_from_slice(challenge);
+    for poly in z {
+        bit_pack(poly, Z_BITS, GAMMA1, &mut output);
+    }
+
+    let mut hint_bytes = [0u8; OMEGA + K];
+    let mut index = 0;
+    for (i, hint) in hints.iter().enumerate() {
+        for (j, &set) in hint.iter().enumerate() {
+            if set {
+                hint_bytes[index] = j as u8;
+                index += 1;
+            }
+        }
+        hint_bytes[OMEGA + i] = index as u8;
+    }
+    output.extend_from_slice(&hint_bytes);
+    output
+}
+
+#[allow(clippy::type_complexity)]
+fn decode_signature(signature: &[u8]) -> Option<(&[u8], PolyVec, Vec<[bool; N]>)> {
+    if signature.len() != SIGNATURE_BYTES {
+        return None;
+    }
+    let (challenge, rest) = signature.split_at(CHALLENGE_BYTES);
+    let (packed_z, hint_bytes) = rest.split_at(L * N * Z_BITS / 8);
+    let z = packed_z
+        .chunks(N * Z_BITS / 8)
+        .map(|chunk| bit_unpack(chunk, Z_BITS, GAMMA1))
+        .collect();
+
+    let mut hints = vec![[false; N]; K];
+    let mut index = 0;
+    for (i, hint) in hints.iter_mut().enumerate() {
+        let end = hint_bytes[OMEGA + i] as usize;
+        if end < index || end > OMEGA {
+            return None;
+        }
+        let first = index;
+        while index < end {
+            if index > first && hint_bytes[index - 1] >= hint_bytes[index] {
+                return None;
+            }
+            hint[hint_bytes[index] as usize] = true;
+            index += 1;
+        }
+    }
+    if hint_bytes[index..OMEGA].iter().any(|&b| b != 0) {
+        return None;
+    }
+    Some((challenge, z, hints))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_encoded_sizes() {
+        assert_eq!(PUBLIC_KEY_BYTES, 1312);
+        assert_eq!(SECRET_KEY_BYTES, 2560);
+        assert_eq!(SIGNATURE_BYTES, 2420);
+
+        let (public_key, secret_key) = generate_keypair();
+        assert_eq!(public_key.len(), PUBLIC_KEY_BYTES);
+        assert_eq!(secret_key.len(), SECRET_KEY_BYTES);
+        assert_eq!(sign(&secret_key, b"size").unwrap().len(), SIGNATURE_BYTES);
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let (public_key, secret_key) = generate_keypair();
+        let message = b"sender:receiver:50";
+
+        let signature = sign(&secret_key, message).unwrap();
+        assert!(verify(&public_key, message, &signature));
+        assert!(!verify(&public_key, b"sender:receiver:51", &signature));
+
+        let (other_public_key, _) = generate_keypair();
+        assert!(!verify(&other_public_key, message, &signature));
+    }
+
+    #[test]
+    fn test_tampered_signature_rejected() {
+        let (public_key, secret_key) = generate_keypair();
+        let message = b"message";
+        let signature = sign(&secret_key, message).unwrap();
+
+        for position in [0, CHALLENGE_BYTES + 10, SIGNATURE_BYTES - 1] {
+            let mut tampered = signature.clone();
+            tampered[position] ^= 0x01;
+            assert!(!verify(&public_key, message, &tampered));
+        }
+        assert!(!verify(&public_key, message, &signature[1..]));
+        assert!(!verify(&public_key[1..], message, &signature));
+    }
+
+    #[test]
+    fn test_keypair_from_seed_is_deterministic() {
+        let seed = [7u8; SEED_BYTES];
+        let (public_key, secret_key) = keypair_from_seed(&seed);
+        assert_eq!(keypair_from_seed(&seed), (public_key.clone(), secret_key.clone()));
+        assert_ne!(keypair_from_seed(&[8u8; SEED_BYTES]).0, public_key);
+
+        let first = sign_deterministic(&secret_key, b"message").unwrap();
+        let second = sign_deterministic(&secret_key, b"message").unwrap();
+        assert_eq!(first, second);
+        assert!(verify(&public_key, b"message", &first));
+    }
+
+    #[test]
+    fn test_public_key_from_secret() {
+        let (public_key, secret_key) = generate_keypair();
+        assert_eq!(public_key_from_secret(&secret_key).unwrap(), public_key);
+
+        let mut corrupted = secret_key.clone();
+        corrupted[SECRET_KEY_BYTES - 1] ^= 0x01;
+        corrupted[2 * SEED_BYTES + TR_BYTES] ^= 0x01;
+        assert!(public_key_from_secret(&corrupted).is_err());
+        assert!(public_key_from_secret(&secret_key[1..]).is_err());
+    }
+
+    #[test]
+    fn test_secret_key_roundtrip() {
+        let (_, secret_key) = keypair_from_seed(&[1u8; SEED_BYTES]);
+        let (rho, key, tr, s1, s2, t0) = decode_secret_key(&secret_key).unwrap();
+        assert!(s1.iter().chain(&s2).all(|p| p.infinity_norm() <= ETA));
+        assert!(t0.iter().all(|p| p.infinity_norm() <= 1 << (D - 1)));
+        assert_eq!(encode_secret_key(rho, key, tr, &s1, &s2, &t0), secret_key);
+    }
+
+    #[test]
+    fn test_sample_in_ball_weight() {
+        let c = sample_in_ball(&[42u8; CHALLENGE_BYTES]);
+        let nonzero = c.coefficients.iter().filter(|&&x| x != 0).count();
+        assert_eq!(nonzero, TAU);
+        assert_eq!(c.infinity_norm(), 1);
+    }
+
+    #[test]
+    fn test_decompose_and_hints() {
+        for r in [0, 1, GAMMA2, GAMMA2 + 1, Q - GAMMA2, Q - 1, 4190208] {
+            let (r1, r0) = decompose(r);
+            assert!(r0.abs() <= GAMMA2);
+            assert_eq!(reduce(r1 * 2 * GAMMA2 + r0), r);
+            for z in [-GAMMA2 + 1, -1, 0, 1, GAMMA2 - 1] {
+                let hint = make_hint(z, r);
+                assert_eq!(use_hint(hint, r + z), high_bits(r));
+            }
+        }
+    }
+
+    // Cases of one section of testdata/ml_dsa_44.rsp, each a map from field
+    // name to decoded value.
+    fn known_answers(section: &str) -> Vec<HashMap<&'static str, Vec<u8>>> {
+        let mut cases = Vec::new();
+        let mut current = None;
+        for line in include_str!("testdata/ml_dsa_44.rsp").lines() {
+            let line = line.trim();
+            if line.starts_with('[') {
+                current = Some(line == format!("[{}]", section));
+            } else if let (Some(true), Some((name, value))) = (current, line.split_once(" = ")) {
+                if cases.last().is_none_or(|case: &HashMap<_, _>| case.contains_key(name)) {
+                    cases.push(HashMap::new());
+                }
+                let value = match value {
+                    "true" => vec![1],
+                    "false" => vec![0],
+                    hex => hex::decode(hex).unwrap(),
+                };
+                cases.last_mut().unwrap().insert(name, value);
+            }
+        }
+        assert!(!cases.is_empty(), "No {} vectors", section);
+        cases
+    }
+
+    #[test]
+    fn test_keygen_known_answers() {
+        for case in known_answers("keyGen") {
+            let seed: [u8; SEED_BYTES] = case["seed"].clone().try_into().unwrap();
+            let (public_key, secret_key) = keypair_from_seed(&seed);
+            assert_eq!(public_key, case["pk"]);
+            assert_eq!(secret_key, case["sk"]);
+        }
+    }
+
+    #[test]
+    fn test_siggen_known_answers() {
+        for case in known_answers("sigGen") {
+            let signature = sign_internal(&case["sk"], &format_message(&case["message"]), &case["rnd"]).unwrap();
+            assert_eq!(signature, case["signature"]);
+        }
+    }
+
+    #[test]
+    fn test_sigver_known_answers() {
+        for case in known_answers("sigVer") {
+            let passed = verify(&case["pk"], &case["message"], &case["signature"]);
+            assert_eq!(passed, case["testPassed"] == [1]);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
+use rand::Rng;
+
+pub const PUBLIC_KEY_BYTES: usize = 32;
+pub const SECRET_KEY_BYTES: usize = 32;
+pub const SIGNATURE_BYTES: usize = 64;
+
+pub fn generate_keypair() -> (Vec<u8>, Vec<u8>) {
+    let mut secret_key = [0u8; SECRET_KEY_BYTES];
+    rand::thread_rng().fill(&mut secret_key);
+    let public_key = public_key_from_secret(&secret_key)
+        .expect("Generated secret key must be 32 bytes");
+    (public_key, secret_key.to_vec())
+}
+
+pub fn public_key_from_secret(secret_key: &[u8]) -> Result<Vec<u8>, String> {
+    let secret = SecretKey::from_bytes(secret_key).map_err(|e| e.to_string())?;
+    Ok(PublicKey::from(&secret).to_bytes().to_vec())
+}
+
+pub fn validate_public_key(public_key: &[u8]) -> Result<(), String> {
+    PublicKey::from_bytes(public_key)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub fn sign(secret_key: &[u8], message: &[u8]) -> Result<Vec<u8>, String> {
+    let secret = SecretKey::from_bytes(secret_key).map_err(|e| e.to_string())?;
+    let public = PublicKey::from(&secret);
+    let keypair = Keypair { secret, public };
+    Ok(keypair.sign(message).to_bytes().to_vec())
+}
+
+pub fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let public_key = match PublicKey::from_bytes(public_key) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    let signature = match Signature::from_bytes(signature) {
+        Ok(sig) => sig,
+        Err(_) => return false,
+    };
+    public_key.verify(message, &signature).is_ok()
+}
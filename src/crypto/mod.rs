@@ -0,0 +1,8 @@
+pub mod poly;
+pub mod dilithium;
+pub mod ed25519;
+pub mod scheme;
+pub mod vrf;
+
+pub use poly::Polynomial;
+pub use scheme::{scheme_for, SignatureAlgorithm, SignatureScheme};
\ No newline at end of file
@@ -0,0 +1,210 @@
+use std::sync::OnceLock;
+
+pub const Q: i64 = 8380417;
+pub const N: usize = 256;
+
+const ZETA: i64 = 1753;
+const N_INVERSE: i64 = 8347681;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Polynomial {
+    pub coefficients: [i64; N],
+}
+
+impl Polynomial {
+    pub fn zero() -> Self {
+        Polynomial { coefficients: [0; N] }
+    }
+
+    pub fn new(coefficients: [i64; N]) -> Self {
+        Polynomial {
+            coefficients: coefficients.map(reduce),
+        }
+    }
+
+    pub fn add(&self, other: &Polynomial) -> Self {
+        let mut result = Polynomial::zero();
+        for i in 0..N {
+            result.coefficients[i] = reduce(self.coefficients[i] + other.coefficients[i]);
+        }
+        result
+    }
+
+    pub fn sub(&self, other: &Polynomial) -> Self {
+        let mut result = Polynomial::zero();
+        for i in 0..N {
+            result.coefficients[i] = reduce(self.coefficients[i] - other.coefficients[i]);
+        }
+        result
+    }
+
+    pub fn negate(&self) -> Self {
+        Polynomial::zero().sub(self)
+    }
+
+    pub fn shift_left(&self, bits: u32) -> Self {
+        Polynomial::new(self.coefficients.map(|c| c << bits))
+    }
+
+    // Coefficient-wise product, only meaningful for polynomials in the NTT domain.
+    pub fn pointwise(&self, other: &Polynomial) -> Self {
+        let mut result = Polynomial::zero();
+        for i in 0..N {
+            result.coefficients[i] = reduce(self.coefficients[i] * other.coefficients[i]);
+        }
+        result
+    }
+
+    // Product in Z_q[X]/(X^256 + 1).
+    pub fn multiply(&self, other: &Polynomial) -> Self {
+        self.ntt().pointwise(&other.ntt()).inverse_ntt()
+    }
+
+    pub fn ntt(&self) -> Self {
+        let zetas = zetas();
+        let mut w = self.coefficients;
+        let mut m = 0;
+        let mut len = 128;
+        while len >= 1 {
+            let mut start = 0;
+            while start < N {
+                m += 1;
+                let z = zetas[m];
+                for j in start..start + len {
+                    let t = reduce(z * w[j + len]);
+                    w[j + len] = reduce(w[j] - t);
+                    w[j] = reduce(w[j] + t);
+                }
+                start += 2 * len;
+            }
+            len /= 2;
+        }
+        Polynomial { coefficients: w }
+    }
+
+    pub fn inverse_ntt(&self) -> Self {
+        let zetas = zetas();
+        let mut w = self.coefficients;
+        let mut m = N;
+        let mut len = 1;
+        while len < N {
+            let mut start = 0;
+            while start < N {
+                m -= 1;
+                let z = Q - zetas[m];
+                for j in start..start + len {
+                    let t = w[j];
+                    w[j] = reduce(t + w[j + len]);
+                    w[j + len] = reduce(z * (t - w[j + len]));
+                }
+                start += 2 * len;
+            }
+            len *= 2;
+        }
+        Polynomial {
+            coefficients: w.map(|c| reduce(c * N_INVERSE)),
+        }
+    }
+
+    pub fn infinity_norm(&self) -> i64 {
+        self.coefficients
+            .iter()
+            .map(|&c| centered(c, Q).abs())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+pub fn reduce(a: i64) -> i64 {
+    a.rem_euclid(Q)
+}
+
+// The representative of `a` modulo `alpha` in (-alpha/2, alpha/2].
+pub fn centered(a: i64, alpha: i64) -> i64 {
+    let r = a.rem_euclid(alpha);
+    if r > alpha / 2 {
+        r - alpha
+    } else {
+        r
+    }
+}
+
+fn zetas() -> &'static [i64; N] {
+    static ZETAS: OnceLock<[i64; N]> = OnceLock::new();
+    ZETAS.get_or_init(|| {
+        let mut table = [0; N];
+        for (k, zeta) in table.iter_mut().enumerate() {
+            *zeta = pow_mod(ZETA, (k as u8).reverse_bits() as u64);
+        }
+        table
+    })
+}
+
+fn pow_mod(base: i64, mut exponent: u64) -> i64 {
+    let mut result = 1;
+    let mut base = reduce(base);
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = reduce(result * base);
+        }
+        base = reduce(base * base);
+        exponent >>= 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(seed: i64) -> Polynomial {
+        let mut coefficients = [0; N];
+        for (i, c) in coefficients.iter_mut().enumerate() {
+            *c = (seed * 7919 + i as i64 * 104729 + (i as i64 * i as i64) * 31) % Q;
+        }
+        Polynomial::new(coefficients)
+    }
+
+    fn schoolbook(a: &Polynomial, b: &Polynomial) -> Polynomial {
+        let mut result = [0i64; N];
+        for i in 0..N {
+            for j in 0..N {
+                let product = reduce(a.coefficients[i] * b.coefficients[j]);
+                if i + j < N {
+                    result[i + j] = reduce(result[i + j] + product);
+                } else {
+                    result[i + j - N] = reduce(result[i + j - N] - product);
+                }
+            }
+        }
+        Polynomial::new(result)
+    }
+
+    #[test]
+    fn test_zeta_is_primitive_512th_root() {
+        assert_eq!(pow_mod(ZETA, 256), Q - 1);
+        assert_eq!(pow_mod(ZETA, 512), 1);
+        assert_eq!(reduce(N as i64 * N_INVERSE), 1);
+    }
+
+    #[test]
+    fn test_ntt_roundtrip() {
+        let poly = sample(3);
+        assert_eq!(poly.ntt().inverse_ntt(), poly);
+    }
+
+    #[test]
+    fn test_ntt_multiplication_matches_schoolbook() {
+        let a = sample(1);
+        let b = sample(2);
+        assert_eq!(a.multiply(&b), schoolbook(&a, &b));
+    }
+
+    #[test]
+    fn test_centered_representative() {
+        assert_eq!(centered(Q - 1, Q), -1);
+        assert_eq!(centered(4096, 8192), 4096);
+        assert_eq!(centered(4097, 8192), -4095);
+        assert_eq!(Polynomial::new([Q - 5; N]).infinity_norm(), 5);
+    }
+}
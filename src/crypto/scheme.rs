@@ -0,0 +1,219 @@
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use super::{dilithium, ed25519};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SignatureAlgorithm {
+    Ed25519,
+    Dilithium,
+}
+
+impl SignatureAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SignatureAlgorithm::Ed25519 => "ed25519",
+            SignatureAlgorithm::Dilithium => "dilithium",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ed25519" => Some(SignatureAlgorithm::Ed25519),
+            "dilithium" => Some(SignatureAlgorithm::Dilithium),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SignatureAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub trait SignatureScheme: Sync {
+    fn algorithm(&self) -> SignatureAlgorithm;
+
+    // Returns `(public_key, secret_key)`.
+    fn generate_keypair(&self) -> (Vec<u8>, Vec<u8>);
+
+    fn derive_public_key(&self, secret_key: &[u8]) -> Result<Vec<u8>, String>;
+
+    fn validate_public_key(&self, public_key: &[u8]) -> Result<(), String>;
+
+    fn signature_len(&self) -> usize;
+
+    fn sign(&self, secret_key: &[u8], message: &[u8]) -> Result<Vec<u8>, String>;
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool;
+
+    fn encode_public_key(&self, public_key: &[u8]) -> String {
+        encode_key(self.algorithm(), public_key)
+    }
+
+    fn decode_public_key(&self, encoded: &str) -> Result<Vec<u8>, String> {
+        let public_key = decode_key(self.algorithm(), encoded)?;
+        self.validate_public_key(&public_key)?;
+        Ok(public_key)
+    }
+
+    fn encode_secret_key(&self, secret_key: &[u8]) -> String {
+        encode_key(self.algorithm(), secret_key)
+    }
+
+    fn decode_secret_key(&self, encoded: &str) -> Result<Vec<u8>, String> {
+        let secret_key = decode_key(self.algorithm(), encoded)?;
+        self.derive_public_key(&secret_key)?;
+        Ok(secret_key)
+    }
+}
+
+pub struct Ed25519;
+
+impl SignatureScheme for Ed25519 {
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::Ed25519
+    }
+
+    fn generate_keypair(&self) -> (Vec<u8>, Vec<u8>) {
+        ed25519::generate_keypair()
+    }
+
+    fn derive_public_key(&self, secret_key: &[u8]) -> Result<Vec<u8>, String> {
+        ed25519::public_key_from_secret(secret_key)
+    }
+
+    fn validate_public_key(&self, public_key: &[u8]) -> Result<(), String> {
+        ed25519::validate_public_key(public_key)
+    }
+
+    fn signature_len(&self) -> usize {
+        ed25519::SIGNATURE_BYTES
+    }
+
+    fn sign(&self, secret_key: &[u8], message: &[u8]) -> Result<Vec<u8>, String> {
+        ed25519::sign(secret_key, message)
+    }
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        ed25519::verify(public_key, message, signature)
+    }
+}
+
+pub struct Dilithium;
+
+impl SignatureScheme for Dilithium {
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::Dilithium
+    }
+
+    fn generate_keypair(&self) -> (Vec<u8>, Vec<u8>) {
+        dilithium::generate_keypair()
+    }
+
+    fn derive_public_key(&self, secret_key: &[u8]) -> Result<Vec<u8>, String> {
+        dilithium::public_key_from_secret(secret_key)
+    }
+
+    fn validate_public_key(&self, public_key: &[u8]) -> Result<(), String> {
+        dilithium::validate_public_key(public_key)
+    }
+
+    fn signature_len(&self) -> usize {
+        dilithium::SIGNATURE_BYTES
+    }
+
+    fn sign(&self, secret_key: &[u8], message: &[u8]) -> Result<Vec<u8>, String> {
+        dilithium::sign(secret_key, message)
+    }
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        dilithium::verify(public_key, message, signature)
+    }
+}
+
+pub fn scheme_for(algorithm: SignatureAlgorithm) -> &'static dyn SignatureScheme {
+    match algorithm {
+        SignatureAlgorithm::Ed25519 => &Ed25519,
+        SignatureAlgorithm::Dilithium => &Dilithium,
+    }
+}
+
+// Keys are exchanged as `<algorithm>:<hex bytes>` so they cannot be
+// mistaken for a key of another scheme.
+fn encode_key(algorithm: SignatureAlgorithm, key: &[u8]) -> String {
+    format!("{}:{}", algorithm.name(), hex::encode(key))
+}
+
+fn decode_key(algorithm: SignatureAlgorithm, encoded: &str) -> Result<Vec<u8>, String> {
+    let (name, key) = encoded
+        .split_once(':')
+        .ok_or_else(|| "Missing algorithm prefix".to_string())?;
+    if SignatureAlgorithm::from_name(name) != Some(algorithm) {
+        return Err(format!("Expected a {} key, got '{}'", algorithm, name));
+    }
+    hex::decode(key).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [SignatureAlgorithm; 2] =
+        [SignatureAlgorithm::Ed25519, SignatureAlgorithm::Dilithium];
+
+    #[test]
+    fn test_sign_and_verify_each_scheme() {
+        for algorithm in ALGORITHMS {
+            let scheme = scheme_for(algorithm);
+            assert_eq!(scheme.algorithm(), algorithm);
+
+            let (public_key, secret_key) = scheme.generate_keypair();
+            assert_eq!(scheme.derive_public_key(&secret_key).unwrap(), public_key);
+
+            let signature = scheme.sign(&secret_key, b"message").unwrap();
+            assert_eq!(signature.len(), scheme.signature_len());
+            assert!(scheme.verify(&public_key, b"message", &signature));
+            assert!(!scheme.verify(&public_key, b"other message", &signature));
+        }
+    }
+
+    #[test]
+    fn test_signatures_do_not_cross_schemes() {
+        let (ed_public, ed_secret) = Ed25519.generate_keypair();
+        let (pq_public, pq_secret) = Dilithium.generate_keypair();
+
+        let ed_signature = Ed25519.sign(&ed_secret, b"message").unwrap();
+        let pq_signature = Dilithium.sign(&pq_secret, b"message").unwrap();
+
+        assert!(!Dilithium.verify(&pq_public, b"message", &ed_signature));
+        assert!(!Ed25519.verify(&ed_public, b"message", &pq_signature));
+        assert!(!Dilithium.verify(&ed_public, b"message", &ed_signature));
+        assert!(Ed25519.sign(&pq_secret, b"message").is_err());
+    }
+
+    #[test]
+    fn test_key_encoding_roundtrip() {
+        for algorithm in ALGORITHMS {
+            let scheme = scheme_for(algorithm);
+            let (public_key, secret_key) = scheme.generate_keypair();
+
+            let encoded = scheme.encode_public_key(&public_key);
+            assert!(encoded.starts_with(algorithm.name()));
+            assert_eq!(scheme.decode_public_key(&encoded).unwrap(), public_key);
+
+            let encoded = scheme.encode_secret_key(&secret_key);
+            assert_eq!(scheme.decode_secret_key(&encoded).unwrap(), secret_key);
+        }
+    }
+
+    #[test]
+    fn test_key_decoding_rejects_other_scheme() {
+        let (public_key, _) = Ed25519.generate_keypair();
+        let encoded = Ed25519.encode_public_key(&public_key);
+
+        assert!(Dilithium.decode_public_key(&encoded).is_err());
+        assert!(Ed25519.decode_public_key(&hex::encode(&public_key)).is_err());
+        assert!(Ed25519.decode_public_key("ed25519:zz").is_err());
+    }
+}
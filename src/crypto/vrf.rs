@@ -0,0 +1,181 @@
+// ECVRF-EDWARDS25519-SHA512-TAI (RFC 9381) over Ed25519 keys: a proof that
+// an output was derived from a message with a secret key, which anyone can
+// check with the public key. Unlike a signature, each key and message have
+// exactly one valid output.
+use curve25519_dalek::constants::ED25519_BASEPOINT_POINT;
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use curve25519_dalek::scalar::Scalar;
+use sha2::{Digest, Sha512};
+
+pub const PROOF_BYTES: usize = 80;
+pub const OUTPUT_BYTES: usize = 64;
+
+const SUITE: u8 = 0x03;
+const CHALLENGE_BYTES: usize = 16;
+
+// Returns the proof for `message`; its output is `proof_to_hash(proof)`.
+pub fn prove(secret_key: &[u8], message: &[u8]) -> Result<Vec<u8>, String> {
+    if secret_key.len() != 32 {
+        return Err(format!("Expected a 32 byte secret key, got {}", secret_key.len()));
+    }
+    let expanded = Sha512::digest(secret_key);
+    let mut scalar_bytes = [0u8; 32];
+    scalar_bytes.copy_from_slice(&expanded[..32]);
+    scalar_bytes[0] &= 248;
+    scalar_bytes[31] &= 127;
+    scalar_bytes[31] |= 64;
+    let x = Scalar::from_bytes_mod_order(scalar_bytes);
+    let public_key = (x * ED25519_BASEPOINT_POINT).compress();
+
+    let h = encode_to_curve(public_key.as_bytes(), message)?;
+    let h_string = h.compress();
+    let gamma = x * h;
+    let k = scalar_from_hash(&[&expanded[32..], h_string.as_bytes()]);
+    let c = challenge(&[
+        public_key,
+        h_string,
+        gamma.compress(),
+        (k * ED25519_BASEPOINT_POINT).compress(),
+        (k * h).compress(),
+    ]);
+    let s = k + c * x;
+
+    let mut proof = Vec::with_capacity(PROOF_BYTES);
+    proof.extend_from_slice(gamma.compress().as_bytes());
+    proof.extend_from_slice(&c.as_bytes()[..CHALLENGE_BYTES]);
+    proof.extend_from_slice(s.as_bytes());
+    Ok(proof)
+}
+
+// Returns the output proven by `proof` if it is valid for `public_key` and
+// `message`.
+pub fn verify(public_key: &[u8], message: &[u8], proof: &[u8]) -> Option<Vec<u8>> {
+    if public_key.len() != 32 {
+        return None;
+    }
+    let y_string = CompressedEdwardsY::from_slice(public_key);
+    let y = y_string.decompress()?;
+    if y.is_small_order() {
+        return None;
+    }
+    let (gamma, c, s) = decode_proof(proof)?;
+
+    let h = encode_to_curve(y_string.as_bytes(), message).ok()?;
+    let u = EdwardsPoint::vartime_double_scalar_mul_basepoint(&-c, &y, &s);
+    let v = s * h - c * gamma;
+    let expected = challenge(&[y_string, h.compress(), gamma.compress(), u.compress(), v.compress()]);
+    if expected != c {
+        return None;
+    }
+    Some(gamma_to_hash(&gamma))
+}
+
+pub fn proof_to_hash(proof: &[u8]) -> Option<Vec<u8>> {
+    let (gamma, _, _) = decode_proof(proof)?;
+    Some(gamma_to_hash(&gamma))
+}
+
+fn decode_proof(proof: &[u8]) -> Option<(EdwardsPoint, Scalar, Scalar)> {
+    if proof.len() != PROOF_BYTES {
+        return None;
+    }
+    let gamma = CompressedEdwardsY::from_slice(&proof[..32]).decompress()?;
+    let mut c_bytes = [0u8; 32];
+    c_bytes[..CHALLENGE_BYTES].copy_from_slice(&proof[32..48]);
+    let mut s_bytes = [0u8; 32];
+    s_bytes.copy_from_slice(&proof[48..]);
+    Some((gamma, Scalar::from_bytes_mod_order(c_bytes), Scalar::from_canonical_bytes(s_bytes)?))
+}
+
+// Try-and-increment: hash with a counter until the result decodes as a
+// point, then clear the cofactor.
+fn encode_to_curve(public_key: &[u8], message: &[u8]) -> Result<EdwardsPoint, String> {
+    for counter in 0..=u8::MAX {
+        let mut hasher = Sha512::new();
+        hasher.update([SUITE, 0x01]);
+        hasher.update(public_key);
+        hasher.update(message);
+        hasher.update([counter, 0x00]);
+        let hash = hasher.finalize();
+        if let Some(point) = CompressedEdwardsY::from_slice(&hash[..32]).decompress() {
+            return Ok(point.mul_by_cofactor());
+        }
+    }
+    Err("No counter maps the message to a curve point".to_string())
+}
+
+fn challenge(points: &[CompressedEdwardsY]) -> Scalar {
+    let mut hasher = Sha512::new();
+    hasher.update([SUITE, 0x02]);
+    for point in points {
+        hasher.update(point.as_bytes());
+    }
+    hasher.update([0x00]);
+    let hash = hasher.finalize();
+    let mut bytes = [0u8; 32];
+    bytes[..CHALLENGE_BYTES].copy_from_slice(&hash[..CHALLENGE_BYTES]);
+    Scalar::from_bytes_mod_order(bytes)
+}
+
+fn scalar_from_hash(parts: &[&[u8]]) -> Scalar {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut wide = [0u8; 64];
+    wide.copy_from_slice(&hasher.finalize());
+    Scalar::from_bytes_mod_order_wide(&wide)
+}
+
+fn gamma_to_hash(gamma: &EdwardsPoint) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    hasher.update([SUITE, 0x03]);
+    hasher.update(gamma.mul_by_cofactor().compress().as_bytes());
+    hasher.update([0x00]);
+    hasher.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ed25519;
+
+    // RFC 9381, appendix B.3, example 16.
+    const SECRET_KEY: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+    const PUBLIC_KEY: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+    const PROOF: &str = "8657106690b5526245a92b003bb079ccd1a92130477671f6fc01ad16f26f723f\
+                         26f8a57ccaed74ee1b190bed1f479d9727d2d0f9b005a6e456a35d4fb0daab12\
+                         68a1b0db10836d9826a528ca76567805";
+    const OUTPUT: &str = "90cf1df3b703cce59e2a35b925d411164068269d7b2d29f3301c03dd757876ff\
+                          66b71dda49d2de59d03450451af026798e8f81cd2e333de5cdf4f3e140fdd8ae";
+
+    #[test]
+    fn test_rfc_vector() {
+        let secret_key = hex::decode(SECRET_KEY).unwrap();
+        let public_key = hex::decode(PUBLIC_KEY).unwrap();
+        let proof = prove(&secret_key, b"").unwrap();
+
+        assert_eq!(hex::encode(&proof), PROOF);
+        assert_eq!(proof_to_hash(&proof).map(hex::encode), Some(OUTPUT.to_string()));
+        assert_eq!(verify(&public_key, b"", &proof).map(hex::encode), Some(OUTPUT.to_string()));
+    }
+
+    #[test]
+    fn test_proof_bound_to_key_and_message() {
+        let (public_key, secret_key) = ed25519::generate_keypair();
+        let (other_public_key, _) = ed25519::generate_keypair();
+        let proof = prove(&secret_key, b"slot 1").unwrap();
+        assert_eq!(proof.len(), PROOF_BYTES);
+        assert_eq!(prove(&secret_key, b"slot 1").unwrap(), proof);
+
+        let output = verify(&public_key, b"slot 1", &proof).unwrap();
+        assert_eq!(output.len(), OUTPUT_BYTES);
+        assert!(verify(&public_key, b"slot 2", &proof).is_none());
+        assert!(verify(&other_public_key, b"slot 1", &proof).is_none());
+
+        let mut tampered = proof.clone();
+        tampered[PROOF_BYTES - 1] ^= 0x01;
+        assert!(verify(&public_key, b"slot 1", &tampered).is_none());
+        assert!(verify(&public_key, b"slot 1", &proof[..PROOF_BYTES - 1]).is_none());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod policy;
+pub mod state;
+
+pub use policy::MonetaryPolicy;
+pub use state::{Account, BlockUndo, Ledger, LedgerError, Stake};
@@ -0,0 +1,78 @@
+use serde::{Serialize, Deserialize};
+
+// How coins come into existence: `genesis_supply` is allocated before the
+// first block and each block then mints a reward for its producer. The
+// default mints nothing after genesis.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MonetaryPolicy {
+    pub genesis_supply: u64,
+    pub block_reward: u64,
+    // Blocks between reward reductions; zero keeps the reward constant.
+    pub reduction_interval: u64,
+    // Percent the reward drops by at each reduction: 50 halves it, smaller
+    // values decay it gradually.
+    pub reduction_percent: u64,
+    // Rewards stop once the supply reaches this.
+    pub max_supply: u64,
+}
+
+impl MonetaryPolicy {
+    // Reward the schedule sets for the block at `height`, before the cap.
+    pub fn scheduled_reward(&self, height: u64) -> u64 {
+        if self.reduction_interval == 0 || self.reduction_percent == 0 {
+            return self.block_reward;
+        }
+        let kept = 100 - self.reduction_percent.min(100);
+        let mut reward = self.block_reward;
+        for _ in 0..height / self.reduction_interval {
+            if reward == 0 {
+                break;
+            }
+            reward = percent_of(reward, kept);
+        }
+        reward
+    }
+
+    // What the block at `height` mints on top of `supply`.
+    pub fn reward(&self, height: u64, supply: u64) -> u64 {
+        self.scheduled_reward(height).min(self.max_supply.saturating_sub(supply))
+    }
+}
+
+pub(crate) fn percent_of(amount: u64, percent: u64) -> u64 {
+    (amount as u128 * percent as u128 / 100) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reward_schedule() {
+        let halving = MonetaryPolicy {
+            genesis_supply: 0,
+            block_reward: 50,
+            reduction_interval: 10,
+            reduction_percent: 50,
+            max_supply: 1_000,
+        };
+        assert_eq!(halving.scheduled_reward(9), 50);
+        assert_eq!(halving.scheduled_reward(10), 25);
+        assert_eq!(halving.scheduled_reward(35), 6);
+        assert_eq!(halving.scheduled_reward(u64::MAX), 0);
+        assert_eq!(halving.reward(0, 980), 20);
+        assert_eq!(halving.reward(0, 2_000), 0);
+
+        let decaying = MonetaryPolicy {
+            reduction_percent: 10,
+            ..halving.clone()
+        };
+        assert_eq!(decaying.scheduled_reward(20), 40);
+        let constant = MonetaryPolicy {
+            reduction_interval: 0,
+            ..halving
+        };
+        assert_eq!(constant.scheduled_reward(1_000), 50);
+        assert_eq!(MonetaryPolicy::default().reward(0, 0), 0);
+    }
+}
@@ -0,0 +1,1191 @@
+use std::collections::{BTreeMap, HashMap};
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use crate::blockchain::Block;
+use crate::consensus::{DoubleSignEvidence, EvidenceError, ProofOfStake, ValidatorSet};
+use crate::ledger::policy::{percent_of, MonetaryPolicy};
+use crate::network::message::{TransactionData, TransactionKind};
+
+// Epochs an unbonded amount stays locked before it can be withdrawn. Double
+// signing can be reported for as long, so stake can't escape its slashing.
+pub const UNBONDING_EPOCHS: u64 = 2;
+// Share of an equivocating validator's stake, and of the stake delegated
+// to it, that is slashed, and share of the slashed amount paid to whoever
+// reported it. The rest is burned.
+pub const SLASH_PERCENT: u64 = 10;
+pub const REPORTER_REWARD_PERCENT: u64 = 50;
+// Epochs a slashed validator is kept out of the active set.
+pub const JAIL_EPOCHS: u64 = 4;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum LedgerError {
+    #[error("Insufficient funds in {address}: balance {balance}, required {required}")]
+    InsufficientFunds {
+        address: String,
+        balance: u64,
+        required: u64,
+    },
+
+    #[error("Nonce {actual} for {address} was already used, next is {expected}")]
+    Replayed {
+        address: String,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error("Nonce {actual} for {address} is ahead of the expected {expected}")]
+    NonceGap {
+        address: String,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error("Balance overflow for {0}")]
+    Overflow(String),
+
+    #[error("{address} has {bonded} bonded, cannot unbond {required}")]
+    InsufficientStake {
+        address: String,
+        bonded: u64,
+        required: u64,
+    },
+
+    #[error("{address} can withdraw {available}, requested {required}")]
+    NotWithdrawable {
+        address: String,
+        available: u64,
+        required: u64,
+    },
+
+    #[error("Transaction carries no evidence against {0}")]
+    NoEvidence(String),
+
+    #[error("Invalid evidence: {0}")]
+    InvalidEvidence(#[from] EvidenceError),
+
+    #[error("Evidence for slot {0} is too old")]
+    EvidenceExpired(u64),
+
+    #[error("{0} is already jailed")]
+    AlreadyJailed(String),
+
+    #[error("{0} has no stake to slash")]
+    NothingToSlash(String),
+
+    #[error("{0} cannot report its own double signing")]
+    SelfReport(String),
+
+    #[error("Block reward should be {expected}, got {actual}")]
+    WrongReward { expected: u64, actual: u64 },
+
+    #[error("{0} is not a validator that can be delegated to")]
+    NotValidator(String),
+
+    #[error("{delegator} has {delegated} delegated to {validator}, cannot undelegate {required}")]
+    InsufficientDelegation {
+        delegator: String,
+        validator: String,
+        delegated: u64,
+        required: u64,
+    },
+
+    #[error("Commission of {0}% is above 100%")]
+    InvalidCommission(u64),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Account {
+    pub balance: u64,
+    pub nonce: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stake {
+    pub bonded: u64,
+    // Unbonded amounts with the epoch each can be withdrawn from, oldest
+    // first.
+    pub unbonding: Vec<(u64, u64)>,
+    // Stake others delegated to this validator, and stake they undelegated
+    // but can't withdraw yet, by delegator. Both are slashed along with the
+    // validator's own.
+    pub delegations: BTreeMap<String, u64>,
+    pub undelegating: BTreeMap<String, Vec<(u64, u64)>>,
+    // Percent of its block earnings the validator keeps before sharing the
+    // rest with its delegators.
+    pub commission: u64,
+}
+
+impl Stake {
+    pub fn withdrawable(&self, epoch: u64) -> u64 {
+        released(&self.unbonding, epoch)
+    }
+
+    // What `delegator` can withdraw from this validator in `epoch`.
+    pub fn withdrawable_by(&self, delegator: &str, epoch: u64) -> u64 {
+        self.undelegating.get(delegator).map_or(0, |unbonding| released(unbonding, epoch))
+    }
+
+    pub fn delegated(&self) -> u64 {
+        self.delegations.values().fold(0, |total, amount| total.saturating_add(*amount))
+    }
+
+    // Stake the validator is elected with: its own plus its delegators'.
+    pub fn power(&self) -> u64 {
+        self.bonded.saturating_add(self.delegated())
+    }
+
+    // Everything that would be slashed with the validator.
+    pub fn total(&self) -> u64 {
+        self.unbonding
+            .iter()
+            .chain(self.undelegating.values().flatten())
+            .fold(self.power(), |total, (amount, _)| total.saturating_add(*amount))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bonded == 0
+            && self.unbonding.is_empty()
+            && self.delegations.is_empty()
+            && self.undelegating.is_empty()
+            && self.commission == 0
+    }
+}
+
+fn released(unbonding: &[(u64, u64)], epoch: u64) -> u64 {
+    unbonding
+        .iter()
+        .filter(|(_, release)| *release <= epoch)
+        .fold(0u64, |total, (amount, _)| total.saturating_add(*amount))
+}
+
+// Takes `amount` out of the entries released by `epoch`, oldest first, and
+// drops the emptied ones.
+fn take_released(unbonding: &mut Vec<(u64, u64)>, epoch: u64, amount: u64) {
+    let mut remaining = amount;
+    for (unbonded, release) in unbonding.iter_mut() {
+        if *release > epoch || remaining == 0 {
+            continue;
+        }
+        let taken = remaining.min(*unbonded);
+        *unbonded -= taken;
+        remaining -= taken;
+    }
+    unbonding.retain(|(unbonded, _)| *unbonded > 0);
+}
+
+// Account, stake and jail states a block overwrote, enough to take the
+// block back out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlockUndo {
+    previous: Vec<(String, Option<Account>)>,
+    previous_stakes: Vec<(String, Option<Stake>)>,
+    previous_jails: Vec<(String, Option<u64>)>,
+    supply: u64,
+    epoch: u64,
+    // Set before the block rotated or slashed it, if it did.
+    validator_set: Option<ValidatorSet>,
+    epoch_stakes: Option<HashMap<String, Stake>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    accounts: HashMap<String, Account>,
+    stakes: HashMap<String, Stake>,
+    // Epoch each slashed validator is jailed until.
+    jails: HashMap<String, u64>,
+    policy: MonetaryPolicy,
+    // Every coin in existence, liquid or staked.
+    supply: u64,
+    // Epoch of the last applied block.
+    epoch: u64,
+    validator_set: ValidatorSet,
+    // Validators' stakes as of the epoch's start, which block earnings are
+    // shared out by, so stake delegated mid-epoch earns from the next one.
+    epoch_stakes: HashMap<String, Stake>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_genesis(allocations: &[(String, u64)]) -> Self {
+        let mut ledger = Ledger::new();
+        for (address, amount) in allocations {
+            ledger.accounts.entry(address.clone()).or_default().balance += amount;
+            ledger.supply += amount;
+        }
+        ledger
+    }
+
+    pub fn with_policy(mut self, policy: MonetaryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    // Stake bonded at genesis, without spending a balance. Bonds large
+    // enough are active from the first block.
+    pub fn with_bonds(mut self, bonds: &[(String, u64)]) -> Self {
+        for (address, amount) in bonds {
+            self.stakes.entry(address.clone()).or_default().bonded += amount;
+            self.supply += amount;
+        }
+        self.validator_set = ValidatorSet::genesis(&self.voting_power());
+        self.epoch_stakes = self.validator_stakes();
+        self
+    }
+
+    pub fn account(&self, address: &str) -> Account {
+        self.accounts.get(address).cloned().unwrap_or_default()
+    }
+
+    pub fn balance(&self, address: &str) -> u64 {
+        self.account(address).balance
+    }
+
+    pub fn nonce(&self, address: &str) -> u64 {
+        self.account(address).nonce
+    }
+
+    pub fn stake(&self, address: &str) -> Stake {
+        self.stakes.get(address).cloned().unwrap_or_default()
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn supply(&self) -> u64 {
+        self.supply
+    }
+
+    pub fn policy(&self) -> &MonetaryPolicy {
+        &self.policy
+    }
+
+    // What the block at `height` built on this state must mint.
+    pub fn block_reward(&self, height: u64) -> u64 {
+        self.policy.reward(height, self.supply)
+    }
+
+    pub fn is_jailed(&self, address: &str) -> bool {
+        self.jails.get(address).is_some_and(|until| *until > self.epoch)
+    }
+
+    pub fn validator_set(&self) -> &ValidatorSet {
+        &self.validator_set
+    }
+
+    // Validators a block in `slot` built on this state is elected from.
+    pub fn validator_set_for(&self, slot: u64) -> ValidatorSet {
+        let epoch = ProofOfStake::epochOf(slot);
+        if epoch > self.validator_set.epoch() {
+            let mut electable = self.voting_power();
+            electable.retain(|(address, _)| self.jails.get(address).is_none_or(|until| *until <= epoch));
+            self.validator_set.rotate(epoch, &electable)
+        } else {
+            self.validator_set.clone()
+        }
+    }
+
+    // Addresses with bonded stake, in address order.
+    pub fn bonded(&self) -> Vec<(String, u64)> {
+        let mut validators: Vec<(String, u64)> = self
+            .stakes
+            .iter()
+            .filter(|(_, stake)| stake.bonded > 0)
+            .map(|(address, stake)| (address.clone(), stake.bonded))
+            .collect();
+        validators.sort();
+        validators
+    }
+
+    fn validator_stakes(&self) -> HashMap<String, Stake> {
+        self.stakes
+            .iter()
+            .filter(|(_, stake)| stake.bonded > 0)
+            .map(|(address, stake)| (address.clone(), stake.clone()))
+            .collect()
+    }
+
+    // Addresses with bonded stake and what they are elected with, counting
+    // the stake delegated to them, in address order.
+    pub fn voting_power(&self) -> Vec<(String, u64)> {
+        let mut validators: Vec<(String, u64)> = self
+            .stakes
+            .iter()
+            .filter(|(_, stake)| stake.bonded > 0)
+            .map(|(address, stake)| (address.clone(), stake.power()))
+            .collect();
+        validators.sort();
+        validators
+    }
+
+    // A copy with only the accounts, stakes and jails of the senders and
+    // receivers of `transactions`, enough to check and apply them in turn
+    // without cloning the whole state.
+    pub fn subset_for<'a>(&self, transactions: impl IntoIterator<Item = &'a TransactionData>) -> Ledger {
+        let mut subset = Ledger {
+            policy: self.policy.clone(),
+            supply: self.supply,
+            epoch: self.epoch,
+            validator_set: self.validator_set.clone(),
+            ..Ledger::default()
+        };
+        for tx in transactions {
+            for address in [&tx.sender, &tx.receiver] {
+                if let Some(account) = self.accounts.get(address) {
+                    subset.accounts.insert(address.clone(), account.clone());
+                }
+                if let Some(stake) = self.stakes.get(address) {
+                    subset.stakes.insert(address.clone(), stake.clone());
+                }
+                if let Some(until) = self.jails.get(address) {
+                    subset.jails.insert(address.clone(), *until);
+                }
+            }
+        }
+        subset
+    }
+
+    pub fn check_transaction(&self, tx: &TransactionData) -> Result<(), LedgerError> {
+        let account = self.account(&tx.sender);
+        if tx.nonce < account.nonce {
+            return Err(LedgerError::Replayed {
+                address: tx.sender.clone(),
+                expected: account.nonce,
+                actual: tx.nonce,
+            });
+        }
+        if tx.nonce > account.nonce {
+            return Err(LedgerError::NonceGap {
+                address: tx.sender.clone(),
+                expected: account.nonce,
+                actual: tx.nonce,
+            });
+        }
+
+        let required = tx
+            .balanceCost()
+            .ok_or_else(|| LedgerError::Overflow(tx.sender.clone()))?;
+        if account.balance < required {
+            return Err(LedgerError::InsufficientFunds {
+                address: tx.sender.clone(),
+                balance: account.balance,
+                required,
+            });
+        }
+
+        let stake = self.stake(&tx.sender);
+        match tx.kind {
+            TransactionKind::Transfer => {}
+            TransactionKind::Bond => {
+                stake
+                    .bonded
+                    .checked_add(tx.amount)
+                    .ok_or_else(|| LedgerError::Overflow(tx.sender.clone()))?;
+            }
+            TransactionKind::Unbond => {
+                if stake.bonded < tx.amount {
+                    return Err(LedgerError::InsufficientStake {
+                        address: tx.sender.clone(),
+                        bonded: stake.bonded,
+                        required: tx.amount,
+                    });
+                }
+            }
+            TransactionKind::Withdraw => {
+                // Withdrawing from another address collects what was
+                // undelegated from it. Stake a validator is still elected
+                // with stays locked until it leaves the active set.
+                let validator = self.stake(&tx.receiver);
+                let still_elected = self.validator_set.stakeOf(&tx.receiver) > validator.power();
+                let available = match (still_elected, tx.receiver == tx.sender) {
+                    (true, _) => 0,
+                    (false, true) => stake.withdrawable(self.epoch),
+                    (false, false) => validator.withdrawable_by(&tx.sender, self.epoch),
+                };
+                if available < tx.amount {
+                    return Err(LedgerError::NotWithdrawable {
+                        address: tx.sender.clone(),
+                        available,
+                        required: tx.amount,
+                    });
+                }
+            }
+            TransactionKind::Evidence => {
+                // Or the offender would get the reporter's share of its
+                // own slash back.
+                if tx.sender == tx.receiver {
+                    return Err(LedgerError::SelfReport(tx.sender.clone()));
+                }
+                let evidence = tx
+                    .evidence
+                    .as_ref()
+                    .filter(|evidence| evidence.offender() == tx.receiver)
+                    .ok_or_else(|| LedgerError::NoEvidence(tx.receiver.clone()))?;
+                self.check_evidence(evidence)?
+            }
+            TransactionKind::Delegate => {
+                let validator = self.stake(&tx.receiver);
+                if tx.receiver == tx.sender || validator.bonded == 0 || self.is_jailed(&tx.receiver) {
+                    return Err(LedgerError::NotValidator(tx.receiver.clone()));
+                }
+                validator
+                    .total()
+                    .checked_add(tx.amount)
+                    .ok_or_else(|| LedgerError::Overflow(tx.receiver.clone()))?;
+            }
+            TransactionKind::Undelegate => {
+                let delegated = self
+                    .stake(&tx.receiver)
+                    .delegations
+                    .get(&tx.sender)
+                    .copied()
+                    .unwrap_or(0);
+                if delegated < tx.amount {
+                    return Err(LedgerError::InsufficientDelegation {
+                        delegator: tx.sender.clone(),
+                        validator: tx.receiver.clone(),
+                        delegated,
+                        required: tx.amount,
+                    });
+                }
+            }
+            TransactionKind::Commission => {
+                if tx.amount > 100 {
+                    return Err(LedgerError::InvalidCommission(tx.amount));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Whether `evidence` would still get its offender slashed.
+    pub fn check_evidence(&self, evidence: &DoubleSignEvidence) -> Result<(), LedgerError> {
+        evidence.verify()?;
+        let offender = evidence.offender();
+        if ProofOfStake::epochOf(evidence.slot()) + UNBONDING_EPOCHS <= self.epoch {
+            return Err(LedgerError::EvidenceExpired(evidence.slot()));
+        }
+        if self.is_jailed(offender) {
+            return Err(LedgerError::AlreadyJailed(offender.to_string()));
+        }
+        if self.stake(offender).total() == 0 {
+            return Err(LedgerError::NothingToSlash(offender.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn apply_transaction(&mut self, tx: &TransactionData) -> Result<(), LedgerError> {
+        self.check_transaction(tx)?;
+
+        let sender = self.accounts.entry(tx.sender.clone()).or_default();
+        sender.balance -= tx.balanceCost().unwrap_or_default();
+        sender.nonce += 1;
+
+        match tx.kind {
+            TransactionKind::Transfer => self.credit(&tx.receiver, tx.amount),
+            TransactionKind::Evidence => self.slash(&tx.receiver, &tx.sender),
+            _ => self.apply_staking(tx),
+        }
+    }
+
+    // Slashes SLASH_PERCENT of the offender's bonded and unbonding stake and
+    // of every delegation to it, pays the reporter its share, and drops the
+    // offender from the active set until its jail time is over.
+    fn slash(&mut self, offender: &str, reporter: &str) -> Result<(), LedgerError> {
+        let cut = |amount: &mut u64| {
+            let slashed = percent_of(*amount, SLASH_PERCENT);
+            *amount -= slashed;
+            slashed
+        };
+        let stake = self.stakes.entry(offender.to_string()).or_default();
+        let mut slashed = cut(&mut stake.bonded);
+        for (amount, _) in stake.unbonding.iter_mut().chain(stake.undelegating.values_mut().flatten()) {
+            slashed = slashed.saturating_add(cut(amount));
+        }
+        for amount in stake.delegations.values_mut() {
+            slashed = slashed.saturating_add(cut(amount));
+        }
+        stake.unbonding.retain(|(amount, _)| *amount > 0);
+        for unbonding in stake.undelegating.values_mut() {
+            unbonding.retain(|(amount, _)| *amount > 0);
+        }
+        stake.undelegating.retain(|_, unbonding| !unbonding.is_empty());
+        stake.delegations.retain(|_, amount| *amount > 0);
+        if stake.is_empty() {
+            self.stakes.remove(offender);
+        }
+
+        self.jails.insert(offender.to_string(), self.epoch + JAIL_EPOCHS);
+        self.validator_set.remove(offender);
+        let reward = percent_of(slashed, REPORTER_REWARD_PERCENT);
+        self.supply -= slashed - reward;
+        self.credit(reporter, reward)
+    }
+
+    // Own stake changes land on the sender's entry, delegations and
+    // withdrawals from a validator on the receiver's.
+    fn apply_staking(&mut self, tx: &TransactionData) -> Result<(), LedgerError> {
+        let release = self.epoch + UNBONDING_EPOCHS;
+        let stake = self.stakes.entry(tx.receiver.clone()).or_default();
+        match tx.kind {
+            TransactionKind::Transfer | TransactionKind::Evidence => {}
+            TransactionKind::Bond => stake.bonded += tx.amount,
+            TransactionKind::Unbond => {
+                stake.bonded -= tx.amount;
+                stake.unbonding.push((tx.amount, release));
+            }
+            TransactionKind::Commission => stake.commission = tx.amount,
+            TransactionKind::Delegate => *stake.delegations.entry(tx.sender.clone()).or_default() += tx.amount,
+            TransactionKind::Undelegate => {
+                let delegated = stake.delegations.entry(tx.sender.clone()).or_default();
+                *delegated -= tx.amount;
+                if *delegated == 0 {
+                    stake.delegations.remove(&tx.sender);
+                }
+                stake.undelegating.entry(tx.sender.clone()).or_default().push((tx.amount, release));
+            }
+            TransactionKind::Withdraw if tx.receiver == tx.sender => {
+                take_released(&mut stake.unbonding, self.epoch, tx.amount)
+            }
+            TransactionKind::Withdraw => {
+                let unbonding = stake.undelegating.entry(tx.sender.clone()).or_default();
+                take_released(unbonding, self.epoch, tx.amount);
+                if unbonding.is_empty() {
+                    stake.undelegating.remove(&tx.sender);
+                }
+            }
+        }
+        if stake.is_empty() {
+            self.stakes.remove(&tx.receiver);
+        }
+        if tx.kind == TransactionKind::Withdraw {
+            self.credit(&tx.sender, tx.amount)?;
+        }
+        Ok(())
+    }
+
+    // The validator keeps its commission on `earned` and shares the rest
+    // with its delegators by stake, keeping its own share and the rounding.
+    // Stakes and commission are taken as of the epoch's start; delegators
+    // who undelegated since are still paid, their stake being slashable
+    // until it is released.
+    fn distribute(&mut self, validator: &str, earned: u64) -> Result<(), LedgerError> {
+        let stake = self.epoch_stakes.get(validator).cloned().unwrap_or_default();
+        let power = stake.power();
+        let shared = earned - percent_of(earned, stake.commission);
+        let mut kept = earned;
+        for (delegator, delegated) in &stake.delegations {
+            let share = (shared as u128 * *delegated as u128 / power as u128) as u64;
+            if share > 0 {
+                self.credit(delegator, share)?;
+                kept -= share;
+            }
+        }
+        self.credit(validator, kept)
+    }
+
+    fn credit(&mut self, address: &str, amount: u64) -> Result<(), LedgerError> {
+        let account = self.accounts.entry(address.to_string()).or_default();
+        account.balance = account
+            .balance
+            .checked_add(amount)
+            .ok_or_else(|| LedgerError::Overflow(address.to_string()))?;
+        Ok(())
+    }
+
+    // A copy as a block for `slot` finds it before its transactions apply:
+    // in the slot's epoch and, if that epoch is new, with its validators.
+    // The set is snapshotted from the stake bonded before the epoch's first
+    // block.
+    pub fn entering_slot(&self, slot: u64) -> Ledger {
+        let mut staged = self.clone();
+        staged.epoch = ProofOfStake::epochOf(slot);
+        if staged.epoch > self.validator_set.epoch() {
+            staged.validator_set = self.validator_set_for(slot);
+            staged.epoch_stakes = self.validator_stakes();
+        }
+        staged
+    }
+
+    // Either every transaction in the block is applied and its fees and
+    // reward are credited to the producer, or the ledger is left untouched. The returned
+    // undo record lets `revert_block` take the block back out.
+    pub fn apply_block(&mut self, block: &Block) -> Result<BlockUndo, LedgerError> {
+        let expected = self.block_reward(block.height());
+        if block.blockHeader.reward != expected {
+            return Err(LedgerError::WrongReward { expected, actual: block.blockHeader.reward });
+        }
+        let new_epoch = ProofOfStake::epochOf(block.slot()) > self.validator_set.epoch();
+        let mut staged = self.entering_slot(block.slot());
+        let mut fees: u64 = 0;
+        for tx in &block.transactions {
+            staged.apply_transaction(tx)?;
+            fees = fees
+                .checked_add(tx.fee)
+                .ok_or_else(|| LedgerError::Overflow(block.producer().to_string()))?;
+        }
+        let earned = fees
+            .checked_add(expected)
+            .ok_or_else(|| LedgerError::Overflow(block.producer().to_string()))?;
+        if earned > 0 {
+            staged.distribute(block.producer(), earned)?;
+        }
+        staged.supply += expected;
+
+        // The producer's delegators were paid their shares.
+        let delegators = staged
+            .epoch_stakes
+            .get(block.producer())
+            .into_iter()
+            .flat_map(|stake| stake.delegations.keys().map(String::as_str));
+        let mut touched: Vec<&str> = block
+            .transactions
+            .iter()
+            .flat_map(|tx| [tx.sender.as_str(), tx.receiver.as_str()])
+            .chain(std::iter::once(block.producer()))
+            .chain(delegators)
+            .collect();
+        touched.sort_unstable();
+        touched.dedup();
+        let undo = BlockUndo {
+            previous: touched
+                .iter()
+                .map(|address| (address.to_string(), self.accounts.get(*address).cloned()))
+                .collect(),
+            previous_stakes: touched
+                .iter()
+                .map(|address| (address.to_string(), self.stakes.get(*address).cloned()))
+                .collect(),
+            previous_jails: touched
+                .iter()
+                .map(|address| (address.to_string(), self.jails.get(*address).copied()))
+                .collect(),
+            supply: self.supply,
+            epoch: self.epoch,
+            validator_set: (staged.validator_set != self.validator_set).then(|| self.validator_set.clone()),
+            epoch_stakes: new_epoch.then(|| self.epoch_stakes.clone()),
+        };
+        *self = staged;
+        Ok(undo)
+    }
+
+    // Blocks must be reverted newest first.
+    pub fn revert_block(&mut self, undo: &BlockUndo) {
+        for (address, previous) in &undo.previous {
+            match previous {
+                Some(account) => self.accounts.insert(address.clone(), account.clone()),
+                None => self.accounts.remove(address),
+            };
+        }
+        for (address, previous) in &undo.previous_stakes {
+            match previous {
+                Some(stake) => self.stakes.insert(address.clone(), stake.clone()),
+                None => self.stakes.remove(address),
+            };
+        }
+        for (address, previous) in &undo.previous_jails {
+            match previous {
+                Some(until) => self.jails.insert(address.clone(), *until),
+                None => self.jails.remove(address),
+            };
+        }
+        self.supply = undo.supply;
+        self.epoch = undo.epoch;
+        if let Some(validator_set) = &undo.validator_set {
+            self.validator_set = validator_set.clone();
+        }
+        if let Some(epoch_stakes) = &undo.epoch_stakes {
+            self.epoch_stakes = epoch_stakes.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SignatureAlgorithm;
+    use crate::consensus::pos::SLOTS_PER_EPOCH;
+    use crate::consensus::{DoubleSignEvidence, SignedHeader};
+    use crate::wallet::{KeyPair, Wallet};
+    use crate::network::message::DEFAULT_CHAIN_ID;
+
+    fn transfer(sender: &str, receiver: &str, amount: u64, nonce: u64) -> TransactionData {
+        TransactionData {
+            kind: TransactionKind::Transfer,
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            amount,
+            fee: 0,
+            nonce,
+            chainId: DEFAULT_CHAIN_ID,
+            algorithm: SignatureAlgorithm::Ed25519,
+            publicKey: Vec::new(),
+            signature: vec![amount as u8],
+            evidence: None,
+        }
+    }
+
+    fn staking(kind: TransactionKind, sender: &str, amount: u64, nonce: u64) -> TransactionData {
+        TransactionData {
+            kind,
+            ..transfer(sender, sender, amount, nonce)
+        }
+    }
+
+    fn block(transactions: Vec<TransactionData>) -> Block {
+        Block::new(0, String::new(), "producer".to_string(), transactions)
+    }
+
+    fn double_sign(keypair: &KeyPair, slot: u64) -> DoubleSignEvidence {
+        let signed = |prev_hash: &str| {
+            let producer = Wallet::generate_address(&keypair.public_key);
+            let mut block = Block::new(1, prev_hash.to_string(), producer, Vec::new());
+            block.blockHeader.slot = slot;
+            block.sign(keypair);
+            SignedHeader::of(&block)
+        };
+        DoubleSignEvidence::new(signed("a"), signed("b"))
+    }
+
+    fn report(reporter: &str, evidence: &DoubleSignEvidence, nonce: u64) -> TransactionData {
+        TransactionData {
+            kind: TransactionKind::Evidence,
+            evidence: Some(Box::new(evidence.clone())),
+            ..transfer(reporter, evidence.offender(), 0, nonce)
+        }
+    }
+
+    fn block_in_epoch(epoch: u64, transactions: Vec<TransactionData>) -> Block {
+        let mut block = block(transactions);
+        block.blockHeader.slot = epoch * SLOTS_PER_EPOCH;
+        block
+    }
+
+    #[test]
+    fn test_genesis_allocations() {
+        let ledger = Ledger::from_genesis(&[("alice".to_string(), 100), ("bob".to_string(), 20)]);
+        assert_eq!(ledger.balance("alice"), 100);
+        assert_eq!(ledger.balance("bob"), 20);
+        assert_eq!(ledger.balance("carol"), 0);
+        assert_eq!(ledger.nonce("alice"), 0);
+    }
+
+    #[test]
+    fn test_apply_block_moves_funds_and_bumps_nonce() {
+        let mut ledger = Ledger::from_genesis(&[("alice".to_string(), 100)]);
+        ledger
+            .apply_block(&block(vec![transfer("alice", "bob", 30, 0), transfer("bob", "carol", 10, 0)]))
+            .unwrap();
+
+        assert_eq!(ledger.account("alice"), Account { balance: 70, nonce: 1 });
+        assert_eq!(ledger.account("bob"), Account { balance: 20, nonce: 1 });
+        assert_eq!(ledger.account("carol"), Account { balance: 10, nonce: 0 });
+    }
+
+    #[test]
+    fn test_overspending_block_is_rejected_atomically() {
+        let mut ledger = Ledger::from_genesis(&[("alice".to_string(), 100)]);
+        let result = ledger.apply_block(&block(vec![
+            transfer("alice", "bob", 60, 0),
+            transfer("alice", "carol", 50, 1),
+        ]));
+
+        assert_eq!(
+            result,
+            Err(LedgerError::InsufficientFunds {
+                address: "alice".to_string(),
+                balance: 40,
+                required: 50,
+            })
+        );
+        assert_eq!(ledger.account("alice"), Account { balance: 100, nonce: 0 });
+        assert_eq!(ledger.balance("bob"), 0);
+    }
+
+    #[test]
+    fn test_replayed_transaction_is_rejected() {
+        let mut ledger = Ledger::from_genesis(&[("alice".to_string(), 100)]);
+        let tx = transfer("alice", "bob", 10, 0);
+        ledger.apply_block(&block(vec![tx.clone()])).unwrap();
+
+        assert_eq!(
+            ledger.apply_block(&block(vec![tx])),
+            Err(LedgerError::Replayed {
+                address: "alice".to_string(),
+                expected: 1,
+                actual: 0,
+            })
+        );
+
+        let duplicated = transfer("alice", "bob", 20, 1);
+        assert!(matches!(
+            ledger.apply_block(&block(vec![duplicated.clone(), duplicated])),
+            Err(LedgerError::Replayed { .. })
+        ));
+        assert_eq!(ledger.account("alice"), Account { balance: 90, nonce: 1 });
+    }
+
+    #[test]
+    fn test_out_of_order_nonce_is_rejected() {
+        let mut ledger = Ledger::from_genesis(&[("alice".to_string(), 100)]);
+        let first = transfer("alice", "bob", 10, 0);
+        let second = transfer("alice", "bob", 10, 1);
+
+        assert!(matches!(
+            ledger.apply_block(&block(vec![second.clone(), first.clone()])),
+            Err(LedgerError::NonceGap { expected: 0, actual: 1, .. })
+        ));
+        ledger.apply_block(&block(vec![first, second])).unwrap();
+        assert_eq!(ledger.account("alice"), Account { balance: 80, nonce: 2 });
+    }
+
+    #[test]
+    fn test_fees_credited_to_producer() {
+        let mut ledger = Ledger::from_genesis(&[("alice".to_string(), 100)]);
+        let mut first = transfer("alice", "bob", 10, 0);
+        first.fee = 3;
+        let mut second = transfer("alice", "bob", 10, 1);
+        second.fee = 2;
+
+        ledger.apply_block(&block(vec![first, second])).unwrap();
+        assert_eq!(ledger.balance("alice"), 75);
+        assert_eq!(ledger.balance("bob"), 20);
+        assert_eq!(ledger.balance("producer"), 5);
+
+        let mut unaffordable = transfer("alice", "bob", 70, 2);
+        unaffordable.fee = 6;
+        assert!(matches!(
+            ledger.apply_block(&block(vec![unaffordable])),
+            Err(LedgerError::InsufficientFunds { required: 76, .. })
+        ));
+    }
+
+    #[test]
+    fn test_revert_block_restores_previous_state() {
+        let mut ledger = Ledger::from_genesis(&[("alice".to_string(), 100)]);
+        let mut paid = transfer("alice", "bob", 30, 0);
+        paid.fee = 2;
+        let first = ledger.apply_block(&block(vec![paid])).unwrap();
+        let second = ledger
+            .apply_block(&block(vec![transfer("bob", "carol", 10, 0), transfer("alice", "bob", 5, 1)]))
+            .unwrap();
+
+        ledger.revert_block(&second);
+        assert_eq!(ledger.account("alice"), Account { balance: 68, nonce: 1 });
+        assert_eq!(ledger.account("bob"), Account { balance: 30, nonce: 0 });
+        assert_eq!(ledger.balance("carol"), 0);
+
+        ledger.revert_block(&first);
+        assert_eq!(ledger.account("alice"), Account { balance: 100, nonce: 0 });
+        assert_eq!(ledger.balance("bob"), 0);
+        assert_eq!(ledger.balance("producer"), 0);
+    }
+
+    #[test]
+    fn test_bond_and_unbond() {
+        let mut ledger = Ledger::from_genesis(&[("alice".to_string(), 100)]).with_bonds(&[("bob".to_string(), 5)]);
+        ledger.apply_block(&block(vec![staking(TransactionKind::Bond, "alice", 30, 0)])).unwrap();
+        assert_eq!(ledger.balance("alice"), 70);
+        assert_eq!(ledger.stake("alice").bonded, 30);
+        assert_eq!(ledger.bonded(), vec![("alice".to_string(), 30), ("bob".to_string(), 5)]);
+
+        assert!(matches!(
+            ledger.apply_block(&block(vec![staking(TransactionKind::Unbond, "alice", 31, 1)])),
+            Err(LedgerError::InsufficientStake { bonded: 30, required: 31, .. })
+        ));
+        ledger.apply_block(&block_in_epoch(3, vec![staking(TransactionKind::Unbond, "alice", 30, 1)])).unwrap();
+        assert_eq!(ledger.stake("alice").unbonding, vec![(30, 3 + UNBONDING_EPOCHS)]);
+        assert_eq!(ledger.bonded(), vec![("bob".to_string(), 5)]);
+        assert_eq!(ledger.balance("alice"), 70);
+    }
+
+    #[test]
+    fn test_withdraw_after_unbonding_delay() {
+        let mut ledger = Ledger::from_genesis(&[("alice".to_string(), 100)]);
+        ledger
+            .apply_block(&block(vec![
+                staking(TransactionKind::Bond, "alice", 50, 0),
+                staking(TransactionKind::Unbond, "alice", 20, 1),
+            ]))
+            .unwrap();
+
+        let early = UNBONDING_EPOCHS - 1;
+        assert!(matches!(
+            ledger.apply_block(&block_in_epoch(early, vec![staking(TransactionKind::Withdraw, "alice", 20, 2)])),
+            Err(LedgerError::NotWithdrawable { available: 0, .. })
+        ));
+        ledger
+            .apply_block(&block_in_epoch(UNBONDING_EPOCHS, vec![
+                staking(TransactionKind::Withdraw, "alice", 15, 2),
+                staking(TransactionKind::Unbond, "alice", 10, 3),
+            ]))
+            .unwrap();
+        assert_eq!(ledger.balance("alice"), 65);
+        assert_eq!(
+            ledger.stake("alice"),
+            Stake {
+                bonded: 20,
+                unbonding: vec![(5, UNBONDING_EPOCHS), (10, 2 * UNBONDING_EPOCHS)],
+                ..Stake::default()
+            }
+        );
+        assert_eq!(ledger.stake("alice").withdrawable(ledger.epoch()), 5);
+    }
+
+    #[test]
+    fn test_revert_block_restores_stakes() {
+        let mut ledger = Ledger::from_genesis(&[("alice".to_string(), 100)]).with_bonds(&[("alice".to_string(), 10)]);
+        let bonded = ledger.apply_block(&block_in_epoch(1, vec![staking(TransactionKind::Bond, "alice", 40, 0)])).unwrap();
+        let unbonded = ledger
+            .apply_block(&block_in_epoch(4, vec![staking(TransactionKind::Unbond, "alice", 50, 1)]))
+            .unwrap();
+        assert!(ledger.bonded().is_empty());
+
+        ledger.revert_block(&unbonded);
+        assert_eq!(ledger.stake("alice"), Stake { bonded: 50, ..Stake::default() });
+        assert_eq!(ledger.epoch(), 1);
+
+        ledger.revert_block(&bonded);
+        assert_eq!(ledger.stake("alice").bonded, 10);
+        assert_eq!(ledger.account("alice"), Account { balance: 100, nonce: 0 });
+        assert_eq!(ledger.epoch(), 0);
+    }
+
+    #[test]
+    fn test_epoch_start_snapshots_validator_set() {
+        let mut ledger = Ledger::from_genesis(&[("alice".to_string(), 100)]).with_bonds(&[("bob".to_string(), 20)]);
+        ledger.apply_block(&block(vec![staking(TransactionKind::Bond, "alice", 50, 0)])).unwrap();
+        assert_eq!(ledger.validator_set().stakeOf("alice"), 0);
+        assert_eq!(ledger.validator_set_for(SLOTS_PER_EPOCH).stakeOf("alice"), 50);
+
+        let before = ledger.validator_set().clone();
+        let undo = ledger
+            .apply_block(&block_in_epoch(1, vec![staking(TransactionKind::Unbond, "alice", 50, 1)]))
+            .unwrap();
+        assert_eq!(ledger.validator_set().epoch(), 1);
+        assert_eq!(ledger.validator_set().totalStake(), 70);
+        assert_eq!(ledger.validator_set().stakeOf("alice"), 50);
+
+        ledger.revert_block(&undo);
+        assert_eq!(ledger.validator_set(), &before);
+        ledger.apply_block(&block_in_epoch(1, Vec::new())).unwrap();
+        ledger.apply_block(&block_in_epoch(2, vec![staking(TransactionKind::Unbond, "alice", 50, 1)])).unwrap();
+        let withdraw = staking(TransactionKind::Withdraw, "alice", 50, 2);
+        ledger.apply_block(&block_in_epoch(2 + UNBONDING_EPOCHS, vec![withdraw])).unwrap();
+        assert_eq!(ledger.validator_set().stakeOf("alice"), 0);
+        assert_eq!(ledger.balance("alice"), 100);
+    }
+
+    #[test]
+    fn test_subset_holds_only_touched_entries() {
+        let ledger = Ledger::from_genesis(&[("alice".to_string(), 100), ("carol".to_string(), 100)])
+            .with_bonds(&[("bob".to_string(), 20), ("dave".to_string(), 20)]);
+        let delegate = TransactionData {
+            receiver: "bob".to_string(),
+            ..staking(TransactionKind::Delegate, "alice", 30, 0)
+        };
+        let mut subset = ledger.subset_for([&delegate]);
+        assert_eq!(subset.account("alice"), ledger.account("alice"));
+        assert_eq!(subset.stake("bob"), ledger.stake("bob"));
+        assert_eq!(subset.balance("carol"), 0);
+        assert_eq!(subset.stake("dave").bonded, 0);
+
+        subset.apply_transaction(&delegate).unwrap();
+        let undelegate = TransactionData {
+            receiver: "bob".to_string(),
+            ..staking(TransactionKind::Undelegate, "alice", 30, 1)
+        };
+        assert_eq!(subset.check_transaction(&undelegate), Ok(()));
+    }
+
+    #[test]
+    fn test_double_sign_slashes_and_jails() {
+        let keypair = KeyPair::generate();
+        let offender = Wallet::generate_address(&keypair.public_key);
+        let mut ledger = Ledger::from_genesis(&[(offender.clone(), 100)])
+            .with_bonds(&[(offender.clone(), 200), ("bob".to_string(), 20)]);
+        ledger.apply_block(&block(vec![staking(TransactionKind::Unbond, &offender, 100, 0)])).unwrap();
+        let evidence = double_sign(&keypair, 3);
+
+        let undo = ledger.apply_block(&block(vec![report("reporter", &evidence, 0)])).unwrap();
+        assert_eq!(
+            ledger.stake(&offender),
+            Stake { bonded: 90, unbonding: vec![(90, UNBONDING_EPOCHS)], ..Stake::default() }
+        );
+        assert_eq!(ledger.balance("reporter"), 10);
+        // The half of the slashed stake the reporter doesn't get is burned.
+        assert_eq!(ledger.supply(), 310);
+        assert!(ledger.is_jailed(&offender));
+        assert_eq!(ledger.validator_set().stakeOf(&offender), 0);
+        assert_eq!(ledger.validator_set().totalStake(), 20);
+        assert_eq!(
+            ledger.check_transaction(&report("reporter", &evidence, 1)),
+            Err(LedgerError::AlreadyJailed(offender.clone()))
+        );
+        assert_eq!(ledger.validator_set_for(JAIL_EPOCHS * SLOTS_PER_EPOCH - 1).stakeOf(&offender), 0);
+        assert_eq!(ledger.validator_set_for(JAIL_EPOCHS * SLOTS_PER_EPOCH).stakeOf(&offender), 90);
+
+        ledger.revert_block(&undo);
+        assert_eq!(ledger.stake(&offender).bonded, 100);
+        assert!(!ledger.is_jailed(&offender));
+        assert_eq!(ledger.validator_set().stakeOf(&offender), 200);
+        assert_eq!(ledger.balance("reporter"), 0);
+        assert_eq!(ledger.supply(), 320);
+
+        assert_eq!(
+            ledger.check_transaction(&report(&offender, &evidence, 1)),
+            Err(LedgerError::SelfReport(offender.clone()))
+        );
+        let mut misdirected = report("reporter", &evidence, 0);
+        misdirected.receiver = "bob".to_string();
+        assert_eq!(ledger.check_transaction(&misdirected), Err(LedgerError::NoEvidence("bob".to_string())));
+        let same = DoubleSignEvidence::new(evidence.first.clone(), evidence.first.clone());
+        assert_eq!(
+            ledger.check_transaction(&report("reporter", &same, 0)),
+            Err(LedgerError::InvalidEvidence(EvidenceError::SameBlock))
+        );
+        assert_eq!(
+            ledger.apply_block(&block_in_epoch(UNBONDING_EPOCHS, vec![report("reporter", &evidence, 0)])),
+            Err(LedgerError::EvidenceExpired(3))
+        );
+    }
+
+    #[test]
+    fn test_block_reward_minted_for_producer() {
+        let policy = MonetaryPolicy {
+            genesis_supply: 100,
+            block_reward: 50,
+            reduction_interval: 1,
+            reduction_percent: 50,
+            max_supply: 170,
+        };
+        let mut ledger = Ledger::from_genesis(&[("alice".to_string(), 100)]).with_policy(policy);
+        assert_eq!(ledger.supply(), 100);
+
+        let unpaid = block(Vec::new());
+        assert_eq!(
+            ledger.apply_block(&unpaid),
+            Err(LedgerError::WrongReward { expected: 50, actual: 0 })
+        );
+        let mut paid = transfer("alice", "bob", 10, 0);
+        paid.fee = 2;
+        let mut first = block(vec![paid]);
+        first.blockHeader.reward = 50;
+        let first_undo = ledger.apply_block(&first).unwrap();
+        assert_eq!(ledger.balance("producer"), 52);
+        assert_eq!(ledger.supply(), 150);
+
+        // Halved to 25 at height 1, then capped by the 20 left under max_supply.
+        assert_eq!(ledger.block_reward(1), 20);
+        let mut second = Block::new(1, String::new(), "producer".to_string(), Vec::new());
+        second.blockHeader.reward = 20;
+        let second_undo = ledger.apply_block(&second).unwrap();
+        assert_eq!(ledger.supply(), 170);
+        assert_eq!(ledger.block_reward(2), 0);
+
+        ledger.revert_block(&second_undo);
+        ledger.revert_block(&first_undo);
+        assert_eq!(ledger.supply(), 100);
+        assert_eq!(ledger.balance("producer"), 0);
+    }
+
+    #[test]
+    fn test_delegators_share_rewards_and_slashing() {
+        let keypair = KeyPair::generate();
+        let validator = Wallet::generate_address(&keypair.public_key);
+        let delegation = |kind: TransactionKind, amount: u64, nonce: u64| TransactionData {
+            kind,
+            ..transfer("dave", &validator, amount, nonce)
+        };
+        let produced = |producer: &str, slot: u64, transactions: Vec<TransactionData>| {
+            let mut block = Block::new(0, String::new(), producer.to_string(), transactions);
+            block.blockHeader.slot = slot;
+            block.blockHeader.reward = 40;
+            block
+        };
+        let mut ledger = Ledger::from_genesis(&[("dave".to_string(), 100)])
+            .with_bonds(&[(validator.clone(), 20)])
+            .with_policy(MonetaryPolicy { block_reward: 40, max_supply: u64::MAX, ..MonetaryPolicy::default() });
+        let mut to_nobody = delegation(TransactionKind::Delegate, 10, 0);
+        to_nobody.receiver = "nobody".to_string();
+        assert_eq!(ledger.check_transaction(&to_nobody), Err(LedgerError::NotValidator("nobody".to_string())));
+        assert_eq!(
+            ledger.check_transaction(&staking(TransactionKind::Commission, &validator, 101, 0)),
+            Err(LedgerError::InvalidCommission(101))
+        );
+
+        // Delegating mid-epoch earns nothing until the next epoch.
+        let delegated = ledger
+            .apply_block(&produced(&validator, 0, vec![
+                delegation(TransactionKind::Delegate, 60, 0),
+                staking(TransactionKind::Commission, &validator, 25, 0),
+            ]))
+            .unwrap();
+        assert_eq!(ledger.balance("dave"), 40);
+        assert_eq!(ledger.balance(&validator), 40);
+        assert_eq!(ledger.voting_power(), vec![(validator.clone(), 80)]);
+        assert_eq!(ledger.validator_set_for(SLOTS_PER_EPOCH).stakeOf(&validator), 80);
+
+        // From then on 25% of the 40 earned is commission, the other 30 is
+        // shared 60:20.
+        let undelegated = ledger
+            .apply_block(&produced(&validator, SLOTS_PER_EPOCH, vec![delegation(TransactionKind::Undelegate, 20, 1)]))
+            .unwrap();
+        assert_eq!(ledger.balance("dave"), 62);
+        assert_eq!(ledger.balance(&validator), 58);
+        assert_eq!(
+            ledger.check_transaction(&delegation(TransactionKind::Undelegate, 50, 2)),
+            Err(LedgerError::InsufficientDelegation {
+                delegator: "dave".to_string(),
+                validator: validator.clone(),
+                delegated: 40,
+                required: 50,
+            })
+        );
+        assert_eq!(
+            ledger.check_transaction(&delegation(TransactionKind::Withdraw, 20, 2)),
+            Err(LedgerError::NotWithdrawable { address: "dave".to_string(), available: 0, required: 20 })
+        );
+
+        let release_epoch = 1 + UNBONDING_EPOCHS;
+        let epoch_start = release_epoch * SLOTS_PER_EPOCH;
+        let withdrawn = ledger
+            .apply_block(&produced("producer", epoch_start, vec![
+                delegation(TransactionKind::Withdraw, 20, 2),
+                delegation(TransactionKind::Undelegate, 10, 3),
+            ]))
+            .unwrap();
+        assert_eq!(ledger.balance("dave"), 82);
+
+        // Own, delegated and undelegating stake all lose SLASH_PERCENT.
+        let evidence = double_sign(&keypair, epoch_start);
+        let slashed = ledger
+            .apply_block(&produced("producer", epoch_start + 1, vec![report("reporter", &evidence, 0)]))
+            .unwrap();
+        let stake = ledger.stake(&validator);
+        assert_eq!((stake.bonded, stake.delegated()), (18, 27));
+        assert_eq!(stake.withdrawable_by("dave", release_epoch + UNBONDING_EPOCHS), 9);
+        assert_eq!(ledger.balance("reporter"), 3);
+
+        for undo in [slashed, withdrawn, undelegated] {
+            ledger.revert_block(&undo);
+        }
+        assert_eq!(ledger.balance("dave"), 40);
+        assert_eq!(ledger.stake(&validator).delegations, BTreeMap::from([("dave".to_string(), 60)]));
+        assert!(ledger.stake(&validator).undelegating.is_empty());
+
+        // The epoch's snapshot is back too, so replaying pays the same.
+        ledger.revert_block(&delegated);
+        assert_eq!(ledger.epoch(), 0);
+        ledger
+            .apply_block(&produced(&validator, 0, vec![delegation(TransactionKind::Delegate, 60, 0)]))
+            .unwrap();
+        ledger
+            .apply_block(&produced(&validator, SLOTS_PER_EPOCH, Vec::new()))
+            .unwrap();
+        assert_eq!(ledger.balance("dave"), 40 + 30);
+    }
+}
@@ -0,0 +1,12 @@
+#![allow(non_snake_case)]
+
+pub mod blockchain;
+pub mod config;
+pub mod consensus;
+pub mod crypto;
+pub mod ledger;
+pub mod mempool;
+pub mod network;
+pub mod storage;
+pub mod utils;
+pub mod wallet;
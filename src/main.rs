@@ -1,84 +1,102 @@
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
-use tokio::task;
-use crate::network::node::Node;
-use crate::network::peer::Peer;
-use crate::network::message::{NetworkMessage, BlockData, TransactionData};
-use crate::wallet::Wallet;
-use chrono::Utc;
+use std::env;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use POST_bitcoin_core::config::NodeConfig;
+use POST_bitcoin_core::consensus::SigningRecord;
+use POST_bitcoin_core::crypto::SignatureAlgorithm;
+use POST_bitcoin_core::ledger::Ledger;
+use POST_bitcoin_core::network::message::NetworkMessage;
+use POST_bitcoin_core::network::{AddressBook, BanList, Node};
+use POST_bitcoin_core::storage::Database;
+use POST_bitcoin_core::wallet::{KeyPair, Wallet};
 
-#[tokio::main]
-async fn main() {
-    let mut cluster = Vec::new();
-    let blockchain = Arc::new(RwLock::new(Vec::<BlockData>::new()));
+const NODE_COUNT: u8 = 3;
+const BLOCK_SIZE_LIMIT: usize = 1000;
+// Every genesis wallet validates with the same stake, bonded out of its
+// share of the genesis supply.
+const VALIDATOR_STAKE: u64 = 10;
+const PAYMENT_INTERVAL: Duration = Duration::from_secs(3);
 
-    let node_count = 3;
-    for i in 0..node_count {
-        let address = format!("127.0.0.1:{}", 8000 + i).parse().unwrap();
-        let (tx_out, rx_out) = mpsc::channel::<NetworkMessage>(100);
-        let (tx_in, rx_in) = mpsc::channel::<NetworkMessage>(100);
-        let node = Node::new(address, rx_in, tx_out.clone(), 1000);
-        let mut wallet = Wallet::new();
-        wallet.balance = 100;
-        cluster.push((node, wallet, tx_in, tx_out));
-    }
+// Every process derives the same well-known genesis wallets, so a node
+// index is all it needs to know which one it owns.
+fn genesis_wallet(index: u8) -> Wallet {
+    let keypair = KeyPair::from_private_key(SignatureAlgorithm::Ed25519, &[index + 1; 32])
+        .expect("Genesis key is valid");
+    Wallet::from_keypair(keypair)
+}
 
-    for (i, (node, _, tx_in, _)) in cluster.iter().enumerate() {
-        for (j, (other_node, _, _, tx_out)) in cluster.iter().enumerate() {
-            if i != j {
-                let peer = Peer::new(other_node.address, false, 0);
-                node.add_peer(peer.clone());
-
-                let tx_in_clone = tx_in.clone();
-                let tx_out_clone = tx_out.clone();
-                task::spawn(async move {
-                    while let Some(msg) = tx_out_clone.recv().await {
-                        tx_in_clone.send(msg).await.unwrap();
-                    }
-                });
-            }
-        }
+// Usage: POST_bitcoin_core <node-index> [config-path]
+// Every node proposes blocks in its slots and pays the next one periodically.
+// Without a config path, node N reads config/nodeN.conf, which gives each
+// node its own port and data directory.
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <node-index> [config-path]", args[0]);
+        std::process::exit(1);
     }
+    let index: u8 = args[1].parse().expect("Node index must be a number");
+    assert!(index < NODE_COUNT, "Node index must be below {}", NODE_COUNT);
+    let config_path = args.get(2).cloned().unwrap_or_else(|| format!("config/node{}.conf", index));
+    let config = NodeConfig::load(&config_path).expect("Failed to load config");
 
-    if let Some((_, wallet, tx_in, _)) = cluster.get(0) {
-        let recipient_address = cluster[1].1.addresses[0].clone();
-        let tx = wallet.send_payment(&recipient_address, 50).unwrap();
+    let wallets: Vec<Wallet> = (0..NODE_COUNT).map(genesis_wallet).collect();
+    let share = config.monetary_policy.genesis_supply / NODE_COUNT as u64;
+    assert!(share >= VALIDATOR_STAKE, "Genesis supply must cover the validator stakes");
+    let genesis = Ledger::from_genesis(
+        &wallets
+            .iter()
+            .map(|wallet| (wallet.addresses[0].clone(), share - VALIDATOR_STAKE))
+            .collect::<Vec<_>>(),
+    )
+    .with_bonds(
+        &wallets
+            .iter()
+            .map(|wallet| (wallet.addresses[0].clone(), VALIDATOR_STAKE))
+            .collect::<Vec<_>>(),
+    )
+    .with_policy(config.monetary_policy.clone());
+    let recipient = wallets[((index + 1) % NODE_COUNT) as usize].addresses[0].clone();
+    let mut wallet = genesis_wallet(index);
+    wallet.sync_from_ledger(&genesis);
 
-        let msg = NetworkMessage::Transaction(tx);
-        tx_in.send(msg).await.unwrap();
+    let (inbound, incoming_messages) = mpsc::channel::<NetworkMessage>(100);
+    let (outgoing_messages, _) = mpsc::channel::<NetworkMessage>(100);
+    let mut node = Node::new(config.bind, incoming_messages, outgoing_messages, BLOCK_SIZE_LIMIT, genesis);
+    node.rewardAddress = wallet.addresses[0].clone();
+    node.validatorKey = Some(wallet.keypairs[0].clone());
+    node.stake = node.ledger.stake(&wallet.addresses[0]).bonded;
+    node.targetOutbound = config.target_outbound;
+    let db = Database::new(&config.data_dir).expect("Failed to open data directory");
+    node.addressBook = AddressBook::open(db.clone()).expect("Failed to load address book");
+    node.banList = BanList::open(db.clone()).expect("Failed to load ban list");
+    node.signingRecord = SigningRecord::open(db.clone()).expect("Failed to load signing record");
+    let loaded = node.loadChain(db).expect("Failed to load the chain");
+    wallet.sync_from_ledger(&node.ledger);
+    node.listen().await.expect("Failed to bind listen address");
+    for seed in &config.seed_nodes {
+        node.connect(*seed);
     }
+    println!("Node {} listening on {} as {}, {} blocks loaded", index, node.address, wallet.addresses[0], loaded);
 
-    for (node, _, _, _) in &cluster {
-        let blockchain_clone = blockchain.clone();
-        let mut node_clone = node.clone();
-
-        task::spawn(async move {
-            node_clone.handle_incoming().await;
-
-            let blocks = blockchain_clone.read().await;
-            for block in blocks.iter() {
-                println!("Block: {:#?}", block);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(PAYMENT_INTERVAL);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            match wallet.send_payment(&recipient, 1) {
+                Ok(tx) => {
+                    println!("Paying 1 to {} with nonce {}", recipient, tx.nonce);
+                    if inbound.send(NetworkMessage::Transaction(tx)).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => eprintln!("Payment failed: {}", e),
             }
-        });
-    }
-
-    for (_, wallet, _, _) in &cluster {
-        let blocks = blockchain.read().await.clone();
-        wallet.sync_balance(&blocks);
-        println!("Wallet: {} - Balance: {}", wallet.addresses[0], wallet.get_balance());
-    }
-
-    if let Some((node, _, tx_in, _)) = cluster.get(0) {
-        let mut node_clone = node.clone();
-
-        task::spawn(async move {
-            node_clone.create_and_broadcast_block().await;
-            let blocks = blockchain.read().await;
-            println!("Updated Blockchain: {:#?}", *blocks);
-        });
-    }
+        }
+    });
 
-    tokio::signal::ctrl_c().await.unwrap();
-    println!("Simulation finished.");
-}
\ No newline at end of file
+    node.run(true).await;
+}
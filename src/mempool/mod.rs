@@ -0,0 +1,3 @@
+pub mod pool;
+
+pub use pool::{Mempool, MempoolEntry, MempoolError};
\ No newline at end of file
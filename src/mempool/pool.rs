@@ -0,0 +1,578 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use thiserror::Error;
+use crate::ledger::{Ledger, LedgerError};
+use crate::blockchain::Block;
+use crate::network::message::TransactionData;
+use crate::utils::encoding;
+
+pub const DEFAULT_MAX_BYTES: usize = 1 << 20;
+pub const DEFAULT_EXPIRY_SECS: i64 = 3 * 60 * 60;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum MempoolError {
+    #[error("Transaction {0} is already in the pool")]
+    Duplicate(String),
+
+    #[error("{address} already has a pending transaction with nonce {nonce}")]
+    NonceConflict { address: String, nonce: u64 },
+
+    #[error("Pool is full and the fee rate is too low to evict anything")]
+    PoolFull,
+
+    #[error(transparent)]
+    Ledger(#[from] LedgerError),
+}
+
+#[derive(Debug, Clone)]
+pub struct MempoolEntry {
+    pub tx: TransactionData,
+    pub hash: String,
+    pub size: usize,
+    pub added_at: i64,
+}
+
+impl MempoolEntry {
+    // Higher fee per byte first, then older entries, then by hash so the
+    // order never depends on map iteration.
+    fn priority(&self, other: &MempoolEntry) -> Ordering {
+        let own_rate = self.tx.fee as u128 * other.size as u128;
+        let other_rate = other.tx.fee as u128 * self.size as u128;
+        own_rate
+            .cmp(&other_rate)
+            .then_with(|| other.added_at.cmp(&self.added_at))
+            .then_with(|| other.hash.cmp(&self.hash))
+    }
+}
+
+pub struct Mempool {
+    entries: HashMap<String, MempoolEntry>,
+    by_sender: HashMap<String, BTreeMap<u64, String>>,
+    total_bytes: usize,
+    max_bytes: usize,
+    expiry_secs: i64,
+}
+
+impl Mempool {
+    pub fn new(max_bytes: usize, expiry_secs: i64) -> Self {
+        Self {
+            entries: HashMap::new(),
+            by_sender: HashMap::new(),
+            total_bytes: 0,
+            max_bytes,
+            expiry_secs,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&TransactionData> {
+        self.entries.get(hash).map(|entry| &entry.tx)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MempoolEntry> {
+        self.entries.values()
+    }
+
+    // Pending transactions of `sender`, in nonce order.
+    pub fn pending_for(&self, sender: &str) -> Vec<&TransactionData> {
+        self.by_sender
+            .get(sender)
+            .map(|queue| queue.values().map(|hash| &self.entries[hash].tx).collect())
+            .unwrap_or_default()
+    }
+
+    // Accepts `tx` only if it extends the sender's pending nonce sequence,
+    // the sender can pay for it together with everything already pending,
+    // and the ledger would take it after the pending ones. Anything else
+    // could never be included, and would hold up the sender's later nonces.
+    pub fn insert(&mut self, tx: TransactionData, ledger: &Ledger, now: i64) -> Result<String, MempoolError> {
+        let hash = tx.calculateHash();
+        if self.entries.contains_key(&hash) {
+            return Err(MempoolError::Duplicate(hash));
+        }
+
+        let account = ledger.account(&tx.sender);
+        if tx.nonce < account.nonce {
+            return Err(LedgerError::Replayed {
+                address: tx.sender.clone(),
+                expected: account.nonce,
+                actual: tx.nonce,
+            }
+            .into());
+        }
+
+        let pending = self.pending_for(&tx.sender);
+        if pending.iter().any(|p| p.nonce == tx.nonce) {
+            return Err(MempoolError::NonceConflict {
+                address: tx.sender.clone(),
+                nonce: tx.nonce,
+            });
+        }
+        let expected = account.nonce + pending.len() as u64;
+        if tx.nonce != expected {
+            return Err(LedgerError::NonceGap {
+                address: tx.sender.clone(),
+                expected,
+                actual: tx.nonce,
+            }
+            .into());
+        }
+
+        let required = pending
+            .iter()
+            .chain(std::iter::once(&&tx))
+            .try_fold(0u64, |total, p| total.checked_add(p.balanceCost()?))
+            .ok_or_else(|| LedgerError::Overflow(tx.sender.clone()))?;
+        if account.balance < required {
+            return Err(LedgerError::InsufficientFunds {
+                address: tx.sender.clone(),
+                balance: account.balance,
+                required,
+            }
+            .into());
+        }
+        check_after_pending(ledger, &pending, &tx)?;
+
+        let entry = MempoolEntry {
+            size: transaction_size(&tx),
+            hash: hash.clone(),
+            tx,
+            added_at: now,
+        };
+        self.make_room(&entry)?;
+
+        self.total_bytes += entry.size;
+        self.by_sender
+            .entry(entry.tx.sender.clone())
+            .or_default()
+            .insert(entry.tx.nonce, hash.clone());
+        self.entries.insert(hash.clone(), entry);
+        Ok(hash)
+    }
+
+    pub fn remove(&mut self, hash: &str) -> Option<TransactionData> {
+        let entry = self.entries.remove(hash)?;
+        self.total_bytes -= entry.size;
+        if let Some(queue) = self.by_sender.get_mut(&entry.tx.sender) {
+            queue.remove(&entry.tx.nonce);
+            if queue.is_empty() {
+                self.by_sender.remove(&entry.tx.sender);
+            }
+        }
+        Some(entry.tx)
+    }
+
+    // Drops the transactions `block` included, plus any pending transaction
+    // whose nonce the ledger has now moved past or that fails on it, such as
+    // a report against an offender the block jailed.
+    pub fn remove_included(&mut self, block: &Block, ledger: &Ledger) {
+        for tx in &block.transactions {
+            self.remove(&tx.calculateHash());
+        }
+        let stale: Vec<String> = self
+            .by_sender
+            .iter()
+            .flat_map(|(sender, queue)| {
+                let confirmed = ledger.nonce(sender);
+                queue.range(..confirmed).map(|(_, hash)| hash.clone())
+            })
+            .collect();
+        for hash in stale {
+            self.remove(&hash);
+        }
+        self.remove_failing(ledger);
+    }
+
+    // Drops each sender's next transaction, and everything queued behind
+    // it, if `ledger` would no longer take it.
+    pub fn remove_failing(&mut self, ledger: &Ledger) {
+        let failing: Vec<String> = self
+            .by_sender
+            .values()
+            .filter_map(|queue| queue.values().next())
+            .filter(|hash| ledger.check_transaction(&self.entries[*hash].tx).is_err())
+            .cloned()
+            .collect();
+        for hash in failing {
+            self.remove_with_descendants(&hash);
+        }
+    }
+
+    // After a reorg: takes back the transactions of disconnected blocks and
+    // re-checks everything pending against the new ledger, dropping whatever
+    // no longer applies. Entries already pooled keep their arrival time.
+    pub fn readmit(&mut self, disconnected: Vec<TransactionData>, ledger: &Ledger, now: i64) {
+        let mut candidates: Vec<(TransactionData, i64)> = disconnected.into_iter().map(|tx| (tx, now)).collect();
+        candidates.extend(self.entries.drain().map(|(_, entry)| (entry.tx, entry.added_at)));
+        self.by_sender.clear();
+        self.total_bytes = 0;
+
+        candidates.sort_by(|(a, _), (b, _)| a.sender.cmp(&b.sender).then_with(|| a.nonce.cmp(&b.nonce)));
+        for (tx, added_at) in candidates {
+            let _ = self.insert(tx, ledger, added_at);
+        }
+    }
+
+    pub fn expire(&mut self, now: i64) -> usize {
+        let expired: Vec<String> = self
+            .entries
+            .values()
+            .filter(|entry| now - entry.added_at > self.expiry_secs)
+            .map(|entry| entry.hash.clone())
+            .collect();
+        let before = self.len();
+        for hash in expired {
+            self.remove_with_descendants(&hash);
+        }
+        before - self.len()
+    }
+
+    // Highest fee-rate first, taking each sender's transactions in nonce
+    // order and skipping anything the ledger would reject.
+    pub fn select_for_block(&self, ledger: &Ledger, max_bytes: usize) -> Vec<TransactionData> {
+        let mut queues: Vec<Vec<&MempoolEntry>> = self
+            .by_sender
+            .values()
+            .map(|queue| queue.values().rev().map(|hash| &self.entries[hash]).collect())
+            .collect();
+
+        let mut staged = ledger.clone();
+        let mut selected = Vec::new();
+        let mut block_bytes = 0;
+        loop {
+            queues.retain(|queue| {
+                queue.last().is_some_and(|head| {
+                    head.size <= max_bytes - block_bytes && staged.check_transaction(&head.tx).is_ok()
+                })
+            });
+            let best = queues
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.last().unwrap().priority(b.last().unwrap()))
+                .map(|(index, _)| index);
+            let Some(index) = best else {
+                break;
+            };
+
+            let entry = queues[index].pop().unwrap();
+            if staged.apply_transaction(&entry.tx).is_err() {
+                queues.swap_remove(index);
+                continue;
+            }
+            block_bytes += entry.size;
+            selected.push(entry.tx.clone());
+        }
+        selected
+    }
+
+    fn remove_with_descendants(&mut self, hash: &str) {
+        let Some(entry) = self.entries.get(hash) else {
+            return;
+        };
+        let descendants: Vec<String> = self.by_sender[&entry.tx.sender]
+            .range(entry.tx.nonce..)
+            .map(|(_, hash)| hash.clone())
+            .collect();
+        for hash in descendants {
+            self.remove(&hash);
+        }
+    }
+
+    // Evicts the cheapest transactions (and the later nonces that depend on
+    // them) until `incoming` fits, but only if all of them pay a lower fee
+    // rate than `incoming`. Nothing is evicted when that is not possible.
+    fn make_room(&mut self, incoming: &MempoolEntry) -> Result<(), MempoolError> {
+        if incoming.size > self.max_bytes {
+            return Err(MempoolError::PoolFull);
+        }
+        if self.total_bytes + incoming.size <= self.max_bytes {
+            return Ok(());
+        }
+
+        let mut candidates: Vec<&MempoolEntry> = self
+            .entries
+            .values()
+            .filter(|entry| entry.tx.sender != incoming.tx.sender)
+            .collect();
+        candidates.sort_by(|a, b| a.priority(b));
+
+        let mut victims = HashSet::new();
+        let mut freed = 0;
+        for candidate in candidates {
+            if self.total_bytes - freed + incoming.size <= self.max_bytes {
+                break;
+            }
+            if candidate.priority(incoming) != Ordering::Less {
+                return Err(MempoolError::PoolFull);
+            }
+            for hash in self.by_sender[&candidate.tx.sender].range(candidate.tx.nonce..).map(|(_, h)| h) {
+                if victims.insert(hash.clone()) {
+                    freed += self.entries[hash].size;
+                }
+            }
+        }
+        if self.total_bytes - freed + incoming.size > self.max_bytes {
+            return Err(MempoolError::PoolFull);
+        }
+
+        for hash in victims {
+            self.remove(&hash);
+        }
+        Ok(())
+    }
+}
+
+// Runs the ledger's checks, staking rules included, on `tx` as it would
+// apply once `pending` has, staging only the entries they touch.
+fn check_after_pending(ledger: &Ledger, pending: &[&TransactionData], tx: &TransactionData) -> Result<(), LedgerError> {
+    if pending.is_empty() {
+        return ledger.check_transaction(tx);
+    }
+    let mut staged = ledger.subset_for(pending.iter().copied().chain([tx]));
+    for pending in pending {
+        staged.apply_transaction(pending)?;
+    }
+    staged.check_transaction(tx)
+}
+
+pub fn transaction_size(tx: &TransactionData) -> usize {
+    encoding::serialize(tx).map(|bytes| bytes.len()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SignatureAlgorithm;
+    use crate::network::message::{TransactionKind, DEFAULT_CHAIN_ID};
+
+    fn transfer(sender: &str, amount: u64, fee: u64, nonce: u64) -> TransactionData {
+        TransactionData {
+            kind: TransactionKind::Transfer,
+            sender: sender.to_string(),
+            receiver: "receiver".to_string(),
+            amount,
+            fee,
+            nonce,
+            chainId: DEFAULT_CHAIN_ID,
+            algorithm: SignatureAlgorithm::Ed25519,
+            publicKey: Vec::new(),
+            signature: Vec::new(),
+            evidence: None,
+        }
+    }
+
+    fn staking(kind: TransactionKind, sender: &str, receiver: &str, amount: u64, nonce: u64) -> TransactionData {
+        TransactionData {
+            kind,
+            receiver: receiver.to_string(),
+            ..transfer(sender, amount, 1, nonce)
+        }
+    }
+
+    fn ledger() -> Ledger {
+        Ledger::from_genesis(&[
+            ("alice".to_string(), 100),
+            ("bobby".to_string(), 100),
+            ("carol".to_string(), 100),
+        ])
+    }
+
+    fn block(transactions: Vec<TransactionData>) -> Block {
+        Block::new(0, String::new(), String::new(), transactions)
+    }
+
+    #[test]
+    fn test_duplicate_rejected() {
+        let mut pool = Mempool::new(DEFAULT_MAX_BYTES, DEFAULT_EXPIRY_SECS);
+        let hash = pool.insert(transfer("alice", 10, 1, 0), &ledger(), 0).unwrap();
+
+        assert_eq!(
+            pool.insert(transfer("alice", 10, 1, 0), &ledger(), 0),
+            Err(MempoolError::Duplicate(hash.clone()))
+        );
+        assert!(pool.contains(&hash));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_sender_nonce_ordering() {
+        let mut pool = Mempool::new(DEFAULT_MAX_BYTES, DEFAULT_EXPIRY_SECS);
+        let ledger = ledger();
+
+        assert!(matches!(
+            pool.insert(transfer("alice", 10, 1, 1), &ledger, 0),
+            Err(MempoolError::Ledger(LedgerError::NonceGap { expected: 0, actual: 1, .. }))
+        ));
+        pool.insert(transfer("alice", 10, 1, 0), &ledger, 0).unwrap();
+        assert_eq!(
+            pool.insert(transfer("alice", 20, 1, 0), &ledger, 0),
+            Err(MempoolError::NonceConflict { address: "alice".to_string(), nonce: 0 })
+        );
+        pool.insert(transfer("alice", 10, 1, 1), &ledger, 0).unwrap();
+
+        let nonces: Vec<u64> = pool.pending_for("alice").iter().map(|tx| tx.nonce).collect();
+        assert_eq!(nonces, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_pending_spend_counts_against_balance() {
+        let mut pool = Mempool::new(DEFAULT_MAX_BYTES, DEFAULT_EXPIRY_SECS);
+        pool.insert(transfer("alice", 60, 1, 0), &ledger(), 0).unwrap();
+
+        assert!(matches!(
+            pool.insert(transfer("alice", 39, 1, 1), &ledger(), 0),
+            Err(MempoolError::Ledger(LedgerError::InsufficientFunds { required: 101, .. }))
+        ));
+        pool.insert(transfer("alice", 38, 1, 1), &ledger(), 0).unwrap();
+    }
+
+    #[test]
+    fn test_staking_rules_checked_after_pending() {
+        let mut pool = Mempool::new(DEFAULT_MAX_BYTES, DEFAULT_EXPIRY_SECS);
+        let ledger = ledger();
+        for rejected in [
+            staking(TransactionKind::Unbond, "alice", "alice", 10, 0),
+            staking(TransactionKind::Withdraw, "alice", "alice", 10, 0),
+            staking(TransactionKind::Undelegate, "alice", "bobby", 10, 0),
+        ] {
+            assert!(pool.insert(rejected, &ledger, 0).is_err());
+        }
+        assert!(pool.is_empty());
+
+        pool.insert(staking(TransactionKind::Bond, "alice", "alice", 20, 0), &ledger, 0).unwrap();
+        assert!(matches!(
+            pool.insert(staking(TransactionKind::Unbond, "alice", "alice", 30, 1), &ledger, 0),
+            Err(MempoolError::Ledger(LedgerError::InsufficientStake { bonded: 20, required: 30, .. }))
+        ));
+        pool.insert(staking(TransactionKind::Unbond, "alice", "alice", 20, 1), &ledger, 0).unwrap();
+        assert_eq!(pool.select_for_block(&ledger, usize::MAX).len(), 2);
+    }
+
+    #[test]
+    fn test_full_pool_evicts_lowest_fee_rate() {
+        let size = transaction_size(&transfer("alice", 10, 1, 0));
+        let mut pool = Mempool::new(3 * size, DEFAULT_EXPIRY_SECS);
+        let ledger = ledger();
+
+        let cheap = pool.insert(transfer("alice", 10, 2, 0), &ledger, 0).unwrap();
+        let cheap_child = pool.insert(transfer("alice", 10, 9, 1), &ledger, 0).unwrap();
+        let medium = pool.insert(transfer("bobby", 10, 5, 0), &ledger, 0).unwrap();
+
+        assert_eq!(pool.insert(transfer("carol", 10, 1, 0), &ledger, 0), Err(MempoolError::PoolFull));
+        assert_eq!(pool.len(), 3);
+
+        let rich = pool.insert(transfer("carol", 10, 7, 0), &ledger, 0).unwrap();
+        assert!(!pool.contains(&cheap));
+        assert!(!pool.contains(&cheap_child));
+        assert!(pool.contains(&medium));
+        assert!(pool.contains(&rich));
+        assert_eq!(pool.total_bytes(), 2 * size);
+    }
+
+    #[test]
+    fn test_stale_entries_expire() {
+        let mut pool = Mempool::new(DEFAULT_MAX_BYTES, 60);
+        let ledger = ledger();
+        pool.insert(transfer("alice", 10, 1, 0), &ledger, 0).unwrap();
+        pool.insert(transfer("alice", 10, 1, 1), &ledger, 50).unwrap();
+        pool.insert(transfer("bobby", 10, 1, 0), &ledger, 50).unwrap();
+
+        assert_eq!(pool.expire(60), 0);
+        assert_eq!(pool.expire(61), 2);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.pending_for("bobby").len(), 1);
+        assert_eq!(pool.total_bytes(), transaction_size(&transfer("bobby", 10, 1, 0)));
+    }
+
+    #[test]
+    fn test_included_and_superseded_transactions_removed() {
+        let mut pool = Mempool::new(DEFAULT_MAX_BYTES, DEFAULT_EXPIRY_SECS);
+        let mut ledger = ledger();
+        let included = transfer("alice", 10, 1, 0);
+        pool.insert(included.clone(), &ledger, 0).unwrap();
+        pool.insert(transfer("alice", 10, 1, 1), &ledger, 0).unwrap();
+        pool.insert(transfer("bobby", 10, 1, 0), &ledger, 0).unwrap();
+
+        let competing = transfer("bobby", 20, 2, 0);
+        let accepted = block(vec![included, competing]);
+        ledger.apply_block(&accepted).unwrap();
+        pool.remove_included(&accepted, &ledger);
+
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.pending_for("alice")[0].nonce, 1);
+        assert!(pool.pending_for("bobby").is_empty());
+    }
+
+    #[test]
+    fn test_transactions_failing_after_block_removed() {
+        let mut pool = Mempool::new(DEFAULT_MAX_BYTES, DEFAULT_EXPIRY_SECS);
+        pool.insert(transfer("alice", 50, 1, 0), &ledger(), 0).unwrap();
+        pool.insert(transfer("alice", 10, 1, 1), &ledger(), 0).unwrap();
+        pool.insert(transfer("bobby", 10, 1, 0), &ledger(), 0).unwrap();
+
+        // As if alice had been slashed by the block.
+        let ledger = Ledger::from_genesis(&[("alice".to_string(), 20), ("bobby".to_string(), 100)]);
+        pool.remove_included(&block(Vec::new()), &ledger);
+
+        assert!(pool.pending_for("alice").is_empty());
+        assert_eq!(pool.pending_for("bobby").len(), 1);
+    }
+
+    #[test]
+    fn test_disconnected_transactions_readmitted() {
+        let mut pool = Mempool::new(DEFAULT_MAX_BYTES, DEFAULT_EXPIRY_SECS);
+        let mut ledger = ledger();
+        let first = transfer("alice", 10, 1, 0);
+        let second = transfer("alice", 10, 1, 1);
+        let undo = ledger.apply_block(&block(vec![first.clone(), second.clone()])).unwrap();
+        pool.insert(transfer("alice", 10, 1, 2), &ledger, 5).unwrap();
+        pool.insert(transfer("bobby", 10, 1, 0), &ledger, 5).unwrap();
+
+        ledger.revert_block(&undo);
+        let replacement = transfer("alice", 50, 1, 0);
+        ledger.apply_block(&block(vec![replacement])).unwrap();
+        pool.readmit(vec![first, second], &ledger, 9);
+
+        let pending: Vec<u64> = pool.pending_for("alice").iter().map(|tx| tx.nonce).collect();
+        assert_eq!(pending, vec![1, 2]);
+        assert_eq!(pool.len(), 3);
+        assert!(pool.iter().all(|entry| entry.added_at == if entry.tx.nonce == 1 { 9 } else { 5 }));
+    }
+
+    #[test]
+    fn test_select_for_block_by_fee_rate_and_nonce() {
+        let size = transaction_size(&transfer("alice", 10, 1, 0));
+        let mut pool = Mempool::new(DEFAULT_MAX_BYTES, DEFAULT_EXPIRY_SECS);
+        let ledger = ledger();
+        pool.insert(transfer("alice", 10, 1, 0), &ledger, 0).unwrap();
+        pool.insert(transfer("alice", 10, 8, 1), &ledger, 0).unwrap();
+        pool.insert(transfer("bobby", 10, 5, 0), &ledger, 0).unwrap();
+        pool.insert(transfer("carol", 10, 3, 0), &ledger, 0).unwrap();
+
+        let picked: Vec<(String, u64)> = pool
+            .select_for_block(&ledger, 3 * size)
+            .into_iter()
+            .map(|tx| (tx.sender, tx.nonce))
+            .collect();
+        assert_eq!(
+            picked,
+            vec![("bobby".to_string(), 0), ("carol".to_string(), 0), ("alice".to_string(), 0)]
+        );
+        assert_eq!(pool.len(), 4);
+    }
+}
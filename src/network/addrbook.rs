@@ -0,0 +1,258 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use serde::{Serialize, Deserialize};
+use log::error;
+use crate::storage::{Database, StorageError};
+
+pub const ADDRESS_BOOK_KEY: &str = "address_book";
+pub const MAX_ADDRESSES: usize = 1000;
+pub const MAX_ADDR_PER_MESSAGE: usize = 1000;
+// New addresses one `Addr` message, and one peer in all, may add, so no
+// single peer can fill the book with addresses it made up.
+pub const MAX_NEW_ADDR_PER_MESSAGE: usize = 50;
+pub const MAX_NEW_ADDR_PER_PEER: usize = 200;
+// Unreachable reports tolerated before an address is forgotten.
+pub const MAX_ADDRESS_FAILURES: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AddressEntry {
+    pub address: SocketAddr,
+    pub last_seen: i64,
+    pub failures: u32,
+}
+
+// Peer addresses learned from seeds, connections and `Addr` gossip. When
+// opened on a database, changes are saved by `flush`, and when dropped.
+#[derive(Default)]
+pub struct AddressBook {
+    entries: HashMap<SocketAddr, AddressEntry>,
+    db: Option<Database>,
+    dirty: bool,
+}
+
+impl AddressBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open(db: Database) -> Result<Self, StorageError> {
+        let saved: Vec<AddressEntry> = db.get(&ADDRESS_BOOK_KEY)?.unwrap_or_default();
+        Ok(Self {
+            entries: saved.into_iter().map(|entry| (entry.address, entry)).collect(),
+            db: Some(db),
+            dirty: false,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, address: &SocketAddr) -> bool {
+        self.entries.contains_key(address)
+    }
+
+    pub fn get(&self, address: &SocketAddr) -> Option<&AddressEntry> {
+        self.entries.get(address)
+    }
+
+    // Records that `address` was heard of at `now`. Returns whether it is
+    // new; hearing of a known address again doesn't make it any fresher.
+    pub fn add(&mut self, address: SocketAddr, now: i64) -> bool {
+        self.insert(address, now)
+    }
+
+    // Adds addresses until `limit` of them were new. Returns how many were.
+    pub fn add_all(&mut self, addresses: &[SocketAddr], now: i64, limit: usize) -> usize {
+        let mut added = 0;
+        for address in addresses {
+            if added == limit {
+                break;
+            }
+            if self.insert(*address, now) {
+                added += 1;
+            }
+        }
+        added
+    }
+
+    // Only a connection proves an address is live, so only this refreshes
+    // when it was last seen.
+    pub fn mark_connected(&mut self, address: SocketAddr, now: i64) {
+        self.insert(address, now);
+        if let Some(entry) = self.entries.get_mut(&address) {
+            entry.last_seen = entry.last_seen.max(now);
+            entry.failures = 0;
+        }
+        self.dirty = true;
+    }
+
+    pub fn mark_failed(&mut self, address: &SocketAddr) {
+        if let Some(entry) = self.entries.get_mut(address) {
+            entry.failures += 1;
+            if entry.failures >= MAX_ADDRESS_FAILURES {
+                self.entries.remove(address);
+            }
+            self.dirty = true;
+        }
+    }
+
+    // Addresses worth dialing, most reliable and most recently seen first.
+    pub fn candidates(&self, exclude: &HashSet<SocketAddr>) -> Vec<SocketAddr> {
+        let mut candidates: Vec<&AddressEntry> = self
+            .entries
+            .values()
+            .filter(|entry| !exclude.contains(&entry.address))
+            .collect();
+        candidates.sort_by(|a, b| {
+            a.failures
+                .cmp(&b.failures)
+                .then_with(|| b.last_seen.cmp(&a.last_seen))
+                .then_with(|| a.address.cmp(&b.address))
+        });
+        candidates.into_iter().map(|entry| entry.address).collect()
+    }
+
+    // Most recently seen addresses, for answering `GetAddr`.
+    pub fn sample(&self, limit: usize, exclude: &SocketAddr) -> Vec<SocketAddr> {
+        let mut entries: Vec<&AddressEntry> = self
+            .entries
+            .values()
+            .filter(|entry| entry.address != *exclude)
+            .collect();
+        entries.sort_by(|a, b| b.last_seen.cmp(&a.last_seen).then_with(|| a.address.cmp(&b.address)));
+        entries.into_iter().take(limit).map(|entry| entry.address).collect()
+    }
+
+    fn insert(&mut self, address: SocketAddr, now: i64) -> bool {
+        if self.entries.contains_key(&address) {
+            return false;
+        }
+        if self.entries.len() >= MAX_ADDRESSES {
+            self.evict_stalest();
+        }
+        self.entries.insert(
+            address,
+            AddressEntry {
+                address,
+                last_seen: now,
+                failures: 0,
+            },
+        );
+        self.dirty = true;
+        true
+    }
+
+    fn evict_stalest(&mut self) {
+        let stalest = self
+            .entries
+            .values()
+            .max_by(|a, b| a.failures.cmp(&b.failures).then_with(|| b.last_seen.cmp(&a.last_seen)))
+            .map(|entry| entry.address);
+        if let Some(address) = stalest {
+            self.entries.remove(&address);
+        }
+    }
+
+    // Saves the book if it changed since it was last saved.
+    pub fn flush(&mut self) {
+        let Some(db) = self.db.as_ref().filter(|_| self.dirty) else {
+            return;
+        };
+        let entries: Vec<&AddressEntry> = self.entries.values().collect();
+        match db.save(&ADDRESS_BOOK_KEY, &entries) {
+            Ok(()) => self.dirty = false,
+            Err(e) => error!("Failed to save the address book: {}", e),
+        }
+    }
+}
+
+impl Drop for AddressBook {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_candidates_prefer_reliable_recent_addresses() {
+        let mut book = AddressBook::new();
+        assert!(book.add(address(1), 10));
+        assert!(!book.add(address(1), 20));
+        assert_eq!(book.get(&address(1)).unwrap().last_seen, 10);
+        book.mark_connected(address(1), 20);
+        book.add_all(&[address(2), address(3)], 15, usize::MAX);
+        book.mark_failed(&address(2));
+
+        assert_eq!(book.get(&address(1)).unwrap().last_seen, 20);
+        assert_eq!(book.candidates(&HashSet::new()), vec![address(1), address(3), address(2)]);
+        assert_eq!(book.candidates(&HashSet::from([address(1)])), vec![address(3), address(2)]);
+        assert_eq!(book.sample(2, &address(3)), vec![address(1), address(2)]);
+    }
+
+    #[test]
+    fn test_repeatedly_unreachable_address_forgotten() {
+        let mut book = AddressBook::new();
+        book.add(address(1), 0);
+        for _ in 1..MAX_ADDRESS_FAILURES {
+            book.mark_failed(&address(1));
+        }
+        book.mark_connected(address(1), 5);
+        assert_eq!(book.get(&address(1)).unwrap().failures, 0);
+
+        for _ in 0..MAX_ADDRESS_FAILURES {
+            book.mark_failed(&address(1));
+        }
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn test_book_is_capped() {
+        let mut book = AddressBook::new();
+        for port in 0..MAX_ADDRESSES as u16 {
+            book.add(address(port), port as i64 + 1);
+        }
+        book.add(address(u16::MAX), 0);
+        assert_eq!(book.len(), MAX_ADDRESSES);
+        assert!(!book.contains(&address(0)));
+    }
+
+    #[test]
+    fn test_add_all_stops_at_limit_of_new_addresses() {
+        let mut book = AddressBook::new();
+        book.add(address(1), 0);
+        let added = book.add_all(&[address(1), address(2), address(3), address(4)], 5, 2);
+        assert_eq!(added, 2);
+        assert_eq!(book.len(), 3);
+        assert!(!book.contains(&address(4)));
+        assert_eq!(book.get(&address(1)).unwrap().last_seen, 0);
+    }
+
+    #[test]
+    fn test_book_persists_through_database() {
+        let db = Database::new("test_addrbook_db").unwrap();
+        db.delete(&ADDRESS_BOOK_KEY).unwrap();
+
+        let mut book = AddressBook::open(db.clone()).unwrap();
+        book.add_all(&[address(1), address(2)], 7, usize::MAX);
+        book.flush();
+        assert_eq!(AddressBook::open(db.clone()).unwrap().len(), 2);
+        book.add(address(3), 8);
+        drop(book);
+
+        let reopened = AddressBook::open(db).unwrap();
+        assert_eq!(reopened.len(), 3);
+        assert_eq!(reopened.get(&address(2)).unwrap().last_seen, 7);
+    }
+}
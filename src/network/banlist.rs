@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use log::error;
+use crate::storage::{Database, StorageError};
+
+pub const BAN_LIST_KEY: &str = "bans";
+// Misbehavior score at which a peer is disconnected and banned.
+pub const BAN_THRESHOLD: u32 = 100;
+pub const DEFAULT_BAN_SECS: i64 = 24 * 60 * 60;
+
+// Peers banned for misbehaving, by their `ban_key`, with the time each ban
+// ends. When opened on a database, every change is written through to it.
+#[derive(Default)]
+pub struct BanList {
+    bans: HashMap<SocketAddr, i64>,
+    db: Option<Database>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open(db: Database) -> Result<Self, StorageError> {
+        let saved: Vec<(SocketAddr, i64)> = db.get(&BAN_LIST_KEY)?.unwrap_or_default();
+        Ok(Self {
+            bans: saved.into_iter().collect(),
+            db: Some(db),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.bans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bans.is_empty()
+    }
+
+    pub fn bans(&self) -> &HashMap<SocketAddr, i64> {
+        &self.bans
+    }
+
+    pub fn ban(&mut self, key: SocketAddr, until: i64) {
+        let banned_until = self.bans.entry(key).or_insert(until);
+        *banned_until = (*banned_until).max(until);
+        self.persist();
+    }
+
+    pub fn unban(&mut self, key: &SocketAddr) {
+        if self.bans.remove(key).is_some() {
+            self.persist();
+        }
+    }
+
+    pub fn is_banned(&self, key: &SocketAddr, now: i64) -> bool {
+        is_banned(&self.bans, key, now)
+    }
+
+    // Forgets bans that have run out.
+    pub fn prune(&mut self, now: i64) {
+        let before = self.bans.len();
+        self.bans.retain(|_, until| now < *until);
+        if self.bans.len() != before {
+            self.persist();
+        }
+    }
+
+    fn persist(&self) {
+        let Some(db) = &self.db else {
+            return;
+        };
+        let bans: Vec<(&SocketAddr, &i64)> = self.bans.iter().collect();
+        if let Err(e) = db.save(&BAN_LIST_KEY, &bans) {
+            error!("Failed to save the ban list: {}", e);
+        }
+    }
+}
+
+// What a peer is scored and banned by: the IP its connection comes from,
+// which unlike its announced listen address it can't pick. Local nodes all
+// share the loopback IP, so there the listen address tells them apart.
+pub fn ban_key(ip: IpAddr, listen: SocketAddr) -> SocketAddr {
+    if ip.is_loopback() {
+        listen
+    } else {
+        SocketAddr::new(ip, 0)
+    }
+}
+
+// Lookup shared with the listener, which checks a published copy of the
+// bans before handshaking.
+pub fn is_banned(bans: &HashMap<SocketAddr, i64>, key: &SocketAddr, now: i64) -> bool {
+    bans.get(key).is_some_and(|until| now < *until)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(last: u8) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, last], 0))
+    }
+
+    #[test]
+    fn test_bans_run_out() {
+        let mut bans = BanList::new();
+        bans.ban(address(1), 100);
+        bans.ban(address(1), 50);
+        bans.ban(address(2), 10);
+
+        assert!(bans.is_banned(&address(1), 99));
+        assert!(!bans.is_banned(&address(1), 100));
+        assert!(!bans.is_banned(&address(3), 0));
+
+        bans.prune(20);
+        assert_eq!(bans.len(), 1);
+        bans.unban(&address(1));
+        assert!(bans.is_empty());
+    }
+
+    #[test]
+    fn test_loopback_peers_keyed_by_listen_address() {
+        let local = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        assert_eq!(ban_key(local(1).ip(), local(2)), local(2));
+
+        let remote = SocketAddr::from(([10, 0, 0, 1], 8333));
+        assert_eq!(ban_key(remote.ip(), remote), SocketAddr::from(([10, 0, 0, 1], 0)));
+        assert_eq!(ban_key(remote.ip(), local(2)), ban_key(remote.ip(), remote));
+    }
+
+    #[test]
+    fn test_ban_list_persists_through_database() {
+        let db = Database::new("test_banlist_db").unwrap();
+        db.delete(&BAN_LIST_KEY).unwrap();
+
+        let mut bans = BanList::open(db.clone()).unwrap();
+        bans.ban(address(1), 100);
+        drop(bans);
+
+        let reopened = BanList::open(db).unwrap();
+        assert!(reopened.is_banned(&address(1), 0));
+    }
+}
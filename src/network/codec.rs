@@ -0,0 +1,229 @@
+use bytes::{Buf, BufMut, BytesMut};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio_util::codec::{Decoder, Encoder};
+use crate::network::message::NetworkMessage;
+use crate::utils::encoding;
+
+pub const NETWORK_MAGIC: [u8; 4] = *b"PQBC";
+pub const PROTOCOL_VERSION: u16 = 1;
+pub const MAX_PAYLOAD_BYTES: usize = 4 * 1024 * 1024;
+
+// magic (4) | version (2) | command (1) | payload length (4) | checksum (4),
+// integers big-endian, followed by the bincode payload.
+pub const HEADER_BYTES: usize = 15;
+
+#[derive(Error, Debug)]
+pub enum CodecError {
+    #[error("Unexpected network magic {0:02x?}")]
+    BadMagic([u8; 4]),
+
+    #[error("Unsupported protocol version {0}")]
+    UnsupportedVersion(u16),
+
+    #[error("Payload of {0} bytes exceeds the frame limit")]
+    Oversized(usize),
+
+    #[error("Payload checksum mismatch")]
+    ChecksumMismatch,
+
+    #[error("Frame command {frame} does not match payload command {payload}")]
+    CommandMismatch { frame: u8, payload: u8 },
+
+    #[error("Malformed payload: {0}")]
+    Malformed(String),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub struct MessageCodec {
+    magic: [u8; 4],
+    maxPayloadBytes: usize,
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        Self::new(NETWORK_MAGIC, MAX_PAYLOAD_BYTES)
+    }
+}
+
+impl MessageCodec {
+    pub fn new(magic: [u8; 4], maxPayloadBytes: usize) -> Self {
+        Self { magic, maxPayloadBytes }
+    }
+
+    // Encodes a single frame, for writers that don't go through `Framed`.
+    pub fn encodeFrame(&mut self, message: &NetworkMessage) -> Result<BytesMut, CodecError> {
+        let mut frame = BytesMut::new();
+        self.encode(message, &mut frame)?;
+        Ok(frame)
+    }
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let digest = Sha256::digest(payload);
+    [digest[0], digest[1], digest[2], digest[3]]
+}
+
+impl Encoder<&NetworkMessage> for MessageCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, message: &NetworkMessage, dst: &mut BytesMut) -> Result<(), CodecError> {
+        let payload = encoding::serialize(message).map_err(CodecError::Malformed)?;
+        if payload.len() > self.maxPayloadBytes {
+            return Err(CodecError::Oversized(payload.len()));
+        }
+
+        dst.reserve(HEADER_BYTES + payload.len());
+        dst.put_slice(&self.magic);
+        dst.put_u16(PROTOCOL_VERSION);
+        dst.put_u8(message.command());
+        dst.put_u32(payload.len() as u32);
+        dst.put_slice(&checksum(&payload));
+        dst.put_slice(&payload);
+        Ok(())
+    }
+}
+
+impl Encoder<NetworkMessage> for MessageCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, message: NetworkMessage, dst: &mut BytesMut) -> Result<(), CodecError> {
+        self.encode(&message, dst)
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = NetworkMessage;
+    type Error = CodecError;
+
+    // Header fields are checked as soon as they arrive, so a bad or oversized
+    // frame is rejected without buffering its payload.
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<NetworkMessage>, CodecError> {
+        if src.len() < HEADER_BYTES {
+            return Ok(None);
+        }
+
+        let magic = [src[0], src[1], src[2], src[3]];
+        if magic != self.magic {
+            return Err(CodecError::BadMagic(magic));
+        }
+        let version = u16::from_be_bytes([src[4], src[5]]);
+        if version != PROTOCOL_VERSION {
+            return Err(CodecError::UnsupportedVersion(version));
+        }
+        let command = src[6];
+        let length = u32::from_be_bytes([src[7], src[8], src[9], src[10]]) as usize;
+        if length > self.maxPayloadBytes {
+            return Err(CodecError::Oversized(length));
+        }
+        let expectedChecksum = [src[11], src[12], src[13], src[14]];
+
+        if src.len() < HEADER_BYTES + length {
+            src.reserve(HEADER_BYTES + length - src.len());
+            return Ok(None);
+        }
+
+        src.advance(HEADER_BYTES);
+        let payload = src.split_to(length);
+        if checksum(&payload) != expectedChecksum {
+            return Err(CodecError::ChecksumMismatch);
+        }
+        let message: NetworkMessage = encoding::deserialize(&payload).map_err(CodecError::Malformed)?;
+        if message.command() != command {
+            return Err(CodecError::CommandMismatch {
+                frame: command,
+                payload: message.command(),
+            });
+        }
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> NetworkMessage {
+        NetworkMessage::ValidatorResponse("validator".to_string())
+    }
+
+    fn frame() -> BytesMut {
+        MessageCodec::default().encodeFrame(&message()).unwrap()
+    }
+
+    fn decodeAll(codec: &mut MessageCodec, buffer: &mut BytesMut) -> Vec<NetworkMessage> {
+        let mut messages = Vec::new();
+        while let Some(message) = codec.decode(buffer).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn test_round_trip_with_back_to_back_frames() {
+        let mut codec = MessageCodec::default();
+        let mut buffer = frame();
+        codec.encode(NetworkMessage::ValidatorRequest, &mut buffer).unwrap();
+
+        let messages = decodeAll(&mut codec, &mut buffer);
+        assert_eq!(messages.len(), 2);
+        assert!(matches!(&messages[0], NetworkMessage::ValidatorResponse(validator) if validator == "validator"));
+        assert!(matches!(messages[1], NetworkMessage::ValidatorRequest));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_truncated_frame_waits_for_more_bytes() {
+        let mut codec = MessageCodec::default();
+        let full = frame();
+
+        for cut in [0, HEADER_BYTES - 1, HEADER_BYTES, full.len() - 1] {
+            let mut partial = BytesMut::from(&full[..cut]);
+            assert!(codec.decode(&mut partial).unwrap().is_none());
+            assert_eq!(partial.len(), cut);
+        }
+
+        let mut partial = BytesMut::from(&full[..full.len() - 1]);
+        assert!(codec.decode_eof(&mut partial).is_err());
+
+        partial.extend_from_slice(&full[full.len() - 1..]);
+        assert!(codec.decode(&mut partial).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_oversized_frame_rejected() {
+        let mut small = MessageCodec::new(NETWORK_MAGIC, 8);
+        assert!(matches!(small.encodeFrame(&message()), Err(CodecError::Oversized(_))));
+
+        let mut header = BytesMut::from(&frame()[..HEADER_BYTES]);
+        assert!(matches!(small.decode(&mut header), Err(CodecError::Oversized(length)) if length > 8));
+
+        let mut huge = BytesMut::from(&frame()[..HEADER_BYTES]);
+        huge[7..11].copy_from_slice(&(MAX_PAYLOAD_BYTES as u32 + 1).to_be_bytes());
+        assert!(matches!(MessageCodec::default().decode(&mut huge), Err(CodecError::Oversized(_))));
+    }
+
+    #[test]
+    fn test_corrupted_frames_rejected() {
+        let mut codec = MessageCodec::default();
+
+        let mut wrongMagic = frame();
+        wrongMagic[0] ^= 0xff;
+        assert!(matches!(codec.decode(&mut wrongMagic), Err(CodecError::BadMagic(_))));
+
+        let mut wrongVersion = frame();
+        wrongVersion[5] = 9;
+        assert!(matches!(codec.decode(&mut wrongVersion), Err(CodecError::UnsupportedVersion(9))));
+
+        let mut flipped = frame();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 0x01;
+        assert!(matches!(codec.decode(&mut flipped), Err(CodecError::ChecksumMismatch)));
+
+        let mut relabeled = frame();
+        relabeled[6] = NetworkMessage::ValidatorRequest.command();
+        assert!(matches!(codec.decode(&mut relabeled), Err(CodecError::CommandMismatch { .. })));
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use futures::{SinkExt, StreamExt};
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio_util::codec::Framed;
+use chrono::Utc;
+use log::{debug, info, warn};
+use crate::network::banlist;
+use crate::network::codec::{CodecError, MessageCodec, PROTOCOL_VERSION};
+use crate::network::message::{NetworkMessage, VersionData};
+
+pub const OUTBOUND_QUEUE_SIZE: usize = 256;
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Error, Debug)]
+pub enum HandshakeError {
+    #[error("Peer speaks protocol version {0}")]
+    VersionMismatch(u16),
+
+    #[error("Peer is on chain {0}")]
+    WrongChain(u64),
+
+    #[error("Connected to ourselves")]
+    SelfConnection,
+
+    #[error("Expected {expected}, got command {actual}")]
+    Unexpected { expected: &'static str, actual: u8 },
+
+    #[error("Handshake timed out")]
+    Timeout,
+
+    #[error("Connection closed during handshake")]
+    Closed,
+
+    #[error(transparent)]
+    Codec(#[from] CodecError),
+}
+
+// Handle to a live, handshaken connection. Dropping the last copy of
+// `outbound` makes the connection flush and close gracefully.
+#[derive(Debug, Clone)]
+pub struct PeerConnection {
+    pub id: u64,
+    pub outbound: mpsc::Sender<NetworkMessage>,
+    pub dialed: bool,
+    // Where the connection comes from as seen on the socket, which unlike
+    // the announced address the peer can't pick.
+    pub remote: SocketAddr,
+}
+
+// Peers are identified by the listen address they announce in their
+// version message, not by the socket address they connect from, unless
+// that address is on another host; see `peerKey`.
+#[derive(Debug)]
+pub enum PeerEvent {
+    Connected {
+        address: SocketAddr,
+        version: VersionData,
+        connection: PeerConnection,
+    },
+    Message {
+        address: SocketAddr,
+        message: NetworkMessage,
+    },
+    // The peer sent a frame that failed to decode; the connection is dropped.
+    Malformed {
+        address: SocketAddr,
+        error: CodecError,
+    },
+    Disconnected {
+        address: SocketAddr,
+        connectionId: u64,
+    },
+    // A dialer gave up on `address` after its allowed attempts.
+    Unreachable {
+        address: SocketAddr,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SessionEnd {
+    // Our side dropped the outbound queue; the connection was closed cleanly.
+    Closed,
+    // The peer went away or the stream broke.
+    Lost,
+}
+
+pub fn backoff(attempt: u32) -> Duration {
+    INITIAL_BACKOFF
+        .checked_mul(1 << attempt.min(16))
+        .map_or(MAX_BACKOFF, |delay| delay.min(MAX_BACKOFF))
+}
+
+// Connections from banned IPs are dropped before the handshake. Local peers
+// are banned by the listen address they announce, so only the node can tell
+// once they have.
+pub async fn acceptConnections(
+    listener: TcpListener,
+    local: watch::Receiver<VersionData>,
+    bans: watch::Receiver<HashMap<SocketAddr, i64>>,
+    events: mpsc::Sender<PeerEvent>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, socketAddress)) => {
+                let key = banlist::ban_key(socketAddress.ip(), socketAddress);
+                if banlist::is_banned(&bans.borrow(), &key, Utc::now().timestamp()) {
+                    continue;
+                }
+                let local = local.clone();
+                let events = events.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = runConnection(stream, socketAddress, false, &local, &events).await {
+                        debug!("Handshake with {} failed: {}", socketAddress, e);
+                    }
+                });
+            }
+            Err(e) => warn!("Failed to accept connection: {}", e),
+        }
+    }
+}
+
+// Keeps a connection to `target` up, reconnecting with exponential backoff
+// until our side closes it or the node goes away. With `maxAttempts`, gives
+// up after that many consecutive failed attempts.
+pub async fn maintainConnection(
+    target: SocketAddr,
+    local: watch::Receiver<VersionData>,
+    events: mpsc::Sender<PeerEvent>,
+    maxAttempts: Option<u32>,
+) {
+    let mut attempt = 0;
+    loop {
+        match TcpStream::connect(target).await {
+            Ok(stream) => match runConnection(stream, target, true, &local, &events).await {
+                Ok(SessionEnd::Closed) => return,
+                Ok(SessionEnd::Lost) => attempt = 0,
+                Err(e) => debug!("Handshake with {} failed: {}", target, e),
+            },
+            Err(e) => debug!("Failed to connect to {}: {}", target, e),
+        }
+        if events.is_closed() {
+            return;
+        }
+        if maxAttempts.is_some_and(|max| attempt + 1 >= max) {
+            let _ = events.send(PeerEvent::Unreachable { address: target }).await;
+            return;
+        }
+        tokio::time::sleep(backoff(attempt)).await;
+        attempt += 1;
+    }
+}
+
+// Who a connection is from. Anyone can announce any address, so an inbound
+// connection only speaks for the announced one, and may take over its
+// peer, if it comes from the same IP. Otherwise it is its own peer, known
+// by the socket address it connects from.
+pub fn peerKey(announced: SocketAddr, remote: SocketAddr, dialed: bool) -> SocketAddr {
+    if dialed || announced.ip() == remote.ip() {
+        announced
+    } else {
+        remote
+    }
+}
+
+async fn runConnection(
+    stream: TcpStream,
+    remote: SocketAddr,
+    dialed: bool,
+    local: &watch::Receiver<VersionData>,
+    events: &mpsc::Sender<PeerEvent>,
+) -> Result<SessionEnd, HandshakeError> {
+    let mut framed = Framed::new(stream, MessageCodec::default());
+    let localVersion = local.borrow().clone();
+    let version = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake(&mut framed, &localVersion))
+        .await
+        .map_err(|_| HandshakeError::Timeout)??;
+
+    let address = peerKey(version.address, remote, dialed);
+    let (outbound, mut queue) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+    let connection = PeerConnection {
+        id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+        outbound,
+        dialed,
+        remote,
+    };
+    let connectionId = connection.id;
+    let connected = PeerEvent::Connected {
+        address,
+        version,
+        connection,
+    };
+    if events.send(connected).await.is_err() {
+        return Ok(SessionEnd::Closed);
+    }
+
+    let end = loop {
+        tokio::select! {
+            frame = framed.next() => match frame {
+                Some(Ok(message)) => {
+                    if events.send(PeerEvent::Message { address, message }).await.is_err() {
+                        break SessionEnd::Closed;
+                    }
+                }
+                Some(Err(CodecError::Io(e))) => {
+                    info!("Dropping connection to {}: {}", address, e);
+                    break SessionEnd::Lost;
+                }
+                Some(Err(error)) => {
+                    info!("Dropping connection to {}: {}", address, error);
+                    let _ = events.send(PeerEvent::Malformed { address, error }).await;
+                    break SessionEnd::Lost;
+                }
+                None => break SessionEnd::Lost,
+            },
+            outgoing = queue.recv() => match outgoing {
+                Some(message) => {
+                    if let Err(e) = framed.send(message).await {
+                        debug!("Error sending message to {}: {}", address, e);
+                        break SessionEnd::Lost;
+                    }
+                }
+                None => {
+                    let _ = SinkExt::<NetworkMessage>::close(&mut framed).await;
+                    break SessionEnd::Closed;
+                }
+            },
+        }
+    };
+
+    let _ = events.send(PeerEvent::Disconnected { address, connectionId }).await;
+    Ok(end)
+}
+
+// Both sides send their version, check the other's, then acknowledge it
+// with a verack.
+async fn handshake(
+    framed: &mut Framed<TcpStream, MessageCodec>,
+    local: &VersionData,
+) -> Result<VersionData, HandshakeError> {
+    framed.send(NetworkMessage::Version(local.clone())).await?;
+    let remote = match framed.next().await {
+        Some(Ok(NetworkMessage::Version(version))) => version,
+        Some(Ok(other)) => {
+            return Err(HandshakeError::Unexpected {
+                expected: "version",
+                actual: other.command(),
+            })
+        }
+        Some(Err(e)) => return Err(e.into()),
+        None => return Err(HandshakeError::Closed),
+    };
+
+    if remote.protocolVersion != PROTOCOL_VERSION {
+        return Err(HandshakeError::VersionMismatch(remote.protocolVersion));
+    }
+    if remote.chainId != local.chainId {
+        return Err(HandshakeError::WrongChain(remote.chainId));
+    }
+    if remote.address == local.address {
+        return Err(HandshakeError::SelfConnection);
+    }
+
+    framed.send(NetworkMessage::Verack).await?;
+    match framed.next().await {
+        Some(Ok(NetworkMessage::Verack)) => Ok(remote),
+        Some(Ok(other)) => Err(HandshakeError::Unexpected {
+            expected: "verack",
+            actual: other.command(),
+        }),
+        Some(Err(e)) => Err(e.into()),
+        None => Err(HandshakeError::Closed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpSocket;
+    use crate::network::message::DEFAULT_CHAIN_ID;
+
+    fn version(address: SocketAddr, chainId: u64) -> VersionData {
+        VersionData {
+            protocolVersion: PROTOCOL_VERSION,
+            chainId,
+            bestHeight: 0,
+            address,
+            stake: 0,
+        }
+    }
+
+    fn noBans() -> watch::Receiver<HashMap<SocketAddr, i64>> {
+        watch::channel(HashMap::new()).1
+    }
+
+    async fn nextEvent(events: &mut mpsc::Receiver<PeerEvent>) -> PeerEvent {
+        tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("Timed out waiting for a peer event")
+            .expect("Event channel closed")
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_cap() {
+        assert_eq!(backoff(0), INITIAL_BACKOFF);
+        assert_eq!(backoff(1), 2 * INITIAL_BACKOFF);
+        assert_eq!(backoff(3), 8 * INITIAL_BACKOFF);
+        assert_eq!(backoff(10), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_inbound_peer_keyed_by_socket_unless_same_ip() {
+        let announced: SocketAddr = "10.0.0.1:8333".parse().unwrap();
+        let sameHost: SocketAddr = "10.0.0.1:50000".parse().unwrap();
+        let otherHost: SocketAddr = "10.0.0.2:50000".parse().unwrap();
+
+        assert_eq!(peerKey(announced, sameHost, false), announced);
+        assert_eq!(peerKey(announced, otherHost, false), otherHost);
+        assert_eq!(peerKey(announced, otherHost, true), announced);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_exchanges_versions() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let serverAddress = listener.local_addr().unwrap();
+        let clientAddress: SocketAddr = "127.0.0.1:1".parse().unwrap();
+
+        let (serverEvents, mut serverReceiver) = mpsc::channel(8);
+        let (_, serverVersion) = watch::channel(version(serverAddress, DEFAULT_CHAIN_ID));
+        tokio::spawn(acceptConnections(listener, serverVersion, noBans(), serverEvents));
+
+        let (clientEvents, mut clientReceiver) = mpsc::channel(8);
+        let (_, clientVersion) = watch::channel(version(clientAddress, DEFAULT_CHAIN_ID));
+        let stream = TcpStream::connect(serverAddress).await.unwrap();
+        let client = tokio::spawn(async move { runConnection(stream, serverAddress, true, &clientVersion, &clientEvents).await });
+
+        let PeerEvent::Connected { address, connection, .. } = nextEvent(&mut clientReceiver).await else {
+            panic!("Expected the client to connect");
+        };
+        assert_eq!(address, serverAddress);
+        assert!(connection.dialed);
+        let PeerEvent::Connected { address, connection: serverConnection, .. } = nextEvent(&mut serverReceiver).await
+        else {
+            panic!("Expected the server to connect");
+        };
+        assert_eq!(address, clientAddress);
+        assert!(!serverConnection.dialed);
+
+        connection.outbound.send(NetworkMessage::ValidatorRequest).await.unwrap();
+        assert!(matches!(
+            nextEvent(&mut serverReceiver).await,
+            PeerEvent::Message { message: NetworkMessage::ValidatorRequest, .. }
+        ));
+
+        drop(connection);
+        assert_eq!(client.await.unwrap().unwrap(), SessionEnd::Closed);
+        assert!(matches!(nextEvent(&mut serverReceiver).await, PeerEvent::Disconnected { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_other_chain() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let serverAddress = listener.local_addr().unwrap();
+        let (serverEvents, mut serverReceiver) = mpsc::channel(8);
+        let (_, serverVersion) = watch::channel(version(serverAddress, DEFAULT_CHAIN_ID));
+        tokio::spawn(acceptConnections(listener, serverVersion, noBans(), serverEvents));
+
+        let (clientEvents, _clientReceiver) = mpsc::channel(8);
+        let (_, clientVersion) = watch::channel(version("127.0.0.1:1".parse().unwrap(), DEFAULT_CHAIN_ID + 1));
+        let stream = TcpStream::connect(serverAddress).await.unwrap();
+        let result = runConnection(stream, serverAddress, true, &clientVersion, &clientEvents).await;
+
+        assert!(matches!(result, Err(HandshakeError::WrongChain(DEFAULT_CHAIN_ID))));
+        assert!(tokio::time::timeout(Duration::from_millis(200), serverReceiver.recv()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_frame_reported() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let serverAddress = listener.local_addr().unwrap();
+        let clientAddress: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let (serverEvents, mut serverReceiver) = mpsc::channel(8);
+        let (_, serverVersion) = watch::channel(version(serverAddress, DEFAULT_CHAIN_ID));
+        tokio::spawn(acceptConnections(listener, serverVersion, noBans(), serverEvents));
+
+        let mut client = Framed::new(TcpStream::connect(serverAddress).await.unwrap(), MessageCodec::default());
+        handshake(&mut client, &version(clientAddress, DEFAULT_CHAIN_ID)).await.unwrap();
+        // Holding the connection keeps the server from closing it.
+        let connected = nextEvent(&mut serverReceiver).await;
+        assert!(matches!(connected, PeerEvent::Connected { .. }));
+
+        let mut frame = MessageCodec::default().encodeFrame(&NetworkMessage::ValidatorRequest).unwrap();
+        let last = frame.len() - 1;
+        frame[last] ^= 0x01;
+        client.get_mut().write_all(&frame).await.unwrap();
+
+        assert!(matches!(
+            nextEvent(&mut serverReceiver).await,
+            PeerEvent::Malformed { address, error: CodecError::ChecksumMismatch } if address == clientAddress
+        ));
+        assert!(matches!(nextEvent(&mut serverReceiver).await, PeerEvent::Disconnected { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_banned_peer_refused_before_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let serverAddress = listener.local_addr().unwrap();
+        // Bound up front so we know the address the server will see.
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let clientAddress = socket.local_addr().unwrap();
+
+        let (serverEvents, mut serverReceiver) = mpsc::channel(8);
+        let (_, serverVersion) = watch::channel(version(serverAddress, DEFAULT_CHAIN_ID));
+        let banned = HashMap::from([(clientAddress, Utc::now().timestamp() + 60)]);
+        tokio::spawn(acceptConnections(listener, serverVersion, watch::channel(banned).1, serverEvents));
+
+        let (clientEvents, _clientReceiver) = mpsc::channel(8);
+        let (_, clientVersion) = watch::channel(version(clientAddress, DEFAULT_CHAIN_ID));
+        let stream = socket.connect(serverAddress).await.unwrap();
+        let result = runConnection(stream, serverAddress, true, &clientVersion, &clientEvents).await;
+
+        assert!(result.is_err());
+        assert!(tokio::time::timeout(Duration::from_millis(200), serverReceiver.recv()).await.is_err());
+    }
+}
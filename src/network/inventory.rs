@@ -0,0 +1,73 @@
+use std::collections::{HashSet, VecDeque};
+use serde::{Serialize, Deserialize};
+
+pub const MAX_INV_PER_MESSAGE: usize = 1000;
+// Items remembered per peer; the oldest are forgotten first.
+pub const MAX_KNOWN_INVENTORY: usize = 5000;
+pub const INVENTORY_REQUEST_TIMEOUT_SECS: i64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InventoryItem {
+    Transaction(String),
+    Block(String),
+}
+
+// Bounded set of inventory items, used to remember what a peer already has
+// so it isn't announced or sent to it again.
+#[derive(Debug, Clone, Default)]
+pub struct KnownInventory {
+    items: HashSet<InventoryItem>,
+    order: VecDeque<InventoryItem>,
+}
+
+impl KnownInventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn contains(&self, item: &InventoryItem) -> bool {
+        self.items.contains(item)
+    }
+
+    // Returns whether `item` was new.
+    pub fn insert(&mut self, item: InventoryItem) -> bool {
+        if !self.items.insert(item.clone()) {
+            return false;
+        }
+        self.order.push_back(item);
+        if self.order.len() > MAX_KNOWN_INVENTORY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.items.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_items_forgotten_first() {
+        let mut known = KnownInventory::new();
+        for index in 0..=MAX_KNOWN_INVENTORY {
+            assert!(known.insert(InventoryItem::Transaction(index.to_string())));
+        }
+        assert!(!known.insert(InventoryItem::Transaction("1".to_string())));
+        assert!(known.insert(InventoryItem::Block("1".to_string())));
+
+        assert_eq!(known.len(), MAX_KNOWN_INVENTORY);
+        assert!(!known.contains(&InventoryItem::Transaction("0".to_string())));
+        assert!(!known.contains(&InventoryItem::Transaction("1".to_string())));
+        assert!(known.contains(&InventoryItem::Transaction(MAX_KNOWN_INVENTORY.to_string())));
+    }
+}
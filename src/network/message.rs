@@ -1,34 +1,145 @@
+use std::net::SocketAddr;
 use serde::{Serialize, Deserialize};
+use crate::crypto::SignatureAlgorithm;
+use crate::blockchain::{Block, BlockHeader};
+use crate::consensus::DoubleSignEvidence;
+use crate::network::inventory::InventoryItem;
+use crate::utils::{hashing, encoding};
 
-#[derive(Debug, Serialize, Deserialize)]
+pub const DEFAULT_CHAIN_ID: u64 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NetworkMessage {
     Transaction(TransactionData),
-    Block(BlockData),
-    StakeUpdate(StakeUpdateData),
+    Block(Block),
     ValidatorRequest,
     ValidatorResponse(String),
+    Version(VersionData),
+    Verack,
+    GetAddr,
+    Addr(Vec<SocketAddr>),
+    // Block locator: hashes from our tip back towards genesis.
+    GetHeaders(Vec<String>),
+    Headers(Vec<BlockHeader>),
+    GetBlocks(Vec<String>),
+    Inv(Vec<InventoryItem>),
+    GetData(Vec<InventoryItem>),
+    Evidence(Box<DoubleSignEvidence>),
+}
+
+impl NetworkMessage {
+    // Command byte carried in the frame header, see `network::codec`.
+    pub fn command(&self) -> u8 {
+        match self {
+            NetworkMessage::Transaction(_) => 1,
+            NetworkMessage::Block(_) => 2,
+            NetworkMessage::ValidatorRequest => 4,
+            NetworkMessage::ValidatorResponse(_) => 5,
+            NetworkMessage::Version(_) => 6,
+            NetworkMessage::Verack => 7,
+            NetworkMessage::GetAddr => 8,
+            NetworkMessage::Addr(_) => 9,
+            NetworkMessage::GetHeaders(_) => 10,
+            NetworkMessage::Headers(_) => 11,
+            NetworkMessage::GetBlocks(_) => 12,
+            NetworkMessage::Inv(_) => 13,
+            NetworkMessage::GetData(_) => 14,
+            NetworkMessage::Evidence(_) => 15,
+        }
+    }
+}
+
+// Bonding locks `amount` from the sender's balance as its own stake,
+// unbonding starts releasing it, and withdrawing returns released funds to
+// the balance. Delegating locks `amount` behind the receiving validator
+// instead, and undelegating starts releasing it; a withdrawal naming that
+// validator collects it. Commission sets the share of its block earnings,
+// as a percent in `amount`, the sender keeps before paying its delegators,
+// from the next epoch on.
+// Evidence reports the receiver for signing two blocks in one slot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionKind {
+    #[default]
+    Transfer,
+    Bond,
+    Unbond,
+    Withdraw,
+    Evidence,
+    Delegate,
+    Undelegate,
+    Commission,
+}
+
+impl TransactionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionKind::Transfer => "transfer",
+            TransactionKind::Bond => "bond",
+            TransactionKind::Unbond => "unbond",
+            TransactionKind::Withdraw => "withdraw",
+            TransactionKind::Evidence => "evidence",
+            TransactionKind::Delegate => "delegate",
+            TransactionKind::Undelegate => "undelegate",
+            TransactionKind::Commission => "commission",
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TransactionData {
+    pub kind: TransactionKind,
     pub sender: String,
     pub receiver: String,
     pub amount: u64,
+    pub fee: u64,
+    pub nonce: u64,
+    pub chainId: u64,
+    pub algorithm: SignatureAlgorithm,
+    pub publicKey: Vec<u8>,
     pub signature: Vec<u8>,
+    // Set on evidence transactions only.
+    pub evidence: Option<Box<DoubleSignEvidence>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct BlockData {
-    pub index: u64,
-    pub previousHash: String,
-    pub timestamp: i64,
-    pub merkleRoot: String,
-    pub nonce: u64,
-    pub transactions: Vec<TransactionData>,
+impl TransactionData {
+    pub fn calculateHash(&self) -> String {
+        let transactionBytes = encoding::serialize(self).expect("Failed to serialize transaction");
+        hashing::sha256(&transactionBytes)
+    }
+
+    // What the sender's balance pays. Unbonding, undelegating and
+    // withdrawing take the amount from stake, so only the fee comes from the
+    // balance.
+    pub fn balanceCost(&self) -> Option<u64> {
+        match self.kind {
+            TransactionKind::Transfer | TransactionKind::Bond | TransactionKind::Delegate => {
+                self.amount.checked_add(self.fee)
+            }
+            TransactionKind::Unbond
+            | TransactionKind::Withdraw
+            | TransactionKind::Evidence
+            | TransactionKind::Undelegate
+            | TransactionKind::Commission => Some(self.fee),
+        }
+    }
+
+    // Transactions that can only act on the sender's own stake, so must be
+    // addressed to the sender.
+    pub fn actsOnOwnStake(&self) -> bool {
+        matches!(
+            self.kind,
+            TransactionKind::Bond | TransactionKind::Unbond | TransactionKind::Commission
+        )
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct StakeUpdateData {
-    pub validator: String,
-    pub amount: u64,
-}
\ No newline at end of file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VersionData {
+    pub protocolVersion: u16,
+    pub chainId: u64,
+    // Number of blocks on the sender's chain.
+    pub bestHeight: u64,
+    // Address the sender accepts connections on.
+    pub address: SocketAddr,
+    pub stake: u64,
+}
@@ -1,7 +1,18 @@
+pub mod addrbook;
+pub mod banlist;
+pub mod codec;
+pub mod connection;
+pub mod inventory;
 pub mod message;
 pub mod peer;
 pub mod node;
+pub mod sync;
 
+pub use addrbook::AddressBook;
+pub use banlist::BanList;
+pub use codec::{CodecError, MessageCodec};
+pub use inventory::{InventoryItem, KnownInventory};
 pub use message::NetworkMessage;
 pub use peer::Peer;
-pub use node::Node;
\ No newline at end of file
+pub use node::Node;
+pub use sync::ChainSync;
\ No newline at end of file
@@ -1,11 +1,145 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use tokio::sync::mpsc;
-use serde::{Serialize, Deserialize};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
 use chrono::Utc;
+use thiserror::Error;
+use log::{debug, error, info, warn};
+use crate::network::addrbook::{AddressBook, MAX_ADDR_PER_MESSAGE, MAX_NEW_ADDR_PER_MESSAGE, MAX_NEW_ADDR_PER_PEER};
+use crate::network::banlist::{self, BanList, BAN_THRESHOLD, DEFAULT_BAN_SECS};
+use crate::network::codec::PROTOCOL_VERSION;
+use crate::network::connection::{self, PeerEvent};
+use crate::network::inventory::{InventoryItem, KnownInventory, INVENTORY_REQUEST_TIMEOUT_SECS, MAX_INV_PER_MESSAGE};
 use crate::network::peer::Peer;
-use crate::network::message::{NetworkMessage, BlockData, TransactionData};
-use crate::utils::{hashing, encoding};
+use crate::network::sync::{ChainSync, SyncError, MAX_BLOCKS_PER_REQUEST, MAX_HEADERS_PER_MESSAGE};
+use crate::blockchain::{Block, BlockHeader, BlockTree};
+use crate::blockchain::tree::FINALITY_DEPTH;
+use crate::consensus::{DoubleSignEvidence, ProofOfStake, ProposerError, SignedHeader, SigningRecord, ValidatorSet};
+use crate::wallet::KeyPair;
+use crate::network::message::{NetworkMessage, TransactionData, TransactionKind, VersionData, DEFAULT_CHAIN_ID};
+use crate::ledger::{BlockUndo, Ledger, LedgerError};
+use crate::mempool::{Mempool, MempoolError};
+use crate::mempool::pool::{transaction_size, DEFAULT_EXPIRY_SECS, DEFAULT_MAX_BYTES};
+use crate::wallet::wallet::Wallet;
+use crate::storage::{Database, StorageError};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TransactionError {
+    #[error("Sender and receiver must be set")]
+    MissingParty,
+
+    #[error("Amount must be greater than zero")]
+    ZeroAmount,
+
+    #[error("Transaction is for chain {0}")]
+    WrongChain(u64),
+
+    #[error("Transaction is not signed")]
+    Unsigned,
+
+    #[error("Sender address does not match the attached public key")]
+    AddressMismatch,
+
+    #[error("Invalid signature")]
+    InvalidSignature,
+
+    #[error("Bonding, unbonding and commission must be sent to the sender's own address")]
+    ForeignStake,
+
+    #[error("Evidence must come in an evidence transaction against its offender, with no amount")]
+    MalformedEvidence,
+
+    #[error(transparent)]
+    Mempool(#[from] MempoolError),
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum BlockError {
+    #[error("Expected height {expected}, got {actual}")]
+    WrongHeight { expected: u64, actual: u64 },
+
+    #[error("Block at height 0 must not have a parent")]
+    InvalidGenesis,
+
+    #[error("Parent {0} is unknown")]
+    UnknownParent(String),
+
+    #[error("Switching to this block would revert {0} blocks, past finality")]
+    Finalized(usize),
+
+    #[error("Merkle root does not match the transactions")]
+    InvalidMerkleRoot,
+
+    #[error("Transactions take {size} bytes, over the {limit} byte block limit")]
+    TooLarge { size: usize, limit: usize },
+
+    #[error("Slot {slot} does not follow the parent's slot {parent}")]
+    SlotNotAfterParent { slot: u64, parent: u64 },
+
+    #[error("Slot {0} has not started yet")]
+    FutureSlot(u64),
+
+    #[error("Block seed does not continue the parent's beacon")]
+    WrongSeed,
+
+    #[error(transparent)]
+    Proposer(#[from] ProposerError),
+
+    #[error("Block is not signed by its producer")]
+    InvalidSignature,
+
+    #[error(transparent)]
+    Transaction(#[from] TransactionError),
+
+    #[error(transparent)]
+    Ledger(#[from] LedgerError),
+}
+
+// Misbehavior score added for each offence; BAN_THRESHOLD gets a peer banned.
+pub const INVALID_TRANSACTION_PENALTY: u32 = 10;
+pub const INVALID_BLOCK_PENALTY: u32 = 100;
+pub const MALFORMED_MESSAGE_PENALTY: u32 = 100;
+pub const UNCONNECTED_HEADERS_PENALTY: u32 = 20;
+pub const ORPHAN_BLOCK_PENALTY: u32 = 5;
+pub const RATE_LIMIT_PENALTY: u32 = 1;
+
+impl TransactionError {
+    // Pool rejections such as a stale nonce or a spent balance can happen
+    // to honest peers relaying a transaction we saw first, so they cost
+    // nothing.
+    pub fn penalty(&self) -> u32 {
+        match self {
+            TransactionError::Mempool(_) => 0,
+            _ => INVALID_TRANSACTION_PENALTY,
+        }
+    }
+}
+
+impl BlockError {
+    pub fn penalty(&self) -> u32 {
+        match self {
+            // A block for a slot that hasn't started may just be clock skew.
+            BlockError::UnknownParent(_) | BlockError::Finalized(_) | BlockError::FutureSlot(_) => 0,
+            _ => INVALID_BLOCK_PENALTY,
+        }
+    }
+}
+
+pub const DEFAULT_TARGET_OUTBOUND: usize = 8;
+// Dial attempts made for an address learned through gossip before it is
+// reported unreachable.
+pub const MAX_DIAL_ATTEMPTS: u32 = 3;
+pub const PEER_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(5);
+// How often a producing node checks whether the current slot is its own.
+pub const PROPOSER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// While our tip is further than this behind the current slot we are still
+// catching up, and the blocks we take in are old news to our peers. Blocks
+// for slots past the next are refused, so the tip can't be pushed ahead.
+pub const CATCH_UP_SLOTS: u64 = 8;
 
 pub struct Node {
     pub address: SocketAddr,
@@ -13,8 +147,45 @@ pub struct Node {
     pub incomingMessages: mpsc::Receiver<NetworkMessage>,
     pub outgoingMessages: mpsc::Sender<NetworkMessage>,
     pub blockSizeLimit: usize,
-    pub transactionPool: Vec<TransactionData>,
-    pub blockchain: Vec<BlockData>,
+    pub mempool: Mempool,
+    // The active chain, the heaviest branch of `blockTree`.
+    pub blockchain: Vec<Block>,
+    pub blockTree: BlockTree,
+    pub consensus: ProofOfStake,
+    pub ledger: Ledger,
+    pub chainId: u64,
+    // The producer address of blocks we build; with stake configured it
+    // must be derived from `validatorKey`, which signs them.
+    pub rewardAddress: String,
+    pub validatorKey: Option<KeyPair>,
+    pub signingRecord: SigningRecord,
+    pub stake: u64,
+    pub addressBook: AddressBook,
+    pub banList: BanList,
+    // Where the active chain is saved, once `loadChain` has replayed it.
+    chainStore: Option<Database>,
+    // Misbehavior scores by ban key, so a remote peer can't shed its score
+    // by reconnecting under another announced address.
+    misbehavior: HashMap<SocketAddr, u32>,
+    // Copy of the ban list the listener checks before handshaking.
+    bans: watch::Sender<HashMap<SocketAddr, i64>>,
+    pub targetOutbound: usize,
+    pub sync: ChainSync,
+    pub peerEvents: mpsc::Receiver<PeerEvent>,
+    peerEventSender: mpsc::Sender<PeerEvent>,
+    localVersion: watch::Sender<VersionData>,
+    dialers: HashMap<SocketAddr, JoinHandle<()>>,
+    blockIndex: HashMap<String, u64>,
+    undoLog: Vec<BlockUndo>,
+    // Transactions we accepted, so announcements of ones that have since
+    // left the mempool aren't fetched again.
+    seenTransactions: KnownInventory,
+    requestedInventory: HashMap<InventoryItem, i64>,
+    // First block we saw from each producer in each slot, to catch a
+    // second, and evidence already relayed, each with its block height.
+    // Both are forgotten below the finality depth.
+    proposals: HashMap<(String, u64), (String, u64)>,
+    seenEvidence: HashMap<String, u64>,
 }
 
 impl Node {
@@ -23,15 +194,50 @@ impl Node {
         receiver: mpsc::Receiver<NetworkMessage>,
         sender: mpsc::Sender<NetworkMessage>,
         blockSizeLimit: usize,
+        ledger: Ledger,
     ) -> Self {
+        let (peerEventSender, peerEvents) = mpsc::channel(1024);
+        let (bans, _) = watch::channel(HashMap::new());
+        let (localVersion, _) = watch::channel(VersionData {
+            protocolVersion: PROTOCOL_VERSION,
+            chainId: DEFAULT_CHAIN_ID,
+            bestHeight: 0,
+            address,
+            stake: 0,
+        });
         Self {
             address,
             peers: HashMap::new(),
             incomingMessages: receiver,
             outgoingMessages: sender,
             blockSizeLimit,
-            transactionPool: Vec::new(),
+            mempool: Mempool::new(DEFAULT_MAX_BYTES, DEFAULT_EXPIRY_SECS),
             blockchain: Vec::new(),
+            blockTree: BlockTree::new(),
+            consensus: ProofOfStake::new(ledger.validator_set().clone()),
+            ledger,
+            chainId: DEFAULT_CHAIN_ID,
+            rewardAddress: String::new(),
+            validatorKey: None,
+            signingRecord: SigningRecord::new(),
+            stake: 0,
+            addressBook: AddressBook::new(),
+            banList: BanList::new(),
+            chainStore: None,
+            misbehavior: HashMap::new(),
+            bans,
+            targetOutbound: DEFAULT_TARGET_OUTBOUND,
+            sync: ChainSync::new(),
+            peerEvents,
+            peerEventSender,
+            localVersion,
+            dialers: HashMap::new(),
+            blockIndex: HashMap::new(),
+            undoLog: Vec::new(),
+            seenTransactions: KnownInventory::new(),
+            requestedInventory: HashMap::new(),
+            proposals: HashMap::new(),
+            seenEvidence: HashMap::new(),
         }
     }
 
@@ -39,123 +245,2108 @@ impl Node {
         self.peers.insert(peer.address, peer);
     }
 
-    pub async fn handleIncoming(&mut self) {
-        while let Some(message) = self.incomingMessages.recv().await {
-            match message {
-                NetworkMessage::Transaction(tx) => self.processTransaction(tx).await,
-                NetworkMessage::Block(block) => self.processBlock(block).await,
-                _ => {}
+    // Binds `address` and accepts peer connections on it. Binding port 0
+    // picks a free port and updates `address` accordingly.
+    pub async fn listen(&mut self) -> std::io::Result<()> {
+        let listener = TcpListener::bind(self.address).await?;
+        self.address = listener.local_addr()?;
+        self.refreshVersion();
+        self.publishBans();
+        tokio::spawn(connection::acceptConnections(
+            listener,
+            self.localVersion.subscribe(),
+            self.bans.subscribe(),
+            self.peerEventSender.clone(),
+        ));
+        Ok(())
+    }
+
+    // Keeps an outbound connection to `address` up until `disconnect` is
+    // called. Does nothing if we are already connected or dialing.
+    pub fn connect(&mut self, address: SocketAddr) {
+        self.dial(address, None);
+    }
+
+    fn dial(&mut self, address: SocketAddr, maxAttempts: Option<u32>) {
+        let peer = self.peers.entry(address).or_insert_with(|| Peer::new(address, false, 0));
+        let dialing = self.dialers.get(&address).is_some_and(|dialer| !dialer.is_finished());
+        if peer.is_connected() || dialing {
+            return;
+        }
+        self.refreshVersion();
+        let dialer = tokio::spawn(connection::maintainConnection(
+            address,
+            self.localVersion.subscribe(),
+            self.peerEventSender.clone(),
+            maxAttempts,
+        ));
+        self.dialers.insert(address, dialer);
+    }
+
+    // Tops outbound connections up to `targetOutbound` from the address book.
+    pub fn maintainOutbound(&mut self) {
+        self.dialers.retain(|_, dialer| !dialer.is_finished());
+        let missing = self.targetOutbound.saturating_sub(self.dialers.len());
+        if missing == 0 {
+            return;
+        }
+        let mut busy: HashSet<SocketAddr> = self
+            .peers
+            .values()
+            .filter(|peer| peer.is_connected())
+            .map(|peer| peer.address)
+            .collect();
+        busy.extend(self.dialers.keys());
+        busy.insert(self.address);
+        let now = Utc::now().timestamp();
+        let candidates: Vec<SocketAddr> = self
+            .addressBook
+            .candidates(&busy)
+            .into_iter()
+            .filter(|address| !self.banList.is_banned(&banlist::ban_key(address.ip(), *address), now))
+            .take(missing)
+            .collect();
+        for address in candidates {
+            self.dial(address, Some(MAX_DIAL_ATTEMPTS));
+        }
+    }
+
+    pub fn sendTo(&self, address: SocketAddr, message: NetworkMessage) {
+        let Some(connection) = self.peers.get(&address).and_then(|peer| peer.connection.as_ref()) else {
+            return;
+        };
+        if let Err(TrySendError::Full(_)) = connection.outbound.try_send(message) {
+            warn!("Outbound queue to {} is full, dropping message", address);
+        }
+    }
+
+    pub fn connectPeers(&mut self) {
+        let addresses: Vec<SocketAddr> = self.peers.keys().copied().collect();
+        for address in addresses {
+            self.connect(address);
+        }
+    }
+
+    // Closes the connection after flushing its outbound queue and stops
+    // redialing the peer.
+    pub fn disconnect(&mut self, address: SocketAddr) {
+        let connected = self
+            .peers
+            .get_mut(&address)
+            .and_then(|peer| peer.connection.take())
+            .is_some();
+        if let Some(dialer) = self.dialers.remove(&address) {
+            if !connected {
+                dialer.abort();
             }
         }
     }
 
-    async fn processTransaction(&mut self, tx: TransactionData) {
-        if self.validateTransaction(&tx) {
-            self.transactionPool.push(tx);
+    // Drops every peer sharing the ban key of `address`, stops dialing them
+    // and refuses the key until `until`.
+    pub fn ban(&mut self, address: SocketAddr, until: i64) {
+        let Some(key) = self.banKey(address) else {
+            return;
+        };
+        info!("Banning {} until {}", key, until);
+        self.banList.ban(key, until);
+        self.publishBans();
+        self.misbehavior.remove(&key);
+        let banned: Vec<SocketAddr> = self
+            .peers
+            .keys()
+            .copied()
+            .filter(|peer| self.banKey(*peer) == Some(key))
+            .collect();
+        for peer in banned {
+            self.peers.remove(&peer);
+            if let Some(dialer) = self.dialers.remove(&peer) {
+                dialer.abort();
+            }
+            self.sync.peerGone(peer);
         }
-        if self.calculatePoolSize() > self.blockSizeLimit {
-            self.createAndBroadcastBlock().await;
+    }
+
+    // Keyed by the IP the peer's connection comes from, or for a peer we
+    // aren't connected to, the one it announced.
+    fn banKey(&self, address: SocketAddr) -> Option<SocketAddr> {
+        let peer = self.peers.get(&address)?;
+        let remote = peer.connection.as_ref().map_or(address, |connection| connection.remote);
+        Some(banlist::ban_key(remote.ip(), address))
+    }
+
+    fn publishBans(&self) {
+        self.bans.send_replace(self.banList.bans().clone());
+    }
+
+    // Adds `penalty` to the misbehavior score of the peer's ban key and bans
+    // it once the score reaches BAN_THRESHOLD.
+    fn misbehaving(&mut self, address: SocketAddr, penalty: u32, reason: &str) {
+        if penalty == 0 {
+            return;
+        }
+        let Some(key) = self.banKey(address) else {
+            return;
+        };
+        let score = self.misbehavior.entry(key).or_default();
+        *score = score.saturating_add(penalty);
+        debug!("Peer {} misbehaved ({}), score {}", address, reason, score);
+        if *score >= BAN_THRESHOLD {
+            self.ban(address, Utc::now().timestamp() + DEFAULT_BAN_SECS);
         }
     }
 
-    fn calculatePoolSize(&self) -> usize {
-        self.transactionPool
-            .iter()
-            .map(|tx| encoding::serialize(tx).unwrap().len())
-            .sum()
+    fn versionData(&self) -> VersionData {
+        VersionData {
+            protocolVersion: PROTOCOL_VERSION,
+            chainId: self.chainId,
+            bestHeight: self.blockchain.len() as u64,
+            address: self.address,
+            stake: self.stake,
+        }
     }
 
-    fn validateTransaction(&self, tx: &TransactionData) -> bool {
-        !tx.sender.is_empty() && !tx.receiver.is_empty() && tx.amount > 0
+    // Connection tasks read this when they handshake.
+    fn refreshVersion(&self) {
+        self.localVersion.send_replace(self.versionData());
     }
 
-    async fn processBlock(&mut self, block: BlockData) {
-        if self.validateBlock(&block) && block.index == self.blockchain.len() as u64 {
-            self.blockchain.push(block);
+    pub async fn handleIncoming(&mut self) {
+        self.run(false).await;
+    }
+
+    // Handles local and peer messages until `incomingMessages` closes. With
+    // `produceBlocks`, fills each slot we are proposer for, with a block of
+    // no transactions if none are pending, so rewards and beacon reveals
+    // don't stop when traffic does.
+    pub async fn run(&mut self, produceBlocks: bool) {
+        let mut ticker = tokio::time::interval(PROPOSER_CHECK_INTERVAL);
+        let mut maintenance = tokio::time::interval(PEER_MAINTENANCE_INTERVAL);
+        loop {
+            tokio::select! {
+                message = self.incomingMessages.recv() => match message {
+                    Some(message) => self.handleMessage(message).await,
+                    None => return,
+                },
+                Some(event) = self.peerEvents.recv() => self.handlePeerEvent(event).await,
+                _ = maintenance.tick() => self.maintain(),
+                _ = ticker.tick(), if produceBlocks => self.createAndBroadcastBlock().await,
+            }
         }
     }
 
-    fn validateBlock(&self, block: &BlockData) -> bool {
-        if block.index > 0 {
-            let previousBlock = &self.blockchain[(block.index - 1) as usize];
-            if block.previousHash != self.hashBlock(previousBlock) {
-                return false;
+    fn maintain(&mut self) {
+        let now = Utc::now().timestamp();
+        self.banList.prune(now);
+        self.publishBans();
+        self.addressBook.flush();
+        self.maintainOutbound();
+        self.sync.expire(now);
+        self.requestedInventory
+            .retain(|_, requestedAt| now - *requestedAt < INVENTORY_REQUEST_TIMEOUT_SECS);
+        self.syncWithPeers();
+    }
+
+    async fn handlePeerEvent(&mut self, event: PeerEvent) {
+        match event {
+            PeerEvent::Connected { address, version, connection } => {
+                // Dropping the connection closes it.
+                let key = banlist::ban_key(connection.remote.ip(), address);
+                if self.banList.is_banned(&key, Utc::now().timestamp()) {
+                    return;
+                }
+                let localAddress = self.address;
+                let peer = self.peers.entry(address).or_insert_with(|| Peer::new(address, false, 0));
+                // When both sides dial each other, both keep the connection
+                // dialed by the lower address; dropping the other closes it.
+                if let Some(existing) = &peer.connection {
+                    let dialer = |dialed: bool| if dialed { localAddress } else { address };
+                    if dialer(existing.dialed) < dialer(connection.dialed) {
+                        return;
+                    }
+                }
+                let dialed = connection.dialed;
+                peer.stake = version.stake;
+                peer.best_height = version.bestHeight;
+                peer.connection = Some(connection);
+                // A peer known by its socket address can't be dialed back.
+                if address == version.address {
+                    self.addressBook.mark_connected(address, Utc::now().timestamp());
+                }
+                if dialed {
+                    self.sendTo(address, NetworkMessage::GetAddr);
+                }
+                self.syncWithPeers();
+            }
+            PeerEvent::Message { address, message } => self.handlePeerMessage(address, message).await,
+            PeerEvent::Malformed { address, error } => {
+                self.misbehaving(address, MALFORMED_MESSAGE_PENALTY, &error.to_string());
+            }
+            PeerEvent::Disconnected { address, connectionId } => {
+                if let Some(peer) = self.peers.get_mut(&address) {
+                    if peer.connection.as_ref().is_some_and(|connection| connection.id == connectionId) {
+                        peer.connection = None;
+                        self.sync.peerGone(address);
+                    }
+                }
+            }
+            PeerEvent::Unreachable { address } => {
+                self.addressBook.mark_failed(&address);
+                if self.peers.get(&address).is_some_and(|peer| !peer.is_connected()) {
+                    self.peers.remove(&address);
+                }
             }
         }
-        if block.merkleRoot != self.calculateMerkleRoot(&block.transactions) {
-            return false;
+    }
+
+    async fn handlePeerMessage(&mut self, address: SocketAddr, message: NetworkMessage) {
+        // Messages from peers we no longer track, such as one just banned,
+        // are dropped along with those over the rate limit.
+        let now = Utc::now().timestamp();
+        let allowed = self.peers.get_mut(&address).is_some_and(|peer| peer.rate_limiter.allow(now));
+        if !allowed {
+            self.misbehaving(address, RATE_LIMIT_PENALTY, "message rate exceeded");
+            return;
+        }
+        match message {
+            NetworkMessage::GetAddr => {
+                let known = self.addressBook.sample(MAX_ADDR_PER_MESSAGE, &address);
+                self.sendTo(address, NetworkMessage::Addr(known));
+            }
+            NetworkMessage::Addr(addresses) => {
+                let learnedBefore = self.peers[&address].addresses_learned;
+                let limit = MAX_NEW_ADDR_PER_MESSAGE.min(MAX_NEW_ADDR_PER_PEER.saturating_sub(learnedBefore));
+                let learned: Vec<SocketAddr> = addresses
+                    .into_iter()
+                    .take(MAX_ADDR_PER_MESSAGE)
+                    .filter(|learned| *learned != self.address && !self.banList.is_banned(&banlist::ban_key(learned.ip(), *learned), now))
+                    .collect();
+                let added = self.addressBook.add_all(&learned, now, limit);
+                if let Some(peer) = self.peers.get_mut(&address) {
+                    peer.addresses_learned += added;
+                }
+            }
+            NetworkMessage::GetHeaders(locator) => {
+                let headers = self.headersAfter(&locator);
+                self.sendTo(address, NetworkMessage::Headers(headers));
+            }
+            NetworkMessage::Headers(headers) => self.receiveHeaders(address, headers),
+            NetworkMessage::GetBlocks(hashes) => {
+                for hash in hashes.into_iter().take(MAX_BLOCKS_PER_REQUEST) {
+                    if let Some(block) = self.blockTree.block(&hash).cloned() {
+                        self.markKnown(address, InventoryItem::Block(hash));
+                        self.sendTo(address, NetworkMessage::Block(block));
+                    }
+                }
+            }
+            NetworkMessage::Inv(items) => {
+                let mut wanted = Vec::new();
+                for item in items.into_iter().take(MAX_INV_PER_MESSAGE) {
+                    self.markKnown(address, item.clone());
+                    if self.hasInventory(&item) || self.requestedInventory.contains_key(&item) {
+                        continue;
+                    }
+                    self.requestedInventory.insert(item.clone(), now);
+                    wanted.push(item);
+                }
+                if !wanted.is_empty() {
+                    self.sendTo(address, NetworkMessage::GetData(wanted));
+                }
+            }
+            NetworkMessage::GetData(items) => {
+                for item in items.into_iter().take(MAX_INV_PER_MESSAGE) {
+                    let message = match &item {
+                        InventoryItem::Transaction(hash) => {
+                            self.mempool.get(hash).cloned().map(NetworkMessage::Transaction)
+                        }
+                        InventoryItem::Block(hash) => self.blockTree.block(hash).cloned().map(NetworkMessage::Block),
+                    };
+                    if let Some(message) = message {
+                        self.markKnown(address, item);
+                        self.sendTo(address, message);
+                    }
+                }
+            }
+            NetworkMessage::Transaction(tx) => {
+                let item = InventoryItem::Transaction(tx.calculateHash());
+                self.requestedInventory.remove(&item);
+                self.markKnown(address, item);
+                if let Err(e) = self.processTransaction(tx).await {
+                    self.misbehaving(address, e.penalty(), "invalid transaction");
+                }
+            }
+            NetworkMessage::Evidence(evidence) => match self.reportEvidence(*evidence, false) {
+                // Evidence against a validator already jailed is stale, not
+                // forged: only the validator could have signed it.
+                Ok(()) | Err(LedgerError::AlreadyJailed(_)) => {}
+                Err(e) => self.misbehaving(address, INVALID_TRANSACTION_PENALTY, &format!("invalid evidence: {}", e)),
+            },
+            NetworkMessage::Block(block) => {
+                let hash = block.calculateHash();
+                let item = InventoryItem::Block(hash.clone());
+                let requested = self.requestedInventory.remove(&item).is_some() || self.sync.isInFlight(&hash);
+                let orphan = block.height() > 0 && !self.blockTree.contains(&block.blockHeader.prevHash);
+                self.markKnown(address, item);
+                if let Some(peer) = self.peers.get_mut(&address) {
+                    peer.best_height = peer.best_height.max(block.height() + 1);
+                }
+                let height = block.height();
+                match self.storeBlock(block) {
+                    Err(e) => {
+                        warn!("Rejected block {} from {}: {}", height, address, e);
+                        self.misbehaving(address, e.penalty(), "invalid block");
+                    }
+                    // Blocks we didn't ask for should extend a chain we know.
+                    Ok(()) if orphan && !requested => {
+                        self.misbehaving(address, ORPHAN_BLOCK_PENALTY, "unrequested orphan block");
+                    }
+                    Ok(()) => {}
+                }
+                self.syncWithPeers();
+            }
+            message => self.handleMessage(message).await,
+        }
+    }
+
+    async fn handleMessage(&mut self, message: NetworkMessage) {
+        match message {
+            NetworkMessage::Transaction(tx) => {
+                let _ = self.processTransaction(tx).await;
+            }
+            NetworkMessage::Block(block) => self.processBlock(block).await,
+            _ => {}
         }
-        true
     }
 
-    pub async fn broadcast(&self, message: &NetworkMessage) {
-        let serialized_msg = match encoding::serialize(message) {
-            Ok(msg) => msg,
+    async fn processTransaction(&mut self, tx: TransactionData) -> Result<(), TransactionError> {
+        self.admitTransaction(tx)
+    }
+
+    fn admitTransaction(&mut self, tx: TransactionData) -> Result<(), TransactionError> {
+        let admitted = self.validateTransaction(&tx).and_then(|_| {
+            self.mempool
+                .insert(tx.clone(), &self.ledger, Utc::now().timestamp())
+                .map_err(TransactionError::from)
+        });
+        let hash = match admitted {
+            Ok(hash) => hash,
             Err(e) => {
-                eprintln!("Error serializing the message: {}", e);
-                return;
+                // The same transaction may arrive from several peers; only log real rejections.
+                if !matches!(e, TransactionError::Mempool(MempoolError::Duplicate(_))) {
+                    debug!("Rejected transaction from {}: {}", tx.sender, e);
+                }
+                return Err(e);
             }
         };
+        let item = InventoryItem::Transaction(hash);
+        self.seenTransactions.insert(item.clone());
+        self.announce(item);
+        Ok(())
+    }
 
-        for peer in self.peers.values() {
-            let peer_addr = peer.address;
-            match tokio::net::TcpStream::connect(peer_addr).await {
-                Ok(mut stream) => {
-                    if let Err(e) = stream.write_all(&serialized_msg).await {
-                        eprintln!("Error sending message to {}: {}", peer_addr, e);
+    fn validateTransaction(&self, tx: &TransactionData) -> Result<(), TransactionError> {
+        if tx.sender.is_empty() || tx.receiver.is_empty() {
+            return Err(TransactionError::MissingParty);
+        }
+        let wellFormedEvidence = match &tx.evidence {
+            Some(evidence) => {
+                tx.kind == TransactionKind::Evidence && evidence.offender() == tx.receiver && tx.amount == 0
+            }
+            None => tx.kind != TransactionKind::Evidence,
+        };
+        if !wellFormedEvidence {
+            return Err(TransactionError::MalformedEvidence);
+        }
+        // A zero commission is a valid rate.
+        if tx.amount == 0 && !matches!(tx.kind, TransactionKind::Evidence | TransactionKind::Commission) {
+            return Err(TransactionError::ZeroAmount);
+        }
+        if tx.actsOnOwnStake() && tx.receiver != tx.sender {
+            return Err(TransactionError::ForeignStake);
+        }
+        if tx.chainId != self.chainId {
+            return Err(TransactionError::WrongChain(tx.chainId));
+        }
+        if tx.signature.is_empty() || tx.publicKey.is_empty() {
+            return Err(TransactionError::Unsigned);
+        }
+        if Wallet::generate_address(&tx.publicKey) != tx.sender {
+            return Err(TransactionError::AddressMismatch);
+        }
+        if !Wallet::verify_transaction(tx, &tx.publicKey) {
+            return Err(TransactionError::InvalidSignature);
+        }
+        Ok(())
+    }
+
+    async fn processBlock(&mut self, block: Block) {
+        let height = block.height();
+        if let Err(e) = self.storeBlock(block) {
+            warn!("Rejected block {}: {}", height, e);
+        }
+    }
+
+    // Replays the active chain saved in `db` on top of our genesis ledger,
+    // then saves the chain there as it changes. Stops at the first stored
+    // block that no longer applies. Returns how many were loaded.
+    pub fn loadChain(&mut self, db: Database) -> Result<usize, StorageError> {
+        let mut loaded = 0;
+        while let Some(block) = db.get_block_at(loaded as u64)? {
+            let height = block.height();
+            if let Err(e) = self.acceptBlock(block.calculateHash(), block) {
+                warn!("Stored block {} no longer applies: {}", height, e);
+                break;
+            }
+            loaded += 1;
+        }
+        self.chainStore = Some(db);
+        Ok(loaded)
+    }
+
+    // Saves the active blocks from height `from` on and drops the heights
+    // the chain no longer reaches since it was `previousLength` long.
+    fn saveChain(&self, from: usize, previousLength: usize) {
+        let Some(db) = &self.chainStore else {
+            return;
+        };
+        let saved = self.blockchain[from..]
+            .iter()
+            .try_for_each(|block| db.save_block(block))
+            .and_then(|()| {
+                (self.blockchain.len()..previousLength).try_for_each(|height| db.remove_block_at(height as u64))
+            });
+        if let Err(e) = saved {
+            error!("Failed to save the chain: {}", e);
+        }
+    }
+
+    // Stores `block` and any buffered descendants in the block tree. A block
+    // whose parent we don't have yet waits in the orphan buffer. Returns why
+    // `block` itself was rejected.
+    fn storeBlock(&mut self, block: Block) -> Result<(), BlockError> {
+        let hash = block.calculateHash();
+        self.sync.blockArrived(&hash);
+        if self.blockTree.contains(&hash) || self.sync.isOrphan(&hash) {
+            return Ok(());
+        }
+        if block.height() > 0 && !self.blockTree.contains(&block.blockHeader.prevHash) {
+            self.sync.addOrphan(hash, block);
+            return Ok(());
+        }
+
+        self.acceptBlock(hash.clone(), block)?;
+        let mut accepted = vec![hash.clone()];
+        let mut parents = vec![hash];
+        while let Some(parent) = parents.pop() {
+            for child in self.sync.takeOrphansOf(&parent) {
+                let childHash = child.calculateHash();
+                let height = child.height();
+                match self.acceptBlock(childHash.clone(), child) {
+                    Ok(()) => {
+                        parents.push(childHash.clone());
+                        accepted.push(childHash);
                     }
+                    Err(e) => warn!("Rejected block {}: {}", height, e),
                 }
-                Err(e) => {
-                    eprintln!("Failed to connect to {}: {}", peer_addr, e);
+            }
+        }
+        // Side branches are not worth relaying.
+        if !self.isCatchingUp() {
+            for hash in accepted {
+                if self.blockIndex.contains_key(&hash) {
+                    self.announce(InventoryItem::Block(hash));
                 }
             }
         }
+        Ok(())
     }
 
-    async fn createAndBroadcastBlock(&mut self) {
-        let previousHash = if let Some(lastBlock) = self.blockchain.last() {
-            self.hashBlock(lastBlock)
-        } else {
-            String::new()
+    fn isCatchingUp(&self) -> bool {
+        let tipSlot = self.blockchain.last().map_or(0, Block::slot);
+        tipSlot + CATCH_UP_SLOTS < ProofOfStake::slotAt(Utc::now().timestamp())
+    }
+
+    // Adds a block whose parent is known to the tree and switches to its
+    // branch if that is now the heaviest. On a tie the current chain stays.
+    fn acceptBlock(&mut self, hash: String, block: Block) -> Result<(), BlockError> {
+        let consensus = self.validateBlock(&block)?;
+        if consensus.totalStake() > 0 {
+            if let Some(evidence) = self.checkEquivocation(&hash, &block) {
+                if let Err(e) = self.reportEvidence(evidence, true) {
+                    warn!("Not reporting double signing by {}: {}", block.producer(), e);
+                }
+            }
+        }
+        let weight = Node::blockWeight(&consensus, &block);
+        let chainWeight = self.blockTree.insert(hash.clone(), block, weight);
+        self.sync.blockStored(&hash);
+        if chainWeight > self.blockTree.chainWeight(&self.tipHash()) {
+            self.reorganize(&hash)?;
+            self.forgetFinalized();
+        }
+        Ok(())
+    }
+
+    fn forgetFinalized(&mut self) {
+        let Some(floor) = (self.blockchain.len() as u64).checked_sub(FINALITY_DEPTH) else {
+            return;
         };
-        let block = BlockData {
-            index: self.blockchain.len() as u64,
-            previousHash,
-            timestamp: Utc::now().timestamp(),
-            merkleRoot: self.calculateMerkleRoot(&self.transactionPool),
-            nonce: 0,
-            transactions: self.transactionPool.clone(),
+        self.proposals.retain(|_, (_, height)| *height >= floor);
+        self.seenEvidence.retain(|_, height| *height >= floor);
+    }
+
+    // Evidence against `block`'s producer if we already have a different
+    // block it signed for the same slot.
+    fn checkEquivocation(&mut self, hash: &str, block: &Block) -> Option<DoubleSignEvidence> {
+        let key = (block.producer().to_string(), block.slot());
+        let (first, _) = self.proposals.entry(key).or_insert_with(|| (hash.to_string(), block.height()));
+        if first == hash {
+            return None;
+        }
+        let first = self.blockTree.block(first)?;
+        Some(DoubleSignEvidence::new(SignedHeader::of(first), SignedHeader::of(block)))
+    }
+
+    // Relays evidence we haven't seen yet and, if we validate and `detected`
+    // it ourselves, submits it on chain to have the offender slashed. Nodes
+    // that learn of it from a peer only relay it, so the chain gets one
+    // report instead of one per validator. Only evidence the ledger would
+    // still slash for is relayed; signing two headers with a key that has no
+    // stake costs nothing.
+    fn reportEvidence(&mut self, evidence: DoubleSignEvidence, detected: bool) -> Result<(), LedgerError> {
+        if self.seenEvidence.contains_key(&evidence.id()) {
+            return Ok(());
+        }
+        self.ledger.check_evidence(&evidence)?;
+        self.seenEvidence.insert(evidence.id(), evidence.first.header.height);
+        warn!("{} signed two blocks in slot {}", evidence.offender(), evidence.slot());
+        self.broadcast(&NetworkMessage::Evidence(Box::new(evidence.clone())));
+
+        let Some(keypair) = self.validatorKey.clone().filter(|_| detected) else {
+            return Ok(());
         };
-        self.transactionPool.clear();
-        self.blockchain.push(block.clone());
-        self.broadcast(&NetworkMessage::Block(block)).await;
+        let mut reporter = Wallet::from_keypair(keypair);
+        reporter.chain_id = self.chainId;
+        reporter.sync_from_ledger(&self.ledger);
+        let address = reporter.addresses[0].clone();
+        let pending = self.mempool.pending_for(&address).len() as u64;
+        reporter.nonces.insert(address.clone(), self.ledger.nonce(&address) + pending);
+        let submitted = reporter
+            .send_evidence(evidence)
+            .and_then(|tx| self.admitTransaction(tx).map_err(|e| e.to_string()));
+        if let Err(e) = submitted {
+            warn!("Could not submit evidence: {}", e);
+        }
+        Ok(())
     }
 
-    fn calculateMerkleRoot(&self, transactions: &[TransactionData]) -> String {
-        if transactions.is_empty() {
+    // Blocks count by their producer's stake in the set that elected them,
+    // so the heaviest chain is the one most stake built on. Without stake
+    // every block counts once and the longest chain wins.
+    fn blockWeight(consensus: &ProofOfStake, block: &Block) -> u64 {
+        consensus.stakeOf(block.producer()).max(1)
+    }
+
+    // Makes `newTip` the active tip: reverts the active blocks that are not
+    // its ancestors, applies its branch, and returns the transactions of the
+    // reverted blocks to the mempool. If a block on the branch doesn't apply,
+    // it is dropped from the tree along with its descendants and the active
+    // chain is left as it was.
+    fn reorganize(&mut self, newTip: &str) -> Result<(), BlockError> {
+        let (disconnect, connect) = self.blockTree.route(&self.tipHash(), newTip);
+        if disconnect.len() as u64 > FINALITY_DEPTH {
+            return Err(BlockError::Finalized(disconnect.len()));
+        }
+
+        let (ledger, undos) = match self.branchLedger(newTip) {
+            Ok(applied) => applied,
+            Err((hash, e)) => {
+                self.blockTree.removeBranch(&hash);
+                return Err(e.into());
+            }
+        };
+
+        let previousLength = self.blockchain.len();
+        let keep = previousLength - disconnect.len();
+        let disconnected = self.blockchain.split_off(keep);
+        self.undoLog.truncate(keep);
+        for block in &disconnected {
+            self.blockIndex.remove(&block.calculateHash());
+        }
+        self.ledger = ledger;
+        self.consensus = ProofOfStake::new(self.ledger.validator_set().clone());
+        for (hash, undo) in connect.into_iter().zip(undos) {
+            let block = self.blockTree.block(&hash).unwrap().clone();
+            self.mempool.remove_included(&block, &self.ledger);
+            self.blockIndex.insert(hash, block.height());
+            self.blockchain.push(block);
+            self.undoLog.push(undo);
+        }
+        self.saveChain(keep, previousLength);
+        if !disconnected.is_empty() {
+            let returned = disconnected.into_iter().flat_map(|block| block.transactions).collect();
+            self.mempool.readmit(returned, &self.ledger, Utc::now().timestamp());
+        }
+        self.refreshVersion();
+        Ok(())
+    }
+
+    // Our ledger rewound to where `hash`'s branch forks off the active chain
+    // and replayed along the branch up to `hash`. Returns the undo of each
+    // replayed block, or the first block that doesn't apply and why.
+    fn branchLedger(&self, hash: &str) -> Result<(Ledger, Vec<BlockUndo>), (String, LedgerError)> {
+        let (disconnect, connect) = self.blockTree.route(&self.tipHash(), hash);
+        let keep = self.blockchain.len() - disconnect.len();
+        let mut ledger = self.ledger.clone();
+        for undo in self.undoLog[keep..].iter().rev() {
+            ledger.revert_block(undo);
+        }
+        let mut undos = Vec::new();
+        for hash in connect {
+            match ledger.apply_block(self.blockTree.block(&hash).unwrap()) {
+                Ok(undo) => undos.push(undo),
+                Err(e) => return Err((hash, e)),
+            }
+        }
+        Ok((ledger, undos))
+    }
+
+    fn ledgerAfter(&self, hash: &str) -> Result<Cow<'_, Ledger>, BlockError> {
+        if hash == self.tipHash() {
+            return Ok(Cow::Borrowed(&self.ledger));
+        }
+        self.branchLedger(hash)
+            .map(|(ledger, _)| Cow::Owned(ledger))
+            .map_err(|(_, e)| e.into())
+    }
+
+    // Validators that elected the active block at `height`.
+    pub fn validatorSetAt(&self, height: u64) -> Option<ValidatorSet> {
+        let block = self.blockchain.get(height as usize)?;
+        let ledger = self.ledgerAfter(&block.calculateHash()).ok()?;
+        Some(ledger.validator_set().clone())
+    }
+
+    // Coins in existence once the active block at `height` was applied.
+    pub fn supplyAt(&self, height: u64) -> Option<u64> {
+        let block = self.blockchain.get(height as usize)?;
+        let ledger = self.ledgerAfter(&block.calculateHash()).ok()?;
+        Some(ledger.supply())
+    }
+
+    // Checks that don't depend on the branch's transactions; the ledger
+    // checks happen when the block's branch is applied. The proposer is
+    // checked against the validators of its epoch as of its parent, which
+    // are returned. Everything that doesn't need the parent's ledger is
+    // checked before it is replayed.
+    fn validateBlock(&self, block: &Block) -> Result<ProofOfStake, BlockError> {
+        if block.height() == 0 {
+            if !block.blockHeader.prevHash.is_empty() {
+                return Err(BlockError::InvalidGenesis);
+            }
+        } else {
+            let parentHeight = self
+                .blockTree
+                .height(&block.blockHeader.prevHash)
+                .ok_or_else(|| BlockError::UnknownParent(block.blockHeader.prevHash.clone()))?;
+            if block.height() != parentHeight + 1 {
+                return Err(BlockError::WrongHeight {
+                    expected: parentHeight + 1,
+                    actual: block.height(),
+                });
+            }
+        }
+        // Measured as block assembly measures it.
+        let size: usize = block.transactions.iter().map(transaction_size).sum();
+        if size > self.blockSizeLimit {
+            return Err(BlockError::TooLarge { size, limit: self.blockSizeLimit });
+        }
+        // Connecting the block would revert at least this many of ours.
+        let reverted = (self.blockchain.len() as u64).saturating_sub(block.height());
+        if reverted > FINALITY_DEPTH {
+            return Err(BlockError::Finalized(reverted as usize));
+        }
+        if !block.hasValidMerkleRoot() {
+            return Err(BlockError::InvalidMerkleRoot);
+        }
+        let signatureChecked = self.consensus.totalStake() > 0;
+        if signatureChecked && !block.hasValidSignature() {
+            return Err(BlockError::InvalidSignature);
+        }
+        let parent = self.ledgerAfter(&block.blockHeader.prevHash)?;
+        let expected = parent.block_reward(block.height());
+        if block.blockHeader.reward != expected {
+            return Err(LedgerError::WrongReward { expected, actual: block.blockHeader.reward }.into());
+        }
+        let consensus = ProofOfStake::new(parent.validator_set_for(block.slot()));
+        // Without any stake configured, anyone may produce blocks.
+        if consensus.totalStake() > 0 {
+            self.validateProposer(block, &consensus)?;
+            if !signatureChecked && !block.hasValidSignature() {
+                return Err(BlockError::InvalidSignature);
+            }
+        }
+        for tx in &block.transactions {
+            self.validateTransaction(tx)?;
+        }
+        Ok(consensus)
+    }
+
+    // Each slot after the parent's can hold one block, built and signed by
+    // the validator the epoch seed selects for it. The header still carries
+    // the running beacon, which later epochs are seeded from.
+    fn validateProposer(&self, block: &Block, consensus: &ProofOfStake) -> Result<(), BlockError> {
+        let slot = block.slot();
+        if let Some(parent) = self.blockTree.block(&block.blockHeader.prevHash) {
+            if slot <= parent.slot() {
+                return Err(BlockError::SlotNotAfterParent { slot, parent: parent.slot() });
+            }
+        }
+        if slot > ProofOfStake::slotAt(Utc::now().timestamp()) + 1 {
+            return Err(BlockError::FutureSlot(slot));
+        }
+        if block.blockHeader.seed != self.beaconAfter(&block.blockHeader.prevHash) {
+            return Err(BlockError::WrongSeed);
+        }
+        consensus.verifyProposer(&self.epochSeed(&block.blockHeader.prevHash, slot), slot, &block.blockHeader)?;
+        Ok(())
+    }
+
+    // Seed for blocks built on `hash`; the empty chain starts from an
+    // empty beacon.
+    fn beaconAfter(&self, hash: &str) -> String {
+        self.blockTree
+            .block(hash)
+            .map_or(String::new(), |block| ProofOfStake::nextBeacon(&block.blockHeader))
+    }
+
+    // Seed of `slot`'s epoch on the branch ending at `parentHash`: the beacon
+    // as of its last block before the seed cutoff.
+    fn epochSeed(&self, parentHash: &str, slot: u64) -> String {
+        let Some(cutoff) = ProofOfStake::seedCutoff(slot) else {
             return String::new();
+        };
+        let mut hash = parentHash;
+        while let Some(block) = self.blockTree.block(hash) {
+            if block.slot() < cutoff {
+                return ProofOfStake::nextBeacon(&block.blockHeader);
+            }
+            hash = &block.blockHeader.prevHash;
         }
-        let mut hashes: Vec<String> = transactions
+        String::new()
+    }
+
+    fn tipHash(&self) -> String {
+        self.blockchain.last().map(Block::calculateHash).unwrap_or_default()
+    }
+
+    // Hashes of our chain from the tip back, one per block at first and
+    // then doubling the step, always ending at genesis. A peer answers with
+    // headers following the first hash it recognizes.
+    pub fn locator(&self) -> Vec<String> {
+        let mut locator = Vec::new();
+        let mut height = self.blockchain.len().checked_sub(1);
+        let mut step = 1;
+        while let Some(current) = height {
+            locator.push(self.blockchain[current].calculateHash());
+            if current == 0 {
+                break;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = Some(current.saturating_sub(step));
+        }
+        locator
+    }
+
+    fn headersAfter(&self, locator: &[String]) -> Vec<BlockHeader> {
+        let start = locator
+            .iter()
+            .find_map(|hash| self.blockIndex.get(hash))
+            .map_or(0, |height| *height as usize + 1);
+        self.blockchain
             .iter()
-            .map(|tx| hashing::sha256(&encoding::serialize(tx).unwrap()))
+            .skip(start)
+            .take(MAX_HEADERS_PER_MESSAGE)
+            .map(|block| block.blockHeader.clone())
+            .collect()
+    }
+
+    fn receiveHeaders(&mut self, address: SocketAddr, headers: Vec<BlockHeader>) {
+        let full = headers.len() == MAX_HEADERS_PER_MESSAGE;
+        let lastHeight = headers.last().map(|header| header.height);
+        let blockTree = &self.blockTree;
+        if let Err(e) = self.sync.receiveHeaders(address, headers, |hash| blockTree.height(hash)) {
+            debug!("Ignoring headers from {}: {}", address, e);
+            let penalty = match e {
+                SyncError::TooManyHeaders(_) => MALFORMED_MESSAGE_PENALTY,
+                SyncError::Unconnected(_) => UNCONNECTED_HEADERS_PENALTY,
+                // Could be an answer that came after we gave up waiting.
+                SyncError::Unrequested => 0,
+            };
+            self.misbehaving(address, penalty, &e.to_string());
+            return;
+        }
+        if let (Some(peer), Some(lastHeight)) = (self.peers.get_mut(&address), lastHeight) {
+            peer.best_height = peer.best_height.max(lastHeight + 1);
+        }
+        // A full batch means the peer has more to send.
+        if full {
+            self.requestHeaders(address);
+        }
+        self.syncWithPeers();
+    }
+
+    fn requestHeaders(&mut self, address: SocketAddr) {
+        if !self.sync.requestHeaders(address, Utc::now().timestamp()) {
+            return;
+        }
+        let mut locator = self.locator();
+        if let Some((_, hash)) = self.sync.headerTip() {
+            locator.insert(0, hash.to_string());
+        }
+        self.sendTo(address, NetworkMessage::GetHeaders(locator));
+    }
+
+    // Asks the peer with the longest chain for headers past the ones we
+    // know, then spreads the blocks still missing over every peer that has
+    // them.
+    fn syncWithPeers(&mut self) {
+        let known = self
+            .sync
+            .headerTip()
+            .map_or(self.blockchain.len() as u64, |(height, _)| height + 1);
+        let best = self
+            .peers
+            .values()
+            .filter(|peer| peer.is_connected() && peer.best_height > known)
+            .max_by_key(|peer| (peer.best_height, peer.address))
+            .map(|peer| peer.address);
+        if let Some(best) = best {
+            self.requestHeaders(best);
+        }
+
+        let peers: Vec<(SocketAddr, u64)> = self
+            .peers
+            .values()
+            .filter(|peer| peer.is_connected())
+            .map(|peer| (peer.address, peer.best_height))
             .collect();
-        while hashes.len() > 1 {
-            let mut newHashes = Vec::new();
-            for chunk in hashes.chunks(2) {
-                let concatenated = if chunk.len() == 2 {
-                    chunk[0].clone() + &chunk[1]
-                } else {
-                    chunk[0].clone() + &chunk[0]
-                };
-                newHashes.push(hashing::sha256(concatenated.as_bytes()));
+        let requests = self.sync.schedule(&peers, Utc::now().timestamp());
+        for (address, hashes) in requests {
+            self.sendTo(address, NetworkMessage::GetBlocks(hashes));
+        }
+    }
+
+    fn hasInventory(&self, item: &InventoryItem) -> bool {
+        match item {
+            InventoryItem::Transaction(hash) => self.mempool.contains(hash) || self.seenTransactions.contains(item),
+            InventoryItem::Block(hash) => self.blockTree.contains(hash) || self.sync.isOrphan(hash),
+        }
+    }
+
+    fn markKnown(&mut self, address: SocketAddr, item: InventoryItem) {
+        if let Some(peer) = self.peers.get_mut(&address) {
+            peer.known_inventory.insert(item);
+        }
+    }
+
+    // Announces `item` to every connected peer not known to have it; peers
+    // fetch it with `GetData` if they want it.
+    pub fn announce(&mut self, item: InventoryItem) {
+        for peer in self.peers.values_mut() {
+            let Some(connection) = &peer.connection else {
+                continue;
+            };
+            if !peer.known_inventory.insert(item.clone()) {
+                continue;
+            }
+            if let Err(TrySendError::Full(_)) = connection.outbound.try_send(NetworkMessage::Inv(vec![item.clone()])) {
+                warn!("Outbound queue to {} is full, dropping message", peer.address);
+            }
+        }
+    }
+
+    // Queues `message` on every connected peer. A peer whose queue is full
+    // misses the message rather than stalling the node.
+    pub fn broadcast(&self, message: &NetworkMessage) {
+        for peer in self.peers.values() {
+            let Some(connection) = &peer.connection else {
+                continue;
+            };
+            if let Err(TrySendError::Full(_)) = connection.outbound.try_send(message.clone()) {
+                warn!("Outbound queue to {} is full, dropping message", peer.address);
+            }
+        }
+    }
+
+    pub fn isProposer(&self, slot: u64) -> bool {
+        self.eligibilityProof(slot).is_some()
+    }
+
+    // Our VRF proof that we won `slot` on top of our tip, if we did, and
+    // haven't signed a block for it or a later slot on any branch.
+    // Without any stake configured every node may produce, with no proof.
+    fn eligibilityProof(&self, slot: u64) -> Option<Vec<u8>> {
+        let consensus = ProofOfStake::new(self.ledger.validator_set_for(slot));
+        if consensus.totalStake() == 0 {
+            return Some(Vec::new());
+        }
+        if self.blockchain.last().is_some_and(|tip| tip.slot() >= slot) || !self.signingRecord.maySign(slot) {
+            return None;
+        }
+        let keypair = self.validatorKey.as_ref()?;
+        consensus.proveEligibility(keypair, &self.epochSeed(&self.tipHash(), slot), slot)
+    }
+
+    async fn createAndBroadcastBlock(&mut self) {
+        self.produceBlock(ProofOfStake::slotAt(Utc::now().timestamp())).await;
+    }
+
+    async fn produceBlock(&mut self, slot: u64) {
+        let Some(vrfProof) = self.eligibilityProof(slot) else {
+            return;
+        };
+        self.mempool.expire(Utc::now().timestamp());
+        // Selected against the ledger as the block will find it, which at an
+        // epoch boundary may already reject what the tip's ledger takes,
+        // such as evidence that has just expired.
+        let entered = self.ledger.entering_slot(slot);
+        self.mempool.remove_failing(&entered);
+        let transactions = self.mempool.select_for_block(&entered, self.blockSizeLimit);
+        let mut block = Block::new(
+            self.blockchain.len() as u64,
+            self.tipHash(),
+            self.rewardAddress.clone(),
+            transactions,
+        );
+        block.blockHeader.slot = slot;
+        block.blockHeader.seed = self.beaconAfter(&block.blockHeader.prevHash);
+        block.blockHeader.vrfProof = vrfProof;
+        block.blockHeader.reward = self.ledger.block_reward(block.height());
+        if let Some(keypair) = &self.validatorKey {
+            if let Err(e) = self.signingRecord.record(slot) {
+                error!("Not signing a block for slot {}: {}", slot, e);
+                return;
+            }
+            block.sign(keypair);
+        }
+        let hash = block.calculateHash();
+        let height = block.height();
+        if let Err(e) = self.acceptBlock(hash.clone(), block) {
+            error!("Failed to apply own block {}: {}", height, e);
+            return;
+        }
+        self.announce(InventoryItem::Block(hash));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SignatureAlgorithm;
+    use crate::ledger::{LedgerError, MonetaryPolicy};
+    use crate::ledger::state::UNBONDING_EPOCHS;
+    use crate::network::connection::PeerConnection;
+    use crate::consensus::pos::SLOTS_PER_EPOCH;
+    use crate::network::message::TransactionKind;
+    use crate::network::peer::{MESSAGES_PER_SEC, MESSAGE_BURST};
+
+    fn testNode(ledger: Ledger) -> Node {
+        let (_, receiver) = mpsc::channel(1);
+        let (sender, _) = mpsc::channel(1);
+        Node::new("127.0.0.1:0".parse().unwrap(), receiver, sender, usize::MAX, ledger)
+    }
+
+    // A peer announcing `address` whose connection comes from `remote`, with
+    // the receiving end of its outbound queue.
+    fn connectedPeer(address: SocketAddr, remote: SocketAddr) -> (Peer, mpsc::Receiver<NetworkMessage>) {
+        let (outbound, sent) = mpsc::channel(8);
+        let mut peer = Peer::new(address, false, 0);
+        peer.connection = Some(PeerConnection { id: 0, outbound, dialed: false, remote });
+        (peer, sent)
+    }
+
+    fn fund(tx: &TransactionData, amount: u64) -> Ledger {
+        Ledger::from_genesis(&[(tx.sender.clone(), amount)])
+    }
+
+    fn signedTransaction(algorithm: SignatureAlgorithm) -> (Wallet, TransactionData) {
+        let mut wallet = Wallet::new();
+        wallet.addresses.clear();
+        wallet.keypairs.clear();
+        wallet.generate_new_address_with(algorithm);
+        wallet.balance = 100;
+        let tx = wallet.send_payment("recipient_address", 10).unwrap();
+        (wallet, tx)
+    }
+
+    #[tokio::test]
+    async fn test_signed_transactions_are_pooled() {
+        for algorithm in [SignatureAlgorithm::Ed25519, SignatureAlgorithm::Dilithium] {
+            let (_, tx) = signedTransaction(algorithm);
+            let mut node = testNode(fund(&tx, 100));
+            assert_eq!(node.processTransaction(tx).await, Ok(()));
+            assert_eq!(node.mempool.len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_transaction_rejected() {
+        let mut node = testNode(Ledger::new());
+        let (_, mut tx) = signedTransaction(SignatureAlgorithm::Ed25519);
+        tx.signature.clear();
+
+        assert_eq!(node.processTransaction(tx).await, Err(TransactionError::Unsigned));
+        assert!(node.mempool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_forged_transaction_rejected() {
+        let mut node = testNode(Ledger::new());
+        let (victim, _) = signedTransaction(SignatureAlgorithm::Ed25519);
+        let (_, mut forged) = signedTransaction(SignatureAlgorithm::Ed25519);
+
+        forged.sender = victim.addresses[0].clone();
+        assert_eq!(node.processTransaction(forged.clone()).await, Err(TransactionError::AddressMismatch));
+
+        forged.publicKey = victim.keypairs[0].public_key.clone();
+        assert_eq!(node.processTransaction(forged).await, Err(TransactionError::InvalidSignature));
+
+        let (_, mut tampered) = signedTransaction(SignatureAlgorithm::Dilithium);
+        tampered.amount = 99;
+        assert_eq!(node.processTransaction(tampered).await, Err(TransactionError::InvalidSignature));
+        assert!(node.mempool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unfunded_transaction_rejected() {
+        let (_, tx) = signedTransaction(SignatureAlgorithm::Ed25519);
+        let mut node = testNode(fund(&tx, 5));
+
+        assert!(matches!(
+            node.processTransaction(tx).await,
+            Err(TransactionError::Mempool(MempoolError::Ledger(LedgerError::InsufficientFunds { .. })))
+        ));
+        assert!(node.mempool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_block_applied_to_ledger() {
+        let (_, tx) = signedTransaction(SignatureAlgorithm::Ed25519);
+        let mut producer = testNode(fund(&tx, 100));
+        producer.processTransaction(tx.clone()).await.unwrap();
+        producer.createAndBroadcastBlock().await;
+        assert_eq!(producer.ledger.balance(&tx.sender), 90 - tx.fee);
+        assert_eq!(producer.ledger.balance(&tx.receiver), 10);
+
+        let block = producer.blockchain[0].clone();
+
+        let mut funded = testNode(fund(&tx, 100));
+        funded.processBlock(block.clone()).await;
+        assert_eq!(funded.blockchain.len(), 1);
+        assert_eq!(funded.ledger.account(&tx.sender).nonce, 1);
+
+        let mut underfunded = testNode(fund(&tx, 5));
+        underfunded.processBlock(block).await;
+        assert!(underfunded.blockchain.is_empty());
+        assert_eq!(underfunded.ledger.balance(&tx.sender), 5);
+    }
+
+    #[tokio::test]
+    async fn test_nonces_enforced_when_pooling() {
+        let mut wallet = Wallet::new();
+        wallet.balance = 100;
+        let first = wallet.send_payment("recipient_address", 10).unwrap();
+        let second = wallet.send_payment("recipient_address", 10).unwrap();
+        let third = wallet.send_payment("recipient_address", 10).unwrap();
+        let mut node = testNode(fund(&first, 100));
+
+        assert!(matches!(
+            node.processTransaction(second.clone()).await,
+            Err(TransactionError::Mempool(MempoolError::Ledger(LedgerError::NonceGap { .. })))
+        ));
+        node.processTransaction(first.clone()).await.unwrap();
+        assert!(matches!(
+            node.processTransaction(first.clone()).await,
+            Err(TransactionError::Mempool(MempoolError::Duplicate(_)))
+        ));
+        node.processTransaction(second).await.unwrap();
+        assert_eq!(node.mempool.len(), 2);
+
+        node.createAndBroadcastBlock().await;
+        assert_eq!(node.ledger.nonce(&first.sender), 2);
+        assert!(matches!(
+            node.processTransaction(first).await,
+            Err(TransactionError::Mempool(MempoolError::Ledger(LedgerError::Replayed { .. })))
+        ));
+        node.processTransaction(third).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transaction_for_other_chain_rejected() {
+        let mut wallet = Wallet::new();
+        wallet.balance = 100;
+        wallet.chain_id = DEFAULT_CHAIN_ID + 1;
+        let tx = wallet.send_payment("recipient_address", 10).unwrap();
+        let mut node = testNode(fund(&tx, 100));
+
+        assert_eq!(
+            node.processTransaction(tx).await,
+            Err(TransactionError::WrongChain(DEFAULT_CHAIN_ID + 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_prefers_higher_fee_rate_and_pays_producer() {
+        let mut genesis = Vec::new();
+        let mut transactions = Vec::new();
+        for fee in [1, 5, 3] {
+            let mut wallet = Wallet::new();
+            wallet.balance = 100;
+            genesis.push((wallet.addresses[0].clone(), 100));
+            transactions.push(wallet.send_payment_with_fee("recipient_address", 10, fee).unwrap());
+        }
+        let mut node = testNode(Ledger::from_genesis(&genesis));
+        node.rewardAddress = "producer".to_string();
+        for tx in transactions {
+            node.processTransaction(tx).await.unwrap();
+        }
+
+        node.blockSizeLimit = 2 * node.mempool.iter().map(|entry| entry.size).max().unwrap();
+        node.createAndBroadcastBlock().await;
+
+        let fees: Vec<u64> = node.blockchain[0].transactions.iter().map(|tx| tx.fee).collect();
+        assert_eq!(fees, vec![5, 3]);
+        assert_eq!(node.mempool.len(), 1);
+        assert_eq!(node.mempool.iter().next().unwrap().tx.fee, 1);
+        assert_eq!(node.ledger.balance("producer"), 8);
+        assert_eq!(node.ledger.balance("recipient_address"), 20);
+    }
+
+    #[tokio::test]
+    async fn test_block_keeps_sender_nonce_order() {
+        let mut wallet = Wallet::new();
+        wallet.balance = 100;
+        let cheap = wallet.send_payment_with_fee("recipient_address", 10, 1).unwrap();
+        let expensive = wallet.send_payment_with_fee("recipient_address", 10, 9).unwrap();
+        let mut node = testNode(fund(&cheap, 100));
+        node.processTransaction(cheap).await.unwrap();
+        node.processTransaction(expensive).await.unwrap();
+
+        node.createAndBroadcastBlock().await;
+
+        let nonces: Vec<u64> = node.blockchain[0].transactions.iter().map(|tx| tx.nonce).collect();
+        assert_eq!(nonces, vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_pool_drops_transactions_included_by_peer() {
+        let mut wallet = Wallet::new();
+        wallet.balance = 100;
+        let first = wallet.send_payment("recipient_address", 10).unwrap();
+        let second = wallet.send_payment("recipient_address", 10).unwrap();
+
+        let mut producer = testNode(fund(&first, 100));
+        producer.processTransaction(first.clone()).await.unwrap();
+        producer.createAndBroadcastBlock().await;
+
+        let mut follower = testNode(fund(&first, 100));
+        follower.processTransaction(first).await.unwrap();
+        follower.processTransaction(second.clone()).await.unwrap();
+        follower.processBlock(producer.blockchain[0].clone()).await;
+
+        assert_eq!(follower.blockchain.len(), 1);
+        assert_eq!(follower.mempool.len(), 1);
+        assert_eq!(follower.mempool.pending_for(&second.sender)[0].nonce, second.nonce);
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order_blocks_connect_once_parent_arrives() {
+        let mut producer = testNode(Ledger::new());
+        for _ in 0..3 {
+            producer.createAndBroadcastBlock().await;
+        }
+
+        let mut follower = testNode(Ledger::new());
+        follower.processBlock(producer.blockchain[2].clone()).await;
+        follower.processBlock(producer.blockchain[1].clone()).await;
+        assert!(follower.blockchain.is_empty());
+        assert_eq!(follower.sync.orphanCount(), 2);
+
+        follower.processBlock(producer.blockchain[0].clone()).await;
+        assert_eq!(follower.blockchain, producer.blockchain);
+        assert_eq!(follower.sync.orphanCount(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_locator_thins_out_towards_genesis() {
+        let mut node = testNode(Ledger::new());
+        for _ in 0..20 {
+            node.createAndBroadcastBlock().await;
+        }
+
+        let heights: Vec<u64> = node.locator().iter().map(|hash| node.blockIndex[hash]).collect();
+        assert_eq!(heights, vec![19, 18, 17, 16, 15, 14, 13, 12, 11, 10, 8, 4, 0]);
+        assert_eq!(node.headersAfter(&[node.blockchain[16].calculateHash()]).len(), 3);
+        assert_eq!(node.headersAfter(&["unknown".to_string()]).len(), 20);
+    }
+
+    // Builds `count` blocks on top of `parent` (or genesis) as `producer`,
+    // including whatever `pool` holds.
+    async fn branch(ledger: &Ledger, parent: &[Block], producer: &str, pool: Vec<TransactionData>, count: usize) -> Vec<Block> {
+        let mut builder = testNode(ledger.clone());
+        builder.rewardAddress = producer.to_string();
+        for block in parent {
+            builder.processBlock(block.clone()).await;
+        }
+        for tx in pool {
+            builder.processTransaction(tx).await.unwrap();
+        }
+        for _ in 0..count {
+            builder.createAndBroadcastBlock().await;
+        }
+        builder.blockchain.split_off(parent.len())
+    }
+
+    #[tokio::test]
+    async fn test_active_chain_reloaded_after_reorg() {
+        let genesis = Ledger::new();
+        let common = branch(&genesis, &[], "a", Vec::new(), 1).await;
+        let ours = branch(&genesis, &common, "a", Vec::new(), 1).await;
+        let theirs = branch(&genesis, &common, "b", Vec::new(), 2).await;
+        let db = Database::new("test_chain_db").unwrap();
+        for height in 0..4 {
+            db.remove_block_at(height).unwrap();
+        }
+
+        let mut node = testNode(genesis.clone());
+        assert_eq!(node.loadChain(db.clone()).unwrap(), 0);
+        for block in common.iter().chain(&ours).chain(&theirs) {
+            node.processBlock(block.clone()).await;
+        }
+        assert_eq!(node.blockchain[1..], theirs[..]);
+
+        let mut restarted = testNode(genesis);
+        assert_eq!(restarted.loadChain(db).unwrap(), 3);
+        assert_eq!(restarted.blockchain, node.blockchain);
+        assert_eq!(restarted.ledger.balance("b"), node.ledger.balance("b"));
+    }
+
+    #[tokio::test]
+    async fn test_one_block_reorg_returns_transactions_to_pool() {
+        let mut wallet = Wallet::new();
+        wallet.balance = 100;
+        let tx = wallet.send_payment("recipient_address", 10).unwrap();
+        let genesis = fund(&tx, 100);
+        let common = branch(&genesis, &[], "a", Vec::new(), 1).await;
+        let ours = branch(&genesis, &common, "a", vec![tx.clone()], 1).await;
+        let theirs = branch(&genesis, &common, "b", Vec::new(), 2).await;
+
+        let mut node = testNode(genesis);
+        for block in common.iter().chain(&ours).chain(&theirs[..1]) {
+            node.processBlock(block.clone()).await;
+        }
+        assert_eq!(node.blockchain[1], ours[0]);
+        assert_eq!(node.blockTree.len(), 3);
+        assert_eq!(node.ledger.balance("recipient_address"), 10);
+
+        node.processBlock(theirs[1].clone()).await;
+        assert_eq!(node.blockchain[1..], theirs[..]);
+        assert_eq!(node.ledger.balance("recipient_address"), 0);
+        assert_eq!(node.ledger.nonce(&tx.sender), 0);
+        assert!(node.mempool.contains(&tx.calculateHash()));
+    }
+
+    #[tokio::test]
+    async fn test_multi_block_reorg_rolls_ledger_back_and_forward() {
+        let mut wallet = Wallet::new();
+        wallet.balance = 100;
+        let payments: Vec<TransactionData> =
+            (0..3).map(|_| wallet.send_payment("recipient_address", 10).unwrap()).collect();
+        let genesis = fund(&payments[0], 100);
+        let common = branch(&genesis, &[], "a", Vec::new(), 1).await;
+        let ours = branch(&genesis, &common, "a", payments.clone(), 3).await;
+        let theirs = branch(&genesis, &common, "b", payments[..1].to_vec(), 4).await;
+
+        let mut node = testNode(genesis.clone());
+        for block in common.iter().chain(&ours) {
+            node.processBlock(block.clone()).await;
+        }
+        assert_eq!(node.ledger.nonce(&wallet.addresses[0]), 3);
+        for block in &theirs {
+            node.processBlock(block.clone()).await;
+        }
+
+        assert_eq!(node.blockchain.len(), 5);
+        assert_eq!(node.blockchain[1..], theirs[..]);
+        assert_eq!(node.ledger.balance("recipient_address"), 10);
+        assert_eq!(node.ledger.balance("a"), 0);
+        assert_eq!(node.ledger.nonce(&wallet.addresses[0]), 1);
+        let pending: Vec<u64> = node.mempool.pending_for(&wallet.addresses[0]).iter().map(|tx| tx.nonce).collect();
+        assert_eq!(pending, vec![1, 2]);
+
+        let mut replayed = testNode(genesis);
+        for block in common.iter().chain(&theirs) {
+            replayed.processBlock(block.clone()).await;
+        }
+        assert_eq!(replayed.ledger.balance(&wallet.addresses[0]), node.ledger.balance(&wallet.addresses[0]));
+        assert_eq!(replayed.ledger.balance("b"), node.ledger.balance("b"));
+    }
+
+    fn validator() -> (KeyPair, String) {
+        let keypair = KeyPair::generate();
+        let address = Wallet::generate_address(&keypair.public_key);
+        (keypair, address)
+    }
+
+    fn stakedNode(ledger: Ledger, stakes: &[(String, u64)], keypair: &KeyPair) -> Node {
+        let mut node = testNode(ledger.with_bonds(stakes));
+        node.rewardAddress = Wallet::generate_address(&keypair.public_key);
+        node.validatorKey = Some(keypair.clone());
+        node
+    }
+
+    // Like `branch`, with every block in the next slot that selects `keypair`.
+    async fn proposedBranch(
+        ledger: &Ledger,
+        parent: &[Block],
+        stakes: &[(String, u64)],
+        keypair: &KeyPair,
+        count: usize,
+    ) -> Vec<Block> {
+        let mut builder = stakedNode(ledger.clone(), stakes, keypair);
+        for block in parent {
+            builder.processBlock(block.clone()).await;
+        }
+        for _ in 0..count {
+            let slot = (0..).find(|slot| builder.isProposer(*slot)).unwrap();
+            builder.produceBlock(slot).await;
+        }
+        builder.blockchain.split_off(parent.len())
+    }
+
+    #[tokio::test]
+    async fn test_fork_choice_weighs_blocks_by_producer_stake() {
+        let genesis = Ledger::new();
+        let (small, smallAddress) = validator();
+        let (large, largeAddress) = validator();
+        let stakes = [(smallAddress, 10), (largeAddress, 50)];
+        let common = proposedBranch(&genesis, &[], &stakes, &small, 1).await;
+        let heavy = proposedBranch(&genesis, &common, &stakes, &large, 1).await;
+        let light = proposedBranch(&genesis, &common, &stakes, &small, 3).await;
+
+        let mut node = stakedNode(genesis, &stakes, &small);
+        for block in common.iter().chain(&light).chain(&heavy) {
+            node.processBlock(block.clone()).await;
+        }
+        assert_eq!(node.blockchain[1..], heavy[..]);
+    }
+
+    #[tokio::test]
+    async fn test_only_slot_winners_build_blocks() {
+        let (ours, ourAddress) = validator();
+        let (_, theirAddress) = validator();
+        let stakes = [(ourAddress, 10), (theirAddress, 10)];
+        let mut node = stakedNode(Ledger::new(), &stakes, &ours);
+        let won = |node: &Node, slot: u64| node.consensus.proveEligibility(&ours, "", slot).is_some();
+
+        let lostSlot = (0..).find(|slot| !won(&node, *slot)).unwrap();
+        node.produceBlock(lostSlot).await;
+        assert!(node.blockchain.is_empty());
+
+        let wonSlot = (0..).find(|slot| won(&node, *slot)).unwrap();
+        node.produceBlock(wonSlot).await;
+        assert_eq!(node.blockchain.len(), 1);
+        let block = &node.blockchain[0];
+        assert_eq!(block.slot(), wonSlot);
+        assert!(block.hasValidSignature());
+        assert_eq!(node.consensus.verifyProposer("", wonSlot, &block.blockHeader), Ok(()));
+        assert!(!node.isProposer(wonSlot));
+    }
+
+    #[tokio::test]
+    async fn test_blocks_from_wrong_proposer_rejected() {
+        let (first, firstAddress) = validator();
+        let (second, secondAddress) = validator();
+        let (outsider, outsiderAddress) = validator();
+        let stakes = [(firstAddress.clone(), 10), (secondAddress.clone(), 10)];
+        let mut node = stakedNode(Ledger::new(), &stakes, &first);
+        let (slot, proof) = (0..)
+            .find_map(|slot| node.consensus.proveEligibility(&second, "", slot).map(|proof| (slot, proof)))
+            .unwrap();
+        let signed = |producer: &String, keypair: &KeyPair, prevHash: String, slot: u64| {
+            let height = if prevHash.is_empty() { 0 } else { 1 };
+            let mut block = Block::new(height, prevHash, producer.clone(), Vec::new());
+            block.blockHeader.slot = slot;
+            block.blockHeader.vrfProof = proof.clone();
+            block.sign(keypair);
+            block
+        };
+
+        let stolen = signed(&firstAddress, &first, String::new(), slot);
+        assert_eq!(node.storeBlock(stolen), Err(BlockError::Proposer(ProposerError::InvalidProof)));
+        let forged = signed(&secondAddress, &first, String::new(), slot);
+        assert_eq!(node.storeBlock(forged), Err(BlockError::InvalidSignature));
+        let unstaked = signed(&outsiderAddress, &outsider, String::new(), slot);
+        assert!(matches!(
+            node.storeBlock(unstaked),
+            Err(BlockError::Proposer(ProposerError::NotValidator(_)))
+        ));
+        let mut resigned = signed(&secondAddress, &second, String::new(), slot);
+        resigned.signature = signed(&secondAddress, &second, String::new(), slot + 1).signature;
+        assert_eq!(node.storeBlock(resigned), Err(BlockError::InvalidSignature));
+        let mut reseeded = signed(&secondAddress, &second, String::new(), slot);
+        reseeded.blockHeader.seed = "chosen".to_string();
+        reseeded.sign(&second);
+        assert_eq!(node.storeBlock(reseeded), Err(BlockError::WrongSeed));
+        let early = signed(&secondAddress, &second, String::new(), ProofOfStake::slotAt(Utc::now().timestamp()) + 10);
+        assert!(matches!(node.storeBlock(early), Err(BlockError::FutureSlot(_))));
+
+        let genuine = signed(&secondAddress, &second, String::new(), slot);
+        let hash = genuine.calculateHash();
+        assert_eq!(node.storeBlock(genuine), Ok(()));
+        let sameSlot = signed(&secondAddress, &second, hash, slot);
+        assert!(matches!(node.storeBlock(sameSlot), Err(BlockError::SlotNotAfterParent { .. })));
+        assert_eq!(node.blockchain.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_epoch_seed_fixed_two_epochs_ahead() {
+        let (ours, ourAddress) = validator();
+        let stakes = [(ourAddress, 10)];
+        let mut node = stakedNode(Ledger::new(), &stakes, &ours);
+        let mut follower = stakedNode(Ledger::new(), &stakes, &ours);
+        for epoch in 0..3 {
+            let slot = (epoch * SLOTS_PER_EPOCH..(epoch + 1) * SLOTS_PER_EPOCH)
+                .find(|slot| node.isProposer(*slot))
+                .unwrap();
+            node.produceBlock(slot).await;
+            let block = node.blockchain.last().unwrap().clone();
+            assert_eq!(follower.storeBlock(block), Ok(()));
+        }
+
+        // Epoch 2 is elected from the beacon after epoch 0's last block,
+        // whichever block of epoch 1 or 2 it builds on.
+        let seed = ProofOfStake::nextBeacon(&node.blockchain[0].blockHeader);
+        let epochTwo = 2 * SLOTS_PER_EPOCH;
+        assert_eq!(node.epochSeed(&node.blockchain[1].calculateHash(), epochTwo), seed);
+        assert_eq!(node.epochSeed(&node.tipHash(), 3 * SLOTS_PER_EPOCH - 1), seed);
+        assert_ne!(node.blockchain[2].blockHeader.seed, seed);
+        assert_eq!(node.epochSeed(&node.tipHash(), SLOTS_PER_EPOCH), "");
+    }
+
+    #[tokio::test]
+    async fn test_validator_set_follows_bonds() {
+        // Fixed keys keep the elected slots the same from run to run.
+        let fixedKey = |seed: u8| KeyPair::from_private_key(SignatureAlgorithm::Ed25519, &[seed; 32]).unwrap();
+        let ours = fixedKey(1);
+        let ourAddress = Wallet::generate_address(&ours.public_key);
+        let mut joiner = Wallet::from_keypair(fixedKey(2));
+        let joinerAddress = joiner.addresses[0].clone();
+        let genesis = Ledger::from_genesis(&[(joinerAddress.clone(), 100)]);
+        let mut node = stakedNode(genesis, &[(ourAddress.clone(), 10)], &ours);
+        joiner.sync_from_ledger(&node.ledger);
+
+        let bond = joiner.send_staking(TransactionKind::Bond, 30).unwrap();
+        let mut foreign = bond.clone();
+        foreign.receiver = ourAddress;
+        assert_eq!(node.processTransaction(foreign).await, Err(TransactionError::ForeignStake));
+        assert_eq!(node.processTransaction(bond).await, Ok(()));
+        // The next slot of `epoch` after the tip that elects us.
+        let produceInEpoch = |epoch: u64, node: &Node| {
+            let first = node.blockchain.last().map_or(0, |tip| tip.slot() + 1).max(epoch * SLOTS_PER_EPOCH);
+            (first..(epoch + 1) * SLOTS_PER_EPOCH).find(|slot| node.isProposer(*slot)).unwrap()
+        };
+        node.produceBlock(produceInEpoch(0, &node)).await;
+        // Bonds take effect from the next epoch.
+        assert_eq!(node.consensus.stakeOf(&joinerAddress), 0);
+        assert_eq!(node.ledger.stake(&joinerAddress).bonded, 30);
+
+        node.produceBlock(produceInEpoch(1, &node)).await;
+        assert_eq!(node.consensus.stakeOf(&joinerAddress), 30);
+        assert_eq!(node.consensus.totalStake(), 40);
+
+        let unbond = joiner.send_staking(TransactionKind::Unbond, 30).unwrap();
+        assert_eq!(node.processTransaction(unbond).await, Ok(()));
+        node.produceBlock(produceInEpoch(1, &node)).await;
+        assert_eq!(node.consensus.stakeOf(&joinerAddress), 30);
+        node.produceBlock(produceInEpoch(2, &node)).await;
+        assert_eq!(node.blockchain.len(), 4);
+        assert_eq!(node.consensus.stakeOf(&joinerAddress), 0);
+
+        let history: Vec<u64> = (0..4).map(|height| node.validatorSetAt(height).unwrap().stakeOf(&joinerAddress)).collect();
+        assert_eq!(history, vec![0, 30, 30, 0]);
+        assert!(node.validatorSetAt(4).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_double_signing_reported_and_slashed() {
+        let (ours, ourAddress) = validator();
+        let (cheat, cheatAddress) = validator();
+        let stakes = [(ourAddress.clone(), 100), (cheatAddress.clone(), 100)];
+        let genesis = Ledger::from_genesis(&[(ourAddress.clone(), 100)]);
+        let mut node = stakedNode(genesis.clone(), &stakes, &ours);
+        let mut builder = stakedNode(genesis, &stakes, &cheat);
+        let slot = (0..).find(|slot| builder.isProposer(*slot)).unwrap();
+        builder.produceBlock(slot).await;
+        let first = builder.blockchain[0].clone();
+        let mut second = first.clone();
+        second.blockHeader.timestamp += 1;
+        second.sign(&cheat);
+
+        let evidence = DoubleSignEvidence::new(SignedHeader::of(&first), SignedHeader::of(&second));
+        assert_eq!(node.storeBlock(first), Ok(()));
+        assert!(node.mempool.is_empty());
+        assert_eq!(node.storeBlock(second), Ok(()));
+        let report = node.mempool.iter().next().unwrap().tx.clone();
+        assert_eq!((report.kind, &report.receiver), (TransactionKind::Evidence, &cheatAddress));
+
+        // A validator that hears of it from a peer relays it without
+        // submitting a report of its own.
+        let (other, _) = validator();
+        let mut relay = stakedNode(Ledger::new(), &stakes, &other);
+        let address: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        relay.addPeer(Peer::new(address, false, 0));
+        relay.handlePeerMessage(address, NetworkMessage::Evidence(Box::new(evidence))).await;
+        assert_eq!(relay.seenEvidence.len(), 1);
+        assert!(relay.mempool.is_empty());
+        assert!(!relay.misbehavior.contains_key(&address));
+
+        let ourSlot = (slot + 1..).find(|slot| node.isProposer(*slot)).unwrap();
+        node.produceBlock(ourSlot).await;
+        assert_eq!(node.blockchain.len(), 2);
+        assert!(node.ledger.is_jailed(&cheatAddress));
+        assert_eq!(node.ledger.stake(&cheatAddress).bonded, 90);
+        assert_eq!(node.consensus.stakeOf(&cheatAddress), 0);
+        assert_eq!(node.ledger.balance(&ourAddress), 105);
+    }
+
+    #[tokio::test]
+    async fn test_expired_evidence_not_included() {
+        let (ours, ourAddress) = validator();
+        let (cheat, cheatAddress) = validator();
+        let stakes = [(ourAddress.clone(), 100), (cheatAddress.clone(), 100)];
+        let genesis = Ledger::from_genesis(&[(ourAddress.clone(), 100)]);
+        let mut node = stakedNode(genesis.clone(), &stakes, &ours);
+        let mut builder = stakedNode(genesis, &stakes, &cheat);
+        let slot = (0..).find(|slot| builder.isProposer(*slot)).unwrap();
+        builder.produceBlock(slot).await;
+        let first = builder.blockchain[0].clone();
+        let mut second = first.clone();
+        second.blockHeader.timestamp += 1;
+        second.sign(&cheat);
+        assert_eq!(node.storeBlock(first), Ok(()));
+        assert_eq!(node.storeBlock(second), Ok(()));
+        assert_eq!(node.mempool.len(), 1);
+
+        // Our next slot is in the first epoch the evidence no longer slashes in.
+        let expiry = (ProofOfStake::epochOf(slot) + UNBONDING_EPOCHS) * SLOTS_PER_EPOCH;
+        let ourSlot = (expiry..).find(|slot| node.isProposer(*slot)).unwrap();
+        node.produceBlock(ourSlot).await;
+        assert_eq!(node.blockchain.len(), 2);
+        assert!(node.blockchain[1].transactions.is_empty());
+        assert!(node.mempool.is_empty());
+        assert!(!node.ledger.is_jailed(&cheatAddress));
+    }
+
+    #[tokio::test]
+    async fn test_delegated_stake_elects_and_earns() {
+        let (ours, ourAddress) = validator();
+        let mut delegator = Wallet::new();
+        let delegatorAddress = delegator.addresses[0].clone();
+        let policy = MonetaryPolicy {
+            block_reward: 40,
+            max_supply: u64::MAX,
+            ..MonetaryPolicy::default()
+        };
+        let genesis = Ledger::from_genesis(&[(delegatorAddress.clone(), 100)]).with_policy(policy);
+        let mut node = stakedNode(genesis, &[(ourAddress.clone(), 10)], &ours);
+        delegator.sync_from_ledger(&node.ledger);
+        assert!(delegator.send_delegation(TransactionKind::Bond, &ourAddress, 30).is_err());
+
+        let delegation = delegator.send_delegation(TransactionKind::Delegate, &ourAddress, 30).unwrap();
+        assert_eq!(node.processTransaction(delegation.clone()).await, Ok(()));
+        let slot = (0..SLOTS_PER_EPOCH).find(|slot| node.isProposer(*slot)).unwrap();
+        node.produceBlock(slot).await;
+        // The delegation backs us, and earns, from the next epoch.
+        assert_eq!(node.ledger.balance(&delegatorAddress), 70 - delegation.fee);
+        assert_eq!(node.ledger.balance(&ourAddress), 40 + delegation.fee);
+        assert_eq!(node.consensus.stakeOf(&ourAddress), 10);
+
+        let nextEpoch = (SLOTS_PER_EPOCH..2 * SLOTS_PER_EPOCH).find(|slot| node.isProposer(*slot)).unwrap();
+        node.produceBlock(nextEpoch).await;
+        assert_eq!(node.blockchain.len(), 2);
+        assert_eq!(node.consensus.stakeOf(&ourAddress), 40);
+        // Without a commission the block's earnings are split 30:10.
+        assert_eq!(node.ledger.balance(&delegatorAddress), 70 - delegation.fee + 30);
+        assert_eq!(node.ledger.balance(&ourAddress), 40 + delegation.fee + 10);
+        assert_eq!(node.consensus.stakeOf(&delegatorAddress), 0);
+    }
+
+    #[tokio::test]
+    async fn test_evidence_without_stake_not_relayed() {
+        let (ours, ourAddress) = validator();
+        let (throwaway, throwawayAddress) = validator();
+        let mut node = stakedNode(Ledger::new(), &[(ourAddress, 10)], &ours);
+        let address: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        node.addPeer(Peer::new(address, false, 0));
+        let signed = |prevHash: &str| {
+            let mut block = Block::new(1, prevHash.to_string(), throwawayAddress.clone(), Vec::new());
+            block.blockHeader.slot = 3;
+            block.sign(&throwaway);
+            SignedHeader::of(&block)
+        };
+        let forged = DoubleSignEvidence::new(signed("a"), signed("b"));
+        assert_eq!(forged.verify(), Ok(()));
+
+        node.handlePeerMessage(address, NetworkMessage::Evidence(Box::new(forged))).await;
+        assert!(node.seenEvidence.is_empty());
+        assert!(node.mempool.is_empty());
+        assert_eq!(node.misbehavior[&address], INVALID_TRANSACTION_PENALTY);
+
+        // What we remember about proposals and evidence goes once final.
+        node.proposals.insert((throwawayAddress.clone(), 3), ("a".to_string(), 0));
+        node.proposals.insert((throwawayAddress, 4), ("b".to_string(), 1));
+        node.seenEvidence.insert("old".to_string(), 0);
+        node.blockchain = vec![Block::new(0, String::new(), String::new(), Vec::new()); FINALITY_DEPTH as usize + 1];
+        node.forgetFinalized();
+        assert_eq!(node.proposals.len(), 1);
+        assert!(node.seenEvidence.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_signed_slots_not_signed_again_on_older_tip() {
+        let (ours, ourAddress) = validator();
+        let stakes = [(ourAddress, 10)];
+        let mut producer = stakedNode(Ledger::new(), &stakes, &ours);
+        let slot = (0..).find(|slot| producer.isProposer(*slot)).unwrap();
+        producer.produceBlock(slot).await;
+        assert_eq!(producer.signingRecord.lastSigned(), Some(slot));
+
+        // As after a reorg onto a branch whose tip is older than our block.
+        let mut restarted = stakedNode(Ledger::new(), &stakes, &ours);
+        assert!(restarted.isProposer(slot));
+        restarted.signingRecord = std::mem::take(&mut producer.signingRecord);
+        assert!(!restarted.isProposer(slot));
+        restarted.produceBlock(slot).await;
+        assert!(restarted.blockchain.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_block_reward_checked_and_supply_tracked() {
+        let (ours, ourAddress) = validator();
+        let policy = MonetaryPolicy {
+            genesis_supply: 100,
+            block_reward: 50,
+            max_supply: 1_000,
+            ..MonetaryPolicy::default()
+        };
+        let genesis = Ledger::from_genesis(&[(ourAddress.clone(), 90)]).with_policy(policy);
+        let stakes = [(ourAddress.clone(), 10)];
+        let mut producer = stakedNode(genesis.clone(), &stakes, &ours);
+        let mut node = stakedNode(genesis, &stakes, &ours);
+        let slot = (0..).find(|slot| producer.isProposer(*slot)).unwrap();
+        producer.produceBlock(slot).await;
+        let block = producer.blockchain[0].clone();
+        assert_eq!(block.blockHeader.reward, 50);
+        assert_eq!(producer.ledger.balance(&ourAddress), 140);
+        assert_eq!(producer.supplyAt(0), Some(150));
+        assert_eq!(producer.supplyAt(1), None);
+
+        let mut inflated = block.clone();
+        inflated.blockHeader.reward = 500;
+        inflated.sign(&ours);
+        assert_eq!(
+            node.storeBlock(inflated),
+            Err(BlockError::Ledger(LedgerError::WrongReward { expected: 50, actual: 500 }))
+        );
+        assert_eq!(node.storeBlock(block), Ok(()));
+        assert_eq!(node.supplyAt(0), Some(150));
+    }
+
+    #[tokio::test]
+    async fn test_side_branch_validated_against_its_epoch() {
+        let (first, firstAddress) = validator();
+        let (second, secondAddress) = validator();
+        let stakes = [(firstAddress.clone(), 10)];
+        let genesis = Ledger::from_genesis(&[(secondAddress.clone(), 100)]);
+        let mut node = stakedNode(genesis.clone(), &stakes, &first);
+        let mut joiner = Wallet::from_keypair(second.clone());
+        joiner.sync_from_ledger(&node.ledger);
+        node.processTransaction(joiner.send_staking(TransactionKind::Bond, 90).unwrap()).await.unwrap();
+        let bondSlot = (0..SLOTS_PER_EPOCH).find(|slot| node.isProposer(*slot)).unwrap();
+        node.produceBlock(bondSlot).await;
+
+        // The second validator is active in epoch 1 on top of the bond, but
+        // not on a branch that forks before it.
+        let mut builder = stakedNode(genesis, &stakes, &second);
+        builder.processBlock(node.blockchain[0].clone()).await;
+        let slot = (SLOTS_PER_EPOCH..).find(|slot| builder.isProposer(*slot)).unwrap();
+        builder.produceBlock(slot).await;
+        let extension = builder.blockchain[1].clone();
+        assert_eq!(node.storeBlock(extension.clone()), Ok(()));
+        assert_eq!(node.validatorSetAt(1).unwrap().stakeOf(&secondAddress), 90);
+
+        let mut forked = extension;
+        forked.blockHeader.height = 0;
+        forked.blockHeader.prevHash = String::new();
+        forked.blockHeader.seed = String::new();
+        forked.sign(&second);
+        assert!(matches!(
+            node.storeBlock(forked),
+            Err(BlockError::Proposer(ProposerError::NotValidator(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_block_rejected() {
+        let (_, tx) = signedTransaction(SignatureAlgorithm::Ed25519);
+        let genesis = fund(&tx, 100);
+        let block = branch(&genesis, &[], "producer", vec![tx.clone()], 1).await.remove(0);
+        let size = transaction_size(&tx);
+
+        let mut node = testNode(genesis);
+        node.blockSizeLimit = size - 1;
+        assert_eq!(node.storeBlock(block.clone()), Err(BlockError::TooLarge { size, limit: size - 1 }));
+        node.blockSizeLimit = size;
+        assert_eq!(node.storeBlock(block), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_slots_filled_without_transactions() {
+        let (_inbound, receiver) = mpsc::channel(1);
+        let (sender, _) = mpsc::channel(1);
+        let mut node = Node::new("127.0.0.1:0".parse().unwrap(), receiver, sender, usize::MAX, Ledger::new());
+        let _ = tokio::time::timeout(Duration::from_millis(100), node.run(true)).await;
+        assert_eq!(node.blockchain.len(), 1);
+        assert!(node.blockchain[0].transactions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_only_blocks_near_current_slot_relayed() {
+        let mut producer = testNode(Ledger::new());
+        producer.produceBlock(1).await;
+        producer.produceBlock(ProofOfStake::slotAt(Utc::now().timestamp())).await;
+
+        let mut node = testNode(Ledger::new());
+        let address: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let (peer, mut sent) = connectedPeer(address, address);
+        node.addPeer(peer);
+
+        assert_eq!(node.storeBlock(producer.blockchain[0].clone()), Ok(()));
+        assert!(sent.try_recv().is_err());
+        assert_eq!(node.storeBlock(producer.blockchain[1].clone()), Ok(()));
+        assert!(matches!(sent.try_recv(), Ok(NetworkMessage::Inv(_))));
+    }
+
+    #[tokio::test]
+    async fn test_finalized_blocks_not_reorganized() {
+        let genesis = Ledger::new();
+        let common = branch(&genesis, &[], "a", Vec::new(), 1).await;
+        let ours = branch(&genesis, &common, "a", Vec::new(), FINALITY_DEPTH as usize + 1).await;
+        let theirs = branch(&genesis, &common, "b", Vec::new(), FINALITY_DEPTH as usize + 2).await;
+
+        let mut node = testNode(genesis);
+        for block in common.iter().chain(&ours).chain(&theirs) {
+            node.processBlock(block.clone()).await;
+        }
+        assert_eq!(node.blockchain[1..], ours[..]);
+        assert_eq!(node.blockTree.len(), 1 + ours.len());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_transactions_add_up_to_ban() {
+        let (_, tx) = signedTransaction(SignatureAlgorithm::Ed25519);
+        let mut node = testNode(Ledger::new());
+        let address: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        node.addPeer(Peer::new(address, false, 0));
+
+        // An unfunded transaction is a pool rejection and costs nothing.
+        node.handlePeerMessage(address, NetworkMessage::Transaction(tx.clone())).await;
+        assert!(node.misbehavior.is_empty());
+
+        let mut unsigned = tx;
+        unsigned.signature.clear();
+        for _ in 1..BAN_THRESHOLD / INVALID_TRANSACTION_PENALTY {
+            node.handlePeerMessage(address, NetworkMessage::Transaction(unsigned.clone())).await;
+        }
+        assert_eq!(node.misbehavior[&address], BAN_THRESHOLD - INVALID_TRANSACTION_PENALTY);
+        node.handlePeerMessage(address, NetworkMessage::Transaction(unsigned)).await;
+        assert!(!node.peers.contains_key(&address));
+        assert!(node.banList.is_banned(&address, Utc::now().timestamp()));
+    }
+
+    #[tokio::test]
+    async fn test_ban_covers_remote_ip_but_not_other_local_peers() {
+        let mut node = testNode(Ledger::new());
+        let local = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        for port in [9, 10] {
+            node.addPeer(connectedPeer(local(port), local(40000 + port)).0);
+        }
+        node.misbehaving(local(9), BAN_THRESHOLD, "test");
+        assert!(!node.peers.contains_key(&local(9)));
+        assert!(node.peers[&local(10)].is_connected());
+        assert!(!node.banList.is_banned(&local(10), Utc::now().timestamp()));
+
+        let remote = |port| SocketAddr::from(([10, 0, 0, 1], port));
+        for port in [9, 10] {
+            node.addPeer(connectedPeer(remote(port), remote(40000 + port)).0);
+        }
+        node.misbehaving(remote(9), BAN_THRESHOLD, "test");
+        assert!(!node.peers.contains_key(&remote(9)));
+        assert!(!node.peers.contains_key(&remote(10)));
+        assert!(node.banList.is_banned(&remote(0), Utc::now().timestamp()));
+        assert!(node.peers.contains_key(&local(10)));
+    }
+
+    #[tokio::test]
+    async fn test_unrequested_orphans_add_up_to_ban() {
+        let mut node = testNode(Ledger::new());
+        let address: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        node.addPeer(Peer::new(address, false, 0));
+
+        for index in 0..BAN_THRESHOLD / ORPHAN_BLOCK_PENALTY {
+            assert!(node.peers.contains_key(&address));
+            let orphan = Block::new(1, format!("unknown{}", index), "spammer".to_string(), Vec::new());
+            node.handlePeerMessage(address, NetworkMessage::Block(orphan)).await;
+        }
+        assert!(node.banList.is_banned(&address, Utc::now().timestamp()));
+    }
+
+    #[tokio::test]
+    async fn test_message_flood_gets_peer_banned() {
+        let mut node = testNode(Ledger::new());
+        let address: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        node.addPeer(Peer::new(address, false, 0));
+
+        // Enough to stay over the limit even if the clock ticks over twice.
+        let flood = MESSAGE_BURST + 2 * MESSAGES_PER_SEC + BAN_THRESHOLD;
+        for _ in 0..flood {
+            node.handlePeerMessage(address, NetworkMessage::GetAddr).await;
+        }
+        assert!(node.banList.is_banned(&address, Utc::now().timestamp()));
+    }
+
+    // Handles peer events until `connects` new connections have been seen.
+    async fn settle(node: &mut Node, connects: usize) {
+        let mut seen = 0;
+        while seen < connects {
+            let event = tokio::time::timeout(Duration::from_secs(5), node.peerEvents.recv())
+                .await
+                .expect("Timed out waiting for a peer event")
+                .unwrap();
+            if matches!(event, PeerEvent::Connected { .. }) {
+                seen += 1;
             }
-            hashes = newHashes;
+            node.handlePeerEvent(event).await;
         }
-        hashes[0].clone()
     }
 
-    fn hashBlock(&self, block: &BlockData) -> String {
-        let blockBytes = encoding::serialize(block).unwrap();
-        hashing::sha256(&blockBytes)
+    #[tokio::test]
+    async fn test_connected_peers_exchange_messages() {
+        let (_, tx) = signedTransaction(SignatureAlgorithm::Ed25519);
+        let mut remote = testNode(fund(&tx, 100));
+        remote.listen().await.unwrap();
+        let mut origin = testNode(fund(&tx, 100));
+        origin.listen().await.unwrap();
+
+        let (originAddress, remoteAddress) = (origin.address, remote.address);
+        let connected = |node: &Node, address: SocketAddr| node.peers.get(&address).is_some_and(Peer::is_connected);
+
+        origin.connect(remoteAddress);
+        driveUntil(&mut [&mut origin, &mut remote], |nodes| {
+            connected(nodes[0], remoteAddress) && connected(nodes[1], originAddress)
+        })
+        .await;
+
+        origin.processTransaction(tx).await.unwrap();
+        origin.createAndBroadcastBlock().await;
+        driveUntil(&mut [&mut origin, &mut remote], |nodes| nodes[1].blockchain.len() == 1).await;
+        assert_eq!(remote.blockchain, origin.blockchain);
+        assert!(remote.mempool.is_empty());
+
+        origin.disconnect(remoteAddress);
+        driveUntil(&mut [&mut origin, &mut remote], |nodes| !connected(nodes[1], originAddress)).await;
+        assert!(!connected(&origin, remoteAddress));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_mutual_dials_keep_one_connection() {
+        let mut first = testNode(Ledger::new());
+        first.listen().await.unwrap();
+        let mut second = testNode(Ledger::new());
+        second.listen().await.unwrap();
+
+        first.connect(second.address);
+        second.connect(first.address);
+        settle(&mut first, 2).await;
+        settle(&mut second, 2).await;
+
+        let firstDialed = first.peers[&second.address].connection.as_ref().unwrap().dialed;
+        let secondDialed = second.peers[&first.address].connection.as_ref().unwrap().dialed;
+        assert_eq!(firstDialed, first.address < second.address);
+        assert_eq!(secondDialed, !firstDialed);
+    }
+
+    // Handles pending peer events on every node until `done` holds.
+    async fn driveUntil(nodes: &mut [&mut Node], done: impl Fn(&[&mut Node]) -> bool) {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while !done(nodes) {
+            assert!(tokio::time::Instant::now() < deadline, "Timed out waiting for the nodes");
+            let mut idle = true;
+            for node in nodes.iter_mut() {
+                while let Ok(event) = node.peerEvents.try_recv() {
+                    node.handlePeerEvent(event).await;
+                    idle = false;
+                }
+            }
+            if idle {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_addresses_learned_from_one_peer_capped() {
+        let mut node = testNode(Ledger::new());
+        let address: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        node.addPeer(Peer::new(address, false, 0));
+
+        let mut port = 10_000;
+        for _ in 0..MAX_NEW_ADDR_PER_PEER / MAX_NEW_ADDR_PER_MESSAGE + 1 {
+            let gossip: Vec<SocketAddr> = (0..2 * MAX_NEW_ADDR_PER_MESSAGE)
+                .map(|_| {
+                    port += 1;
+                    SocketAddr::from(([10, 0, 0, 1], port))
+                })
+                .collect();
+            let before = node.addressBook.len();
+            node.handlePeerMessage(address, NetworkMessage::Addr(gossip)).await;
+            assert!(node.addressBook.len() - before <= MAX_NEW_ADDR_PER_MESSAGE);
+        }
+        assert_eq!(node.addressBook.len(), MAX_NEW_ADDR_PER_PEER);
+    }
+
+    #[tokio::test]
+    async fn test_new_node_discovers_peers_from_one_seed() {
+        let mut seed = testNode(Ledger::new());
+        seed.listen().await.unwrap();
+        let mut member = testNode(Ledger::new());
+        member.listen().await.unwrap();
+        member.connect(seed.address);
+        let memberAddress = member.address;
+        driveUntil(&mut [&mut seed, &mut member], |nodes| nodes[0].addressBook.contains(&memberAddress)).await;
+
+        let mut newcomer = testNode(Ledger::new());
+        newcomer.listen().await.unwrap();
+        newcomer.connect(seed.address);
+        driveUntil(&mut [&mut newcomer, &mut seed, &mut member], |nodes| {
+            nodes[0].addressBook.contains(&memberAddress)
+        })
+        .await;
+
+        newcomer.targetOutbound = 2;
+        newcomer.maintainOutbound();
+        driveUntil(&mut [&mut newcomer, &mut seed, &mut member], |nodes| {
+            nodes[0].peers.get(&memberAddress).is_some_and(Peer::is_connected)
+        })
+        .await;
+        assert_eq!(newcomer.peers.values().filter(|peer| peer.is_connected()).count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_late_node_catches_up_from_several_peers() {
+        let (_, tx) = signedTransaction(SignatureAlgorithm::Ed25519);
+        let mut origin = testNode(fund(&tx, 100));
+        origin.processTransaction(tx.clone()).await.unwrap();
+        for _ in 0..3 {
+            origin.createAndBroadcastBlock().await;
+        }
+        let mut mirror = testNode(fund(&tx, 100));
+        for block in origin.blockchain.clone() {
+            mirror.processBlock(block).await;
+        }
+        origin.listen().await.unwrap();
+        mirror.listen().await.unwrap();
+
+        let mut newcomer = testNode(fund(&tx, 100));
+        newcomer.listen().await.unwrap();
+        newcomer.connect(origin.address);
+        newcomer.connect(mirror.address);
+        driveUntil(&mut [&mut newcomer, &mut origin, &mut mirror], |nodes| nodes[0].blockchain.len() == 3).await;
+
+        assert_eq!(newcomer.blockchain, origin.blockchain);
+        assert_eq!(newcomer.ledger.balance(&tx.receiver), 10);
+        assert!(!newcomer.sync.isDownloading());
+    }
+
+    #[tokio::test]
+    async fn test_announcements_relay_across_hops() {
+        let (_, tx) = signedTransaction(SignatureAlgorithm::Ed25519);
+        let mut nodes: Vec<Node> = (0..3).map(|_| testNode(fund(&tx, 100))).collect();
+        for node in nodes.iter_mut() {
+            node.listen().await.unwrap();
+        }
+        let addresses: Vec<SocketAddr> = nodes.iter().map(|node| node.address).collect();
+        let [first, middle, last] = &mut nodes[..] else {
+            unreachable!();
+        };
+        first.connect(addresses[1]);
+        last.connect(addresses[1]);
+        driveUntil(&mut [first, middle, last], |nodes| {
+            nodes[1].peers.values().filter(|peer| peer.is_connected()).count() == 2
+        })
+        .await;
+
+        first.processTransaction(tx.clone()).await.unwrap();
+        driveUntil(&mut [first, middle, last], |nodes| nodes[2].mempool.len() == 1).await;
+        first.createAndBroadcastBlock().await;
+        driveUntil(&mut [first, middle, last], |nodes| nodes[2].blockchain.len() == 1).await;
+
+        assert_eq!(last.blockchain, first.blockchain);
+        assert!(last.mempool.is_empty());
+        let item = InventoryItem::Transaction(tx.calculateHash());
+        assert!(middle.peers.values().all(|peer| peer.known_inventory.contains(&item)));
+        assert!(!last.peers.contains_key(&addresses[0]));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_block_bans_peer_and_refuses_reconnect() {
+        let mut victim = testNode(Ledger::new());
+        let mut attacker = testNode(Ledger::new());
+        victim.listen().await.unwrap();
+        attacker.listen().await.unwrap();
+        let (victimAddress, attackerAddress) = (victim.address, attacker.address);
+        attacker.connect(victimAddress);
+        driveUntil(&mut [&mut victim, &mut attacker], |nodes| {
+            nodes.iter().all(|node| node.peers.values().any(|peer| peer.is_connected()))
+        })
+        .await;
+
+        let mut forged = Block::new(0, String::new(), "attacker".to_string(), Vec::new());
+        forged.blockHeader.merkleRoot = "forged".to_string();
+        attacker.sendTo(victimAddress, NetworkMessage::Block(forged));
+        driveUntil(&mut [&mut victim, &mut attacker], |nodes| {
+            nodes[0].banList.is_banned(&attackerAddress, Utc::now().timestamp())
+        })
+        .await;
+        assert!(!victim.peers.contains_key(&attackerAddress));
+        assert!(victim.blockchain.is_empty());
+
+        let (outbound, _) = mpsc::channel(1);
+        let version = VersionData {
+            protocolVersion: PROTOCOL_VERSION,
+            chainId: DEFAULT_CHAIN_ID,
+            bestHeight: 0,
+            address: attackerAddress,
+            stake: 0,
+        };
+        let connection = PeerConnection { id: 0, outbound, dialed: false, remote: attackerAddress };
+        victim
+            .handlePeerEvent(PeerEvent::Connected { address: attackerAddress, version, connection })
+            .await;
+        assert!(!victim.peers.contains_key(&attackerAddress));
+    }
+}
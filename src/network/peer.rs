@@ -1,11 +1,62 @@
 use serde::{Serialize, Deserialize};
 use std::net::SocketAddr;
+use crate::network::connection::PeerConnection;
+use crate::network::inventory::KnownInventory;
+
+pub const MESSAGES_PER_SEC: u32 = 200;
+pub const MESSAGE_BURST: u32 = 1000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Peer {
     pub address: SocketAddr,
     pub is_super_node: bool, 
     pub stake: u64,
+    pub best_height: u64,
+    #[serde(skip)]
+    pub connection: Option<PeerConnection>,
+    #[serde(skip)]
+    pub known_inventory: KnownInventory,
+    #[serde(skip)]
+    pub rate_limiter: RateLimiter,
+    // New addresses its `Addr` messages added to our address book.
+    #[serde(skip)]
+    pub addresses_learned: usize,
+}
+
+// Token bucket allowing MESSAGES_PER_SEC on average and bursts of up to
+// MESSAGE_BURST messages.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    tokens: u32,
+    refilled_at: i64,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self {
+            tokens: MESSAGE_BURST,
+            refilled_at: 0,
+        }
+    }
+}
+
+impl RateLimiter {
+    // Takes a token for a message received at `now`, if one is left.
+    pub fn allow(&mut self, now: i64) -> bool {
+        if now > self.refilled_at {
+            let elapsed = (now - self.refilled_at).min(MESSAGE_BURST as i64) as u32;
+            self.tokens = self
+                .tokens
+                .saturating_add(elapsed.saturating_mul(MESSAGES_PER_SEC))
+                .min(MESSAGE_BURST);
+            self.refilled_at = now;
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
 }
 
 impl Peer {
@@ -14,6 +65,11 @@ impl Peer {
             address,
             is_super_node,
             stake,
+            best_height: 0,
+            connection: None,
+            known_inventory: KnownInventory::new(),
+            rate_limiter: RateLimiter::default(),
+            addresses_learned: 0,
         }
     }
 
@@ -25,7 +81,31 @@ impl Peer {
         self.stake = new_stake;
     }
 
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
     pub fn has_valid_stake(&self) -> bool {
         self.stake > 0
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_refills_over_time() {
+        let mut limiter = RateLimiter::default();
+        for _ in 0..MESSAGE_BURST {
+            assert!(limiter.allow(10));
+        }
+        assert!(!limiter.allow(10));
+
+        assert!(limiter.allow(11));
+        for _ in 1..MESSAGES_PER_SEC {
+            assert!(limiter.allow(11));
+        }
+        assert!(!limiter.allow(11));
+    }
+}
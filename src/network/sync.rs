@@ -0,0 +1,437 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use thiserror::Error;
+use crate::blockchain::{Block, BlockHeader};
+
+pub const MAX_HEADERS_PER_MESSAGE: usize = 2000;
+pub const MAX_BLOCKS_PER_REQUEST: usize = 128;
+pub const MAX_BLOCKS_IN_FLIGHT_PER_PEER: usize = 16;
+// Blocks are only requested this far past our tip, which also bounds how
+// many out-of-order blocks wait in the orphan buffer.
+pub const DOWNLOAD_WINDOW: u64 = 512;
+pub const MAX_ORPHANS: usize = 1024;
+// Headers past this many waiting for their blocks are dropped, and no more
+// are asked for until blocks have been stored.
+pub const MAX_QUEUED_HEADERS: usize = 4 * MAX_HEADERS_PER_MESSAGE;
+pub const HEADERS_TIMEOUT_SECS: i64 = 30;
+pub const BLOCK_DOWNLOAD_TIMEOUT_SECS: i64 = 20;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SyncError {
+    #[error("Received {0} headers, more than a message may carry")]
+    TooManyHeaders(usize),
+
+    #[error("Header at height {0} does not extend the known chain")]
+    Unconnected(u64),
+
+    #[error("Received headers that were not asked for")]
+    Unrequested,
+}
+
+struct Request {
+    peer: SocketAddr,
+    sentAt: i64,
+}
+
+struct QueuedHeader {
+    height: u64,
+    // The peer that sent the header, whose headers are forgotten when it
+    // goes away or stops answering.
+    peer: SocketAddr,
+}
+
+// Tracks header-first catch-up: headers of blocks we don't have yet, which
+// of those blocks are being fetched from whom, and blocks that arrived before
+// their parent. It only decides what to ask for; the node does the sending.
+#[derive(Default)]
+pub struct ChainSync {
+    // In the order they were announced; entries whose block has since been
+    // stored are skipped and trimmed from the ends.
+    headers: VecDeque<(String, BlockHeader)>,
+    queued: HashMap<String, QueuedHeader>,
+    headerRequest: Option<Request>,
+    inFlight: HashMap<String, Request>,
+    orphans: HashMap<String, Block>,
+}
+
+impl ChainSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Whether headers are known for blocks we don't have yet.
+    pub fn isDownloading(&self) -> bool {
+        !self.queued.is_empty()
+    }
+
+    pub fn headerCount(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn inFlightCount(&self) -> usize {
+        self.inFlight.len()
+    }
+
+    pub fn orphanCount(&self) -> usize {
+        self.orphans.len()
+    }
+
+    // Height and hash of the last announced header still waiting for its block.
+    pub fn headerTip(&self) -> Option<(u64, &str)> {
+        self.headers.back().map(|(hash, header)| (header.height, hash.as_str()))
+    }
+
+    // Claims the single outstanding headers request for `peer`. Fails while
+    // another request is still waiting for its answer or the queue is full.
+    pub fn requestHeaders(&mut self, peer: SocketAddr, now: i64) -> bool {
+        if self.headerRequest.is_some() || self.queued.len() >= MAX_QUEUED_HEADERS {
+            return false;
+        }
+        self.headerRequest = Some(Request { peer, sentAt: now });
+        true
+    }
+
+    // Queues headers `peer` sent in answer to our request whose parent is
+    // either stored (`storedHeight` knows its height) or queued already, so
+    // branches other than our own can be followed. Headers we already have
+    // are skipped, and those past MAX_QUEUED_HEADERS dropped. Returns how
+    // many were added.
+    pub fn receiveHeaders(
+        &mut self,
+        peer: SocketAddr,
+        headers: Vec<BlockHeader>,
+        storedHeight: impl Fn(&str) -> Option<u64>,
+    ) -> Result<usize, SyncError> {
+        if self.headerRequest.as_ref().is_none_or(|request| request.peer != peer) {
+            return Err(SyncError::Unrequested);
+        }
+        self.headerRequest = None;
+        if headers.len() > MAX_HEADERS_PER_MESSAGE {
+            return Err(SyncError::TooManyHeaders(headers.len()));
+        }
+
+        let mut added = 0;
+        for header in headers {
+            if self.queued.len() >= MAX_QUEUED_HEADERS {
+                break;
+            }
+            let hash = header.calculateHash();
+            if self.queued.contains_key(&hash) || storedHeight(&hash).is_some() {
+                continue;
+            }
+            let parentHeight = self
+                .queued
+                .get(&header.prevHash)
+                .map(|parent| parent.height)
+                .or_else(|| storedHeight(&header.prevHash));
+            let linked = match parentHeight {
+                Some(parentHeight) => header.height == parentHeight + 1,
+                None => header.height == 0 && header.prevHash.is_empty(),
+            };
+            if !linked {
+                return Err(SyncError::Unconnected(header.height));
+            }
+            self.queued.insert(hash.clone(), QueuedHeader { height: header.height, peer });
+            self.headers.push_back((hash, header));
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    // Assigns blocks of known headers to peers that have them, least busy
+    // peer first, so consecutive blocks are fetched from several peers at
+    // once. `peers` pairs each connected peer with its chain length.
+    pub fn schedule(&mut self, peers: &[(SocketAddr, u64)], now: i64) -> Vec<(SocketAddr, Vec<String>)> {
+        let mut load: HashMap<SocketAddr, usize> = peers.iter().map(|(peer, _)| (*peer, 0)).collect();
+        for request in self.inFlight.values() {
+            if let Some(count) = load.get_mut(&request.peer) {
+                *count += 1;
+            }
+        }
+
+        let mut requests: Vec<(SocketAddr, Vec<String>)> = Vec::new();
+        let firstHeight = self.headers.front().map_or(0, |(_, header)| header.height);
+        for (hash, header) in &self.headers {
+            if header.height >= firstHeight + DOWNLOAD_WINDOW {
+                break;
+            }
+            if !self.queued.contains_key(hash) || self.inFlight.contains_key(hash) || self.orphans.contains_key(hash) {
+                continue;
+            }
+            let Some(peer) = peers
+                .iter()
+                .filter(|(peer, bestHeight)| *bestHeight > header.height && load[peer] < MAX_BLOCKS_IN_FLIGHT_PER_PEER)
+                .min_by_key(|(peer, _)| (load[peer], *peer))
+                .map(|(peer, _)| *peer)
+            else {
+                continue;
+            };
+            *load.get_mut(&peer).unwrap() += 1;
+            self.inFlight.insert(hash.clone(), Request { peer, sentAt: now });
+            match requests.iter_mut().find(|(requested, _)| *requested == peer) {
+                Some((_, hashes)) => hashes.push(hash.clone()),
+                None => requests.push((peer, vec![hash.clone()])),
+            }
+        }
+        requests
+    }
+
+    pub fn isInFlight(&self, hash: &str) -> bool {
+        self.inFlight.contains_key(hash)
+    }
+
+    pub fn blockArrived(&mut self, hash: &str) {
+        self.inFlight.remove(hash);
+    }
+
+    // Called once the block is in our block tree, on whichever branch.
+    pub fn blockStored(&mut self, hash: &str) {
+        if self.queued.remove(hash).is_none() {
+            return;
+        }
+        self.trimHeaders();
+    }
+
+    fn trimHeaders(&mut self) {
+        while self.headers.front().is_some_and(|(hash, _)| !self.queued.contains_key(hash)) {
+            self.headers.pop_front();
+        }
+        while self.headers.back().is_some_and(|(hash, _)| !self.queued.contains_key(hash)) {
+            self.headers.pop_back();
+        }
+    }
+
+    // Buffers a block whose parent we don't have yet. When full, the orphan
+    // furthest from our tip makes room.
+    pub fn addOrphan(&mut self, hash: String, block: Block) {
+        if self.orphans.len() >= MAX_ORPHANS {
+            let furthest = self
+                .orphans
+                .iter()
+                .max_by_key(|(_, orphan)| orphan.height())
+                .map(|(hash, _)| hash.clone());
+            match furthest {
+                Some(furthest) if self.orphans[&furthest].height() > block.height() => {
+                    self.orphans.remove(&furthest);
+                }
+                _ => return,
+            }
+        }
+        self.orphans.insert(hash, block);
+    }
+
+    pub fn isOrphan(&self, hash: &str) -> bool {
+        self.orphans.contains_key(hash)
+    }
+
+    // Removes and returns the buffered children of `parentHash`.
+    pub fn takeOrphansOf(&mut self, parentHash: &str) -> Vec<Block> {
+        let children: Vec<String> = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| orphan.blockHeader.prevHash == parentHash)
+            .map(|(hash, _)| hash.clone())
+            .collect();
+        children
+            .into_iter()
+            .filter_map(|hash| self.orphans.remove(&hash))
+            .collect()
+    }
+
+    // Releases everything asked of `peer` so it can be asked of others, and
+    // forgets the headers it sent.
+    pub fn peerGone(&mut self, peer: SocketAddr) {
+        self.inFlight.retain(|_, request| request.peer != peer);
+        if self.headerRequest.as_ref().is_some_and(|request| request.peer == peer) {
+            self.headerRequest = None;
+        }
+        self.forgetHeaders(|_, sender| sender == peer);
+    }
+
+    // Releases requests that went unanswered for too long. The peers asked
+    // lose the headers they sent, and a block that didn't come loses its
+    // header, to be fetched again if some peer still has it.
+    pub fn expire(&mut self, now: i64) {
+        let mut silent = HashSet::new();
+        let mut missing = HashSet::new();
+        self.inFlight.retain(|hash, request| {
+            let waiting = now - request.sentAt < BLOCK_DOWNLOAD_TIMEOUT_SECS;
+            if !waiting {
+                silent.insert(request.peer);
+                missing.insert(hash.clone());
+            }
+            waiting
+        });
+        if let Some(request) = self.headerRequest.take_if(|request| now - request.sentAt >= HEADERS_TIMEOUT_SECS) {
+            silent.insert(request.peer);
+        }
+        if !silent.is_empty() {
+            self.forgetHeaders(|hash, sender| silent.contains(&sender) || missing.contains(hash));
+        }
+    }
+
+    // Forgets the queued headers `forget` picks by hash and sender, along
+    // with those queued on top of them, whose blocks could no longer link.
+    fn forgetHeaders(&mut self, forget: impl Fn(&str, SocketAddr) -> bool) {
+        let mut forgotten: HashSet<String> = HashSet::new();
+        for (hash, header) in &self.headers {
+            let Some(queued) = self.queued.get(hash) else {
+                continue;
+            };
+            if forget(hash, queued.peer) || forgotten.contains(&header.prevHash) {
+                forgotten.insert(hash.clone());
+            }
+        }
+        if forgotten.is_empty() {
+            return;
+        }
+        self.queued.retain(|hash, _| !forgotten.contains(hash));
+        self.inFlight.retain(|hash, _| !forgotten.contains(hash));
+        self.headers.retain(|(hash, _)| !forgotten.contains(hash));
+        self.trimHeaders();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn chain(length: u64) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for height in 0..length {
+            let prevHash = blocks.last().map(Block::calculateHash).unwrap_or_default();
+            blocks.push(Block::new(height, prevHash, "producer".to_string(), Vec::new()));
+        }
+        blocks
+    }
+
+    fn headers(blocks: &[Block]) -> Vec<BlockHeader> {
+        blocks.iter().map(|block| block.blockHeader.clone()).collect()
+    }
+
+    fn stored(blocks: &[Block]) -> impl Fn(&str) -> Option<u64> + '_ {
+        |hash| blocks.iter().find(|block| block.calculateHash() == hash).map(Block::height)
+    }
+
+    #[test]
+    fn test_headers_must_extend_known_blocks() {
+        let blocks = chain(6);
+        let mut sync = ChainSync::new();
+
+        assert!(sync.requestHeaders(peer(1), 0));
+        assert!(!sync.requestHeaders(peer(2), 0));
+        assert_eq!(sync.receiveHeaders(peer(1), headers(&blocks[..4]), stored(&blocks[..2])), Ok(2));
+        assert_eq!(sync.headerTip(), Some((3, blocks[3].calculateHash().as_str())));
+        assert!(sync.requestHeaders(peer(2), 0));
+
+        assert_eq!(
+            sync.receiveHeaders(peer(2), headers(&blocks[5..]), stored(&blocks[..2])),
+            Err(SyncError::Unconnected(5))
+        );
+        assert!(sync.requestHeaders(peer(2), 0));
+        assert_eq!(sync.receiveHeaders(peer(2), headers(&blocks[4..]), stored(&blocks[..2])), Ok(2));
+        assert_eq!(sync.headerCount(), 4);
+
+        let fork = Block::new(1, blocks[0].calculateHash(), "other".to_string(), Vec::new());
+        assert!(sync.requestHeaders(peer(2), 0));
+        assert_eq!(sync.receiveHeaders(peer(2), headers(&[fork]), stored(&blocks[..2])), Ok(1));
+        assert_eq!(sync.headerCount(), 5);
+    }
+
+    #[test]
+    fn test_downloads_spread_across_peers() {
+        let blocks = chain(5);
+        let mut sync = ChainSync::new();
+        sync.requestHeaders(peer(1), 0);
+        sync.receiveHeaders(peer(1), headers(&blocks), stored(&[])).unwrap();
+
+        let requests = sync.schedule(&[(peer(1), 5), (peer(2), 5), (peer(3), 3)], 0);
+        let assigned: HashMap<SocketAddr, usize> =
+            requests.iter().map(|(peer, hashes)| (*peer, hashes.len())).collect();
+        assert_eq!(assigned[&peer(1)], 2);
+        assert_eq!(assigned[&peer(2)], 2);
+        assert_eq!(assigned[&peer(3)], 1);
+        assert_eq!(sync.inFlightCount(), 5);
+        assert!(sync.schedule(&[(peer(1), 5)], 0).is_empty());
+
+        sync.peerGone(peer(2));
+        let requests = sync.schedule(&[(peer(1), 5)], 1);
+        assert_eq!(requests, vec![(peer(1), vec![blocks[1].calculateHash(), blocks[4].calculateHash()])]);
+
+        sync.expire(BLOCK_DOWNLOAD_TIMEOUT_SECS + 1);
+        assert_eq!(sync.inFlightCount(), 0);
+    }
+
+    #[test]
+    fn test_orphans_wait_for_their_parent() {
+        let blocks = chain(3);
+        let mut sync = ChainSync::new();
+        sync.requestHeaders(peer(1), 0);
+        sync.receiveHeaders(peer(1), headers(&blocks), stored(&[])).unwrap();
+        sync.addOrphan(blocks[2].calculateHash(), blocks[2].clone());
+        sync.addOrphan(blocks[1].calculateHash(), blocks[1].clone());
+
+        assert_eq!(sync.takeOrphansOf(&blocks[0].calculateHash()), vec![blocks[1].clone()]);
+        assert_eq!(sync.orphanCount(), 1);
+
+        sync.blockStored(&blocks[1].calculateHash());
+        assert_eq!(sync.headerCount(), 2);
+        assert_eq!(sync.headerTip(), Some((2, blocks[2].calculateHash().as_str())));
+        sync.blockStored(&blocks[2].calculateHash());
+        sync.blockStored(&blocks[0].calculateHash());
+        assert!(!sync.isDownloading());
+        assert_eq!(sync.headerTip(), None);
+    }
+
+    #[test]
+    fn test_headers_of_gone_or_silent_peers_forgotten() {
+        let blocks = chain(5);
+        let mut sync = ChainSync::new();
+        assert_eq!(
+            sync.receiveHeaders(peer(1), headers(&blocks), stored(&[])),
+            Err(SyncError::Unrequested)
+        );
+
+        // Headers others queued on top of a departed peer's go with them.
+        sync.requestHeaders(peer(1), 0);
+        sync.receiveHeaders(peer(1), headers(&blocks[..3]), stored(&[])).unwrap();
+        sync.requestHeaders(peer(2), 0);
+        sync.receiveHeaders(peer(2), headers(&blocks[3..]), stored(&[])).unwrap();
+        sync.peerGone(peer(1));
+        assert!(!sync.isDownloading());
+        assert_eq!(sync.headerTip(), None);
+
+        sync.requestHeaders(peer(2), 0);
+        sync.receiveHeaders(peer(2), headers(&blocks), stored(&[])).unwrap();
+        sync.schedule(&[(peer(2), 5)], 0);
+        sync.expire(BLOCK_DOWNLOAD_TIMEOUT_SECS);
+        assert!(!sync.isDownloading());
+
+        assert!(sync.requestHeaders(peer(3), 0));
+        sync.expire(HEADERS_TIMEOUT_SECS);
+        assert_eq!(
+            sync.receiveHeaders(peer(3), headers(&blocks), stored(&[])),
+            Err(SyncError::Unrequested)
+        );
+    }
+
+    #[test]
+    fn test_header_queue_capped() {
+        let blocks = chain(MAX_QUEUED_HEADERS as u64 + 1);
+        let mut sync = ChainSync::new();
+        for batch in blocks.chunks(MAX_HEADERS_PER_MESSAGE) {
+            if sync.requestHeaders(peer(1), 0) {
+                sync.receiveHeaders(peer(1), headers(batch), stored(&[])).unwrap();
+            }
+        }
+        assert_eq!(sync.headerCount(), MAX_QUEUED_HEADERS);
+        assert!(!sync.requestHeaders(peer(1), 0));
+
+        sync.blockStored(&blocks[0].calculateHash());
+        assert!(sync.requestHeaders(peer(1), 0));
+    }
+}
@@ -1,7 +1,8 @@
-use sled::{Db, IVec};
+use sled::Db;
 use serde::{Serialize, Deserialize};
 use thiserror::Error;
 use log::{info, error};
+use crate::blockchain::Block;
 
 #[derive(Error, Debug)]
 pub enum StorageError {
@@ -108,7 +109,31 @@ impl Database {
     }
 
     pub fn flush(&self) -> Result<(), StorageError> {
-        self.db.flush().map_err(|e| StorageError::DatabaseError(e.to_string()))
+        self.db.flush().map(|_| ()).map_err(|e| StorageError::DatabaseError(e.to_string()))
+    }
+
+    // Blocks are stored by header hash, with a separate height index for
+    // the blocks on the main chain.
+    pub fn save_block(&self, block: &Block) -> Result<(), StorageError> {
+        let hash = block.calculateHash();
+        self.save(&("block", &hash), block)?;
+        self.save(&("height", block.height()), &hash)
+    }
+
+    pub fn get_block(&self, hash: &str) -> Result<Option<Block>, StorageError> {
+        self.get(&("block", hash))
+    }
+
+    pub fn get_block_at(&self, height: u64) -> Result<Option<Block>, StorageError> {
+        match self.get::<_, String>(&("height", height))? {
+            Some(hash) => self.get_block(&hash),
+            None => Ok(None),
+        }
+    }
+
+    // Takes `height` off the main chain; the block stays stored by hash.
+    pub fn remove_block_at(&self, height: u64) -> Result<(), StorageError> {
+        self.delete(&("height", height))
     }
 }
 
@@ -143,4 +168,19 @@ mod tests {
 
         db.flush().unwrap();
     }
+
+    #[test]
+    fn test_block_round_trip() {
+        let db = Database::new("test_block_db").unwrap();
+        let block = Block::new(3, "parent".to_string(), "producer".to_string(), Vec::new());
+
+        db.save_block(&block).unwrap();
+        assert_eq!(db.get_block(&block.calculateHash()).unwrap(), Some(block.clone()));
+        assert_eq!(db.get_block_at(3).unwrap(), Some(block.clone()));
+        assert!(db.get_block_at(4).unwrap().is_none());
+
+        db.remove_block_at(3).unwrap();
+        assert!(db.get_block_at(3).unwrap().is_none());
+        assert_eq!(db.get_block(&block.calculateHash()).unwrap(), Some(block));
+    }
 }
\ No newline at end of file
@@ -1,50 +1,52 @@
-use crate::utils::{hashing, encoding};
-use rand::Rng;
-use sha2::{Sha256, Digest};
+use crate::crypto::{scheme_for, SignatureAlgorithm};
 
 #[derive(Debug, Clone)]
 pub struct KeyPair {
+    pub algorithm: SignatureAlgorithm,
     pub private_key: Vec<u8>,
     pub public_key: Vec<u8>,
 }
 
 impl KeyPair {
     pub fn generate() -> Self {
-        let private_key = KeyPair::generate_private_key();
-        let public_key = KeyPair::generate_public_key(&private_key);
+        KeyPair::generate_with(SignatureAlgorithm::Ed25519)
+    }
+
+    pub fn generate_with(algorithm: SignatureAlgorithm) -> Self {
+        let (public_key, private_key) = scheme_for(algorithm).generate_keypair();
         Self {
+            algorithm,
             private_key,
             public_key,
         }
     }
 
-    fn generate_private_key() -> Vec<u8> {
-        let mut rng = rand::thread_rng();
-        let mut private_key = [0u8; 32];
-        rng.fill(&mut private_key);
-        private_key.to_vec()
-    }
-
-    fn generate_public_key(private_key: &[u8]) -> Vec<u8> {
-        let mut hasher = Sha256::new();
-        hasher.update(private_key);
-        hasher.finalize().to_vec()
+    pub fn from_private_key(algorithm: SignatureAlgorithm, private_key: &[u8]) -> Result<Self, String> {
+        let public_key = scheme_for(algorithm).derive_public_key(private_key)?;
+        Ok(Self {
+            algorithm,
+            private_key: private_key.to_vec(),
+            public_key,
+        })
     }
 
     pub fn sign_message(&self, message: &[u8]) -> Vec<u8> {
-        let hash = hashing::sha256(message);
-        hash.iter()
-            .zip(&self.private_key)
-            .map(|(h, k)| h ^ k)
-            .collect()
+        scheme_for(self.algorithm)
+            .sign(&self.private_key, message)
+            .expect("Key pair holds an invalid private key")
     }
 
     pub fn verify_signature(&self, message: &[u8], signature: &[u8]) -> bool {
-        let hash = hashing::sha256(message);
-        signature
-            .iter()
-            .zip(&self.public_key)
-            .all(|(s, p)| *s == (*p ^ hash[0]))
+        KeyPair::verify(self.algorithm, &self.public_key, message, signature)
+    }
+
+    pub fn verify(
+        algorithm: SignatureAlgorithm,
+        public_key: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> bool {
+        scheme_for(algorithm).verify(public_key, message, signature)
     }
 }
 
@@ -55,19 +57,44 @@ mod tests {
     #[test]
     fn test_keypair_generation() {
         let keypair = KeyPair::generate();
+        assert_eq!(keypair.algorithm, SignatureAlgorithm::Ed25519);
         assert_eq!(keypair.private_key.len(), 32);
         assert_eq!(keypair.public_key.len(), 32);
     }
 
+    #[test]
+    fn test_public_key_derived_from_private_key() {
+        for algorithm in [SignatureAlgorithm::Ed25519, SignatureAlgorithm::Dilithium] {
+            let keypair = KeyPair::generate_with(algorithm);
+            let restored = KeyPair::from_private_key(algorithm, &keypair.private_key).unwrap();
+            assert_eq!(restored.public_key, keypair.public_key);
+        }
+        assert!(KeyPair::from_private_key(SignatureAlgorithm::Ed25519, &[0u8; 16]).is_err());
+    }
+
     #[test]
     fn test_sign_and_verify() {
+        for algorithm in [SignatureAlgorithm::Ed25519, SignatureAlgorithm::Dilithium] {
+            let keypair = KeyPair::generate_with(algorithm);
+            let message = b"Test message";
+
+            let signature = keypair.sign_message(message);
+            assert!(keypair.verify_signature(message, &signature));
+
+            let tampered_message = b"Tampered message";
+            assert!(!keypair.verify_signature(tampered_message, &signature));
+        }
+    }
+
+    #[test]
+    fn test_forged_signature_rejected() {
         let keypair = KeyPair::generate();
+        let other = KeyPair::generate();
         let message = b"Test message";
 
-        let signature = keypair.sign_message(message);
-        assert!(keypair.verify_signature(message, &signature));
-
-        let tampered_message = b"Tampered message";
-        assert!(!keypair.verify_signature(tampered_message, &signature));
+        let forged = other.sign_message(message);
+        assert!(!keypair.verify_signature(message, &forged));
+        assert!(!keypair.verify_signature(message, &[0u8; 64]));
+        assert!(!keypair.verify_signature(message, &[]));
     }
-}
\ No newline at end of file
+}
@@ -1,2 +1,6 @@
 pub mod keys;
-pub mod wallet;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod wallet;
+
+pub use keys::KeyPair;
+pub use wallet::Wallet;
\ No newline at end of file
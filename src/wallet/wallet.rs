@@ -1,33 +1,60 @@
+use std::collections::HashMap;
 use crate::wallet::keys::KeyPair;
+use crate::crypto::{scheme_for, SignatureAlgorithm};
 use crate::utils::{hashing, encoding};
-use crate::network::message::{BlockData, TransactionData};
+use crate::blockchain::Block;
+use crate::consensus::DoubleSignEvidence;
+use crate::network::message::{TransactionData, TransactionKind, DEFAULT_CHAIN_ID};
+use crate::ledger::Ledger;
+
+// Fee charged per 1000 bytes of serialized transaction.
+pub const DEFAULT_FEE_RATE: u64 = 1;
 
 pub struct Wallet {
     pub addresses: Vec<String>, 
     pub keypairs: Vec<KeyPair>, 
     pub balance: u64, 
+    pub chain_id: u64,
+    pub nonces: HashMap<String, u64>,
+    pub fee_rate: u64,
+}
+
+impl Default for Wallet {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Wallet {
     pub fn new() -> Self {
-        let keypair = KeyPair::generate();
+        Wallet::from_keypair(KeyPair::generate())
+    }
+
+    pub fn from_keypair(keypair: KeyPair) -> Self {
         let address = Wallet::generate_address(&keypair.public_key);
         Self {
             addresses: vec![address],
             keypairs: vec![keypair],
             balance: 0,
+            chain_id: DEFAULT_CHAIN_ID,
+            nonces: HashMap::new(),
+            fee_rate: DEFAULT_FEE_RATE,
         }
     }
 
     pub fn generate_new_address(&mut self) -> String {
-        let keypair = KeyPair::generate();
+        self.generate_new_address_with(SignatureAlgorithm::Ed25519)
+    }
+
+    pub fn generate_new_address_with(&mut self, algorithm: SignatureAlgorithm) -> String {
+        let keypair = KeyPair::generate_with(algorithm);
         let address = Wallet::generate_address(&keypair.public_key);
         self.addresses.push(address.clone());
         self.keypairs.push(keypair);
         address
     }
 
-    fn generate_address(public_key: &[u8]) -> String {
+    pub fn generate_address(public_key: &[u8]) -> String {
         hashing::sha256(public_key)
     }
 
@@ -35,17 +62,53 @@ impl Wallet {
         self.balance
     }
 
-    pub fn sync_balance(&mut self, blockchain: &[BlockData]) {
+    pub fn next_nonce(&self, address: &str) -> u64 {
+        self.nonces.get(address).copied().unwrap_or(0)
+    }
+
+    pub fn sync_from_ledger(&mut self, ledger: &Ledger) {
+        self.balance = self
+            .addresses
+            .iter()
+            .map(|address| ledger.balance(address))
+            .sum();
+        for address in &self.addresses {
+            let confirmed = ledger.nonce(address);
+            let next = self.nonces.entry(address.clone()).or_insert(confirmed);
+            *next = (*next).max(confirmed);
+        }
+    }
+
+    // Counts a produced block's earnings in full; shares paid to the
+    // producer's delegators, or to ours, only show up in the ledger.
+    pub fn sync_balance(&mut self, blockchain: &[Block]) {
         let mut new_balance = 0;
 
         for block in blockchain {
+            if self.addresses.iter().any(|address| address == block.producer()) {
+                new_balance += block.blockHeader.reward;
+            }
             for tx in &block.transactions {
-                if self.addresses.contains(&tx.receiver) {
+                let credited = match tx.kind {
+                    TransactionKind::Transfer => self.addresses.contains(&tx.receiver),
+                    TransactionKind::Withdraw => self.addresses.contains(&tx.sender),
+                    TransactionKind::Bond
+                    | TransactionKind::Unbond
+                    | TransactionKind::Evidence
+                    | TransactionKind::Delegate
+                    | TransactionKind::Undelegate
+                    | TransactionKind::Commission => false,
+                };
+                if credited {
                     new_balance += tx.amount;
                 }
 
                 if self.addresses.contains(&tx.sender) {
-                    new_balance = new_balance.saturating_sub(tx.amount);
+                    new_balance = new_balance.saturating_sub(tx.balanceCost().unwrap_or(u64::MAX));
+                }
+
+                if self.addresses.iter().any(|address| address == block.producer()) {
+                    new_balance += tx.fee;
                 }
             }
         }
@@ -53,53 +116,148 @@ impl Wallet {
         self.balance = new_balance;
     }
 
+    pub fn estimate_fee(&self, recipient: &str, amount: u64) -> Result<u64, String> {
+        let tx = self.build_transaction(TransactionKind::Transfer, recipient, amount, 0)?;
+        self.fee_for(&tx)
+    }
+
+    fn fee_for(&self, tx: &TransactionData) -> Result<u64, String> {
+        let size = encoding::serialize(tx)?.len() as u64;
+        Ok((size * self.fee_rate).div_ceil(1000))
+    }
+
     pub fn send_payment(
         &mut self,
         recipient: &str,
         amount: u64,
     ) -> Result<TransactionData, String> {
-        if amount > self.balance {
+        let fee = self.estimate_fee(recipient, amount)?;
+        self.send_payment_with_fee(recipient, amount, fee)
+    }
+
+    pub fn send_payment_with_fee(
+        &mut self,
+        recipient: &str,
+        amount: u64,
+        fee: u64,
+    ) -> Result<TransactionData, String> {
+        let tx = self.build_transaction(TransactionKind::Transfer, recipient, amount, fee)?;
+        self.sign_transaction(tx)
+    }
+
+    // Bonds, unbonds or withdraws `amount` of the first address's own stake,
+    // or with Commission sets its commission to `amount` percent.
+    pub fn send_staking(&mut self, kind: TransactionKind, amount: u64) -> Result<TransactionData, String> {
+        if !matches!(
+            kind,
+            TransactionKind::Bond | TransactionKind::Unbond | TransactionKind::Withdraw | TransactionKind::Commission
+        ) {
+            return Err("Not a staking transaction".to_string());
+        }
+        let own = self
+            .addresses
+            .first()
+            .cloned()
+            .ok_or_else(|| "No address available".to_string())?;
+        let fee = self.estimate_fee(&own, amount)?;
+        let tx = self.build_transaction(kind, &own, amount, fee)?;
+        self.sign_transaction(tx)
+    }
+
+    // Delegates to, undelegates from or withdraws undelegated stake from
+    // `validator`.
+    pub fn send_delegation(
+        &mut self,
+        kind: TransactionKind,
+        validator: &str,
+        amount: u64,
+    ) -> Result<TransactionData, String> {
+        if !matches!(
+            kind,
+            TransactionKind::Delegate | TransactionKind::Undelegate | TransactionKind::Withdraw
+        ) {
+            return Err("Not a delegation transaction".to_string());
+        }
+        let fee = self.estimate_fee(validator, amount)?;
+        let tx = self.build_transaction(kind, validator, amount, fee)?;
+        self.sign_transaction(tx)
+    }
+
+    // Reports a validator for signing two blocks in one slot.
+    pub fn send_evidence(&mut self, evidence: DoubleSignEvidence) -> Result<TransactionData, String> {
+        let mut tx = self.build_transaction(TransactionKind::Evidence, evidence.offender(), 0, 0)?;
+        tx.evidence = Some(Box::new(evidence));
+        tx.fee = self.fee_for(&tx)?;
+        self.sign_transaction(tx)
+    }
+
+    fn sign_transaction(&mut self, mut tx: TransactionData) -> Result<TransactionData, String> {
+        let sender_index = 0;
+        let total = tx.balanceCost().ok_or_else(|| "Amount overflow".to_string())?;
+        if total > self.balance {
             return Err("Insufficient funds".to_string());
         }
 
+        tx.signature = self.keypairs[sender_index].sign_message(Wallet::signing_payload(&tx).as_bytes());
+
+        self.balance -= total;
+        self.nonces.insert(tx.sender.clone(), tx.nonce + 1);
+        Ok(tx)
+    }
+
+    // Unsigned transaction from the first address, with a placeholder
+    // signature of the right size so it can be measured.
+    fn build_transaction(
+        &self,
+        kind: TransactionKind,
+        recipient: &str,
+        amount: u64,
+        fee: u64,
+    ) -> Result<TransactionData, String> {
         let sender_index = 0;
         let sender_address = self
             .addresses
             .get(sender_index)
             .ok_or_else(|| "No address available".to_string())?;
-        self.balance -= amount;
+        let keypair = &self.keypairs[sender_index];
 
-        let transaction_data = format!("{}:{}:{}", sender_address, recipient, amount);
-        let signature = {
-            let private_key = &self.keypairs[sender_index].private_key;
-            let hash = hashing::sha256(transaction_data.as_bytes());
-            encoding::serialize(&hash.iter().zip(private_key).map(|(h, k)| h ^ k).collect::<Vec<u8>>())
-                .expect("Failed to serialize signature")
-        };
-
-        let tx = TransactionData {
+        Ok(TransactionData {
+            kind,
             sender: sender_address.clone(),
             receiver: recipient.to_string(),
             amount,
-            signature,
-        };
-
-        Ok(tx)
+            fee,
+            nonce: self.next_nonce(sender_address),
+            chainId: self.chain_id,
+            algorithm: keypair.algorithm,
+            publicKey: keypair.public_key.clone(),
+            signature: vec![0; scheme_for(keypair.algorithm).signature_len()],
+            evidence: None,
+        })
     }
 
     pub fn verify_transaction(transaction: &TransactionData, public_key: &[u8]) -> bool {
-        let transaction_data = format!("{}:{}:{}", transaction.sender, transaction.receiver, transaction.amount);
-
-        let hash = hashing::sha256(transaction_data.as_bytes());
+        let transaction_data = Wallet::signing_payload(transaction);
+        KeyPair::verify(transaction.algorithm, public_key, transaction_data.as_bytes(), &transaction.signature)
+    }
 
-        if let Ok(signature) = encoding::deserialize::<Vec<u8>>(&transaction.signature) {
-            signature
-                .iter()
-                .zip(public_key)
-                .all(|(s, p)| *s == (*p ^ hash[0]))
-        } else {
-            false
+    fn signing_payload(transaction: &TransactionData) -> String {
+        let mut payload = format!(
+            "{}:{}:{}:{}:{}:{}:{}:{}",
+            transaction.chainId,
+            transaction.algorithm.name(),
+            transaction.kind.name(),
+            transaction.sender,
+            transaction.receiver,
+            transaction.amount,
+            transaction.fee,
+            transaction.nonce
+        );
+        if let Some(evidence) = &transaction.evidence {
+            payload.push(':');
+            payload.push_str(&evidence.id());
         }
+        payload
     }
 }
 
@@ -125,42 +283,59 @@ mod tests {
         let recipient = wallet.addresses[0].clone();
 
         let blockchain = vec![
-            BlockData {
-                index: 0,
-                previousHash: String::new(),
-                timestamp: 0,
-                merkleRoot: String::new(),
-                nonce: 0,
-                transactions: vec![
+            Block::new(
+                0,
+                String::new(),
+                String::new(),
+                vec![
                     TransactionData {
+                        kind: TransactionKind::Transfer,
                         sender: "sender_1".to_string(),
                         receiver: recipient.clone(),
                         amount: 50,
+                        fee: 0,
+                        nonce: 0,
+                        chainId: DEFAULT_CHAIN_ID,
+                        algorithm: SignatureAlgorithm::Ed25519,
+                        publicKey: Vec::new(),
                         signature: Vec::new(),
+                        evidence: None,
                     },
                 ],
-            },
-            BlockData {
-                index: 1,
-                previousHash: String::new(),
-                timestamp: 0,
-                merkleRoot: String::new(),
-                nonce: 0,
-                transactions: vec![
+            ),
+            Block::new(
+                1,
+                String::new(),
+                String::new(),
+                vec![
                     TransactionData {
+                        kind: TransactionKind::Transfer,
                         sender: "sender_2".to_string(),
                         receiver: recipient.clone(),
                         amount: 75,
+                        fee: 0,
+                        nonce: 0,
+                        chainId: DEFAULT_CHAIN_ID,
+                        algorithm: SignatureAlgorithm::Ed25519,
+                        publicKey: Vec::new(),
                         signature: Vec::new(),
+                        evidence: None,
                     },
                     TransactionData {
+                        kind: TransactionKind::Transfer,
                         sender: recipient.clone(),
                         receiver: "other_address".to_string(),
                         amount: 30,
+                        fee: 0,
+                        nonce: 0,
+                        chainId: DEFAULT_CHAIN_ID,
+                        algorithm: SignatureAlgorithm::Ed25519,
+                        publicKey: Vec::new(),
                         signature: Vec::new(),
+                        evidence: None,
                     },
                 ],
-            },
+            ),
         ];
 
         wallet.sync_balance(&blockchain);
@@ -168,6 +343,20 @@ mod tests {
         assert_eq!(wallet.get_balance(), 95);
     }
 
+    #[test]
+    fn test_wallet_sync_from_ledger() {
+        let mut wallet = Wallet::new();
+        let second = wallet.generate_new_address();
+        let ledger = Ledger::from_genesis(&[
+            (wallet.addresses[0].clone(), 100),
+            (second, 25),
+            ("other_address".to_string(), 500),
+        ]);
+
+        wallet.sync_from_ledger(&ledger);
+        assert_eq!(wallet.get_balance(), 125);
+    }
+
     #[test]
     fn test_transaction_signature() {
         let mut wallet = Wallet::new();
@@ -178,5 +367,130 @@ mod tests {
 
         let public_key = &wallet.keypairs[0].public_key;
         assert!(Wallet::verify_transaction(&tx, public_key));
+
+        let other = Wallet::new();
+        assert!(!Wallet::verify_transaction(&tx, &other.keypairs[0].public_key));
+
+        let mut tampered = tx.clone();
+        tampered.amount = 90;
+        assert!(!Wallet::verify_transaction(&tampered, public_key));
+
+        let mut replayed = tx.clone();
+        replayed.nonce += 1;
+        assert!(!Wallet::verify_transaction(&replayed, public_key));
+
+        let mut other_chain = tx.clone();
+        other_chain.chainId += 1;
+        assert!(!Wallet::verify_transaction(&other_chain, public_key));
+
+        // The algorithm tag is signed, so a relayer can't swap it.
+        let mut relabeled = tx.clone();
+        relabeled.algorithm = SignatureAlgorithm::Dilithium;
+        assert_ne!(Wallet::signing_payload(&relabeled), Wallet::signing_payload(&tx));
+        assert!(!Wallet::verify_transaction(&relabeled, public_key));
+    }
+
+    #[test]
+    fn test_wallet_tracks_nonces() {
+        let mut wallet = Wallet::new();
+        let address = wallet.addresses[0].clone();
+        wallet.balance = 100;
+
+        assert_eq!(wallet.send_payment("recipient_address", 10).unwrap().nonce, 0);
+        assert_eq!(wallet.send_payment("recipient_address", 10).unwrap().nonce, 1);
+        assert_eq!(wallet.next_nonce(&address), 2);
+
+        let mut ledger = Ledger::from_genesis(&[(address.clone(), 100)]);
+        wallet.sync_from_ledger(&ledger);
+        assert_eq!(wallet.next_nonce(&address), 2);
+
+        let mut restored = Wallet::new();
+        restored.addresses = wallet.addresses.clone();
+        restored.keypairs = wallet.keypairs.clone();
+        restored.sync_from_ledger(&ledger);
+        let tx = restored.send_payment("recipient_address", 10).unwrap();
+        assert_eq!(tx.nonce, 0);
+
+        ledger.apply_transaction(&tx).unwrap();
+        restored.nonces.clear();
+        restored.sync_from_ledger(&ledger);
+        assert_eq!(restored.next_nonce(&address), 1);
+    }
+
+    #[test]
+    fn test_post_quantum_transaction_signature() {
+        let mut wallet = Wallet::new();
+        wallet.addresses.clear();
+        wallet.keypairs.clear();
+        wallet.generate_new_address_with(SignatureAlgorithm::Dilithium);
+        wallet.balance = 100;
+
+        let tx = wallet.send_payment("recipient_address", 10).unwrap();
+        assert_eq!(tx.algorithm, SignatureAlgorithm::Dilithium);
+
+        let public_key = &wallet.keypairs[0].public_key;
+        assert!(Wallet::verify_transaction(&tx, public_key));
+
+        let mut mislabeled = tx;
+        mislabeled.algorithm = SignatureAlgorithm::Ed25519;
+        assert!(!Wallet::verify_transaction(&mislabeled, public_key));
+    }
+
+    #[test]
+    fn test_fee_estimate_and_explicit_fee() {
+        let mut wallet = Wallet::new();
+        wallet.balance = 100;
+
+        let fee = wallet.estimate_fee("recipient_address", 10).unwrap();
+        assert!(fee > 0);
+        let tx = wallet.send_payment("recipient_address", 10).unwrap();
+        assert_eq!(tx.fee, fee);
+        assert_eq!(wallet.get_balance(), 90 - fee);
+
+        let mut post_quantum = Wallet::new();
+        post_quantum.keypairs[0] = KeyPair::generate_with(SignatureAlgorithm::Dilithium);
+        assert!(post_quantum.estimate_fee("recipient_address", 10).unwrap() > fee);
+
+        wallet.fee_rate = 10 * DEFAULT_FEE_RATE;
+        assert!(wallet.estimate_fee("recipient_address", 10).unwrap() > fee);
+
+        let tx = wallet.send_payment_with_fee("recipient_address", 10, 7).unwrap();
+        assert_eq!(tx.fee, 7);
+        assert!(Wallet::verify_transaction(&tx, &wallet.keypairs[0].public_key));
+
+        let mut tampered = tx;
+        tampered.fee = 0;
+        assert!(!Wallet::verify_transaction(&tampered, &wallet.keypairs[0].public_key));
+
+        let remaining = wallet.get_balance();
+        assert!(wallet.send_payment_with_fee("recipient_address", remaining, 1).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_staking_transactions() {
+        let mut wallet = Wallet::new();
+        wallet.balance = 100;
+        let own = wallet.addresses[0].clone();
+
+        let bond = wallet.send_staking(TransactionKind::Bond, 40).unwrap();
+        assert_eq!((bond.kind, &bond.receiver, bond.amount), (TransactionKind::Bond, &own, 40));
+        assert!(Wallet::verify_transaction(&bond, &wallet.keypairs[0].public_key));
+        assert_eq!(wallet.get_balance(), 60 - bond.fee);
+
+        let mut relabeled = bond.clone();
+        relabeled.kind = TransactionKind::Withdraw;
+        assert!(!Wallet::verify_transaction(&relabeled, &wallet.keypairs[0].public_key));
+
+        let unbond = wallet.send_staking(TransactionKind::Unbond, 500).unwrap();
+        assert_eq!(wallet.get_balance(), 60 - bond.fee - unbond.fee);
+        assert!(wallet.send_staking(TransactionKind::Transfer, 1).is_err());
+
+        let withdraw = wallet.send_staking(TransactionKind::Withdraw, 40).unwrap();
+        let mut faucet = Wallet::new();
+        faucet.balance = 100;
+        let funding = faucet.send_payment(&own, 90).unwrap();
+        let chain = vec![Block::new(0, String::new(), "producer".to_string(), vec![funding, bond.clone(), withdraw.clone()])];
+        wallet.sync_balance(&chain);
+        assert_eq!(wallet.get_balance(), 90 - bond.fee - withdraw.fee);
+    }
+}